@@ -0,0 +1,636 @@
+use crate::target::Target;
+
+use super::ir::{Arithmetic, Condition, LabelId, LabelKind, Operation, Program, Value, VariableId};
+
+/// amount of variables the compiler keeps for itself to run the bitwise operations.
+/// they are shared by every bitwise operation in the program
+pub const SCRATCH_SIZE: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitwiseOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+    ShiftLeft,
+    ShiftRight,
+    Modulo,
+}
+
+/// the program the code goes into, the target it is for and the AST node it comes from
+struct Code<'a> {
+    program: &'a mut Program,
+    target: &'a Target,
+    source: usize,
+}
+
+/// lowers `c = a <operation> b`, b is not used by NOT.
+/// the operations work on the bits of the words and none of the arithmetic they turn into
+/// goes past the words of the target, so they give the same result with every overflow.
+/// AND, OR, XOR, SHL and SHR need words that can hold 2
+#[allow(clippy::too_many_arguments)]
+pub fn lower_bitwise(
+    program: &mut Program,
+    target: &Target,
+    source: usize,
+    operation: BitwiseOperation,
+    a: VariableId,
+    b: Option<VariableId>,
+    c: VariableId,
+    scratch: &[VariableId],
+) {
+    let mut code = Code {
+        program,
+        target,
+        source,
+    };
+    match operation {
+        BitwiseOperation::Not => {
+            // every bit set minus a never goes past the word
+            code.arithmetic(
+                Arithmetic::Sub,
+                Value::Immediate(target.mask()),
+                Value::Variable(a),
+                c,
+            );
+        }
+        BitwiseOperation::Modulo => {
+            let b = b.expect("MOD needs 2 variables");
+            code.modulo(a, b, c, scratch[0]);
+        }
+        BitwiseOperation::ShiftLeft | BitwiseOperation::ShiftRight => {
+            let b = b.expect("SHL and SHR need 2 variables");
+            let left = operation == BitwiseOperation::ShiftLeft;
+            code.shift(left, a, b, c, scratch);
+        }
+        BitwiseOperation::And | BitwiseOperation::Or | BitwiseOperation::Xor => {
+            let b = b.expect("AND, OR and XOR need 2 variables");
+            code.bit_by_bit(operation, a, b, c, scratch);
+        }
+    }
+}
+
+/// if the operations can be lowered for the target
+pub fn fits_bitwise(target: &Target) -> bool {
+    target.max() >= 2
+}
+
+impl Code<'_> {
+    /// the word with only the highest bit set, the smallest number on signed targets
+    fn top(&self) -> u64 {
+        1 << (self.target.word_size - 1)
+    }
+
+    /// a - a / b * b, the division takes the sign of a like DIV does
+    fn modulo(&mut self, a: VariableId, b: VariableId, c: VariableId, quotient: VariableId) {
+        let done = self.target.signed.then(|| {
+            // the smallest number divided by -1 does not fit, every number divided by -1 leaves 0
+            let (divide, done) = (self.label(), self.label());
+            self.branch(
+                Condition::NotEqual,
+                Value::Variable(b),
+                Value::Immediate(self.target.mask()),
+                divide,
+            );
+            self.set(c, 0);
+            self.jump(done);
+            self.program.place_label(divide);
+            done
+        });
+        self.arithmetic(
+            Arithmetic::Div,
+            Value::Variable(a),
+            Value::Variable(b),
+            quotient,
+        );
+        self.arithmetic(
+            Arithmetic::Mul,
+            Value::Variable(quotient),
+            Value::Variable(b),
+            quotient,
+        );
+        self.arithmetic(
+            Arithmetic::Sub,
+            Value::Variable(a),
+            Value::Variable(quotient),
+            c,
+        );
+        if let Some(done) = done {
+            self.program.place_label(done);
+        }
+    }
+
+    /// shifts a by one bit b times, counts that are negative or the word size or more leave nothing
+    fn shift(
+        &mut self,
+        left: bool,
+        a: VariableId,
+        b: VariableId,
+        c: VariableId,
+        scratch: &[VariableId],
+    ) {
+        let (value, count, bit) = (scratch[0], scratch[1], scratch[2]);
+        let (loop_start, too_far, done) = (self.label(), self.label(), self.label());
+        self.copy(a, value);
+        self.copy(b, count);
+        if self.target.signed {
+            self.branch(
+                Condition::HigherThan,
+                Value::Immediate(0),
+                Value::Variable(count),
+                too_far,
+            );
+        }
+        self.branch(
+            Condition::HigherThan,
+            Value::Variable(count),
+            Value::Immediate(self.target.word_size as u64 - 1),
+            too_far,
+        );
+        self.program.place_label(loop_start);
+        self.branch(
+            Condition::Equal,
+            Value::Variable(count),
+            Value::Immediate(0),
+            done,
+        );
+        if left {
+            self.shift_left_once(value, bit);
+        } else {
+            self.shift_right_once(value, bit);
+        }
+        self.arithmetic(
+            Arithmetic::Sub,
+            Value::Variable(count),
+            Value::Immediate(1),
+            count,
+        );
+        self.jump(loop_start);
+        self.program.place_label(too_far);
+        self.set(value, 0);
+        self.program.place_label(done);
+        self.copy(value, c);
+    }
+
+    /// drops the highest bit before doubling, when the next bit becomes the highest bit it is
+    /// added back as the top word after doubling the rest
+    fn shift_left_once(&mut self, value: VariableId, bit: VariableId) {
+        let half_top = self.top() / 2;
+        let (low, shifted) = (self.label(), self.label());
+        self.split_top_bit(value, bit);
+        self.branch(
+            Condition::HigherThan,
+            Value::Immediate(half_top),
+            Value::Variable(value),
+            low,
+        );
+        self.arithmetic(
+            Arithmetic::Sub,
+            Value::Variable(value),
+            Value::Immediate(half_top),
+            value,
+        );
+        self.arithmetic(
+            Arithmetic::Mul,
+            Value::Variable(value),
+            Value::Immediate(2),
+            value,
+        );
+        self.arithmetic(
+            Arithmetic::Add,
+            Value::Variable(value),
+            Value::Immediate(self.top()),
+            value,
+        );
+        self.jump(shifted);
+        self.program.place_label(low);
+        self.arithmetic(
+            Arithmetic::Mul,
+            Value::Variable(value),
+            Value::Immediate(2),
+            value,
+        );
+        self.program.place_label(shifted);
+    }
+
+    /// halves the word without its highest bit, so signed words shift in a 0 too
+    fn shift_right_once(&mut self, value: VariableId, bit: VariableId) {
+        let shifted = self.label();
+        self.split_top_bit(value, bit);
+        self.arithmetic(
+            Arithmetic::Div,
+            Value::Variable(value),
+            Value::Immediate(2),
+            value,
+        );
+        self.branch(
+            Condition::Equal,
+            Value::Variable(bit),
+            Value::Immediate(0),
+            shifted,
+        );
+        self.arithmetic(
+            Arithmetic::Add,
+            Value::Variable(value),
+            Value::Immediate(self.top() / 2),
+            value,
+        );
+        self.program.place_label(shifted);
+    }
+
+    /// moves the highest bit of value into bit, value is left as a positive number
+    fn split_top_bit(&mut self, value: VariableId, bit: VariableId) {
+        let clear = self.label();
+        self.set(bit, 0);
+        let (a, b) = match self.target.signed {
+            // the highest bit is clear for positive numbers
+            true => (Value::Variable(value), Value::Immediate(self.target.mask())),
+            false => (Value::Immediate(self.top()), Value::Variable(value)),
+        };
+        self.branch(Condition::HigherThan, a, b, clear);
+        self.set(bit, 1);
+        self.arithmetic(
+            Arithmetic::Sub,
+            Value::Variable(value),
+            Value::Immediate(self.top()),
+            value,
+        );
+        self.program.place_label(clear);
+    }
+
+    /// combines the highest bits first and then takes the lowest bit of a and b until both are 0
+    /// and adds the combined bit to the result
+    fn bit_by_bit(
+        &mut self,
+        operation: BitwiseOperation,
+        a: VariableId,
+        b: VariableId,
+        c: VariableId,
+        scratch: &[VariableId],
+    ) {
+        let [left, right, result, bit_value, half, temp, left_bit, right_bit, combined] = *scratch
+        else {
+            panic!("bitwise operations need {} scratch variables", SCRATCH_SIZE)
+        };
+        let (loop_start, more, done) = (self.label(), self.label(), self.label());
+        self.copy(a, left);
+        self.copy(b, right);
+        self.split_top_bit(left, left_bit);
+        self.split_top_bit(right, right_bit);
+        self.combine(operation, left_bit, right_bit, combined, temp);
+        self.arithmetic(
+            Arithmetic::Mul,
+            Value::Variable(combined),
+            Value::Immediate(self.top()),
+            result,
+        );
+        self.set(bit_value, 1);
+        self.program.place_label(loop_start);
+        for (value, bit) in [(left, left_bit), (right, right_bit)] {
+            // bit = value - value / 2 * 2
+            self.arithmetic(
+                Arithmetic::Div,
+                Value::Variable(value),
+                Value::Immediate(2),
+                half,
+            );
+            self.arithmetic(
+                Arithmetic::Mul,
+                Value::Variable(half),
+                Value::Immediate(2),
+                temp,
+            );
+            self.arithmetic(
+                Arithmetic::Sub,
+                Value::Variable(value),
+                Value::Variable(temp),
+                bit,
+            );
+            self.copy(half, value);
+        }
+        self.combine(operation, left_bit, right_bit, combined, temp);
+        self.arithmetic(
+            Arithmetic::Mul,
+            Value::Variable(combined),
+            Value::Variable(bit_value),
+            combined,
+        );
+        self.arithmetic(
+            Arithmetic::Add,
+            Value::Variable(result),
+            Value::Variable(combined),
+            result,
+        );
+        // the bit value only doubles while there are bits left, so it stays below the top bit
+        self.branch(
+            Condition::NotEqual,
+            Value::Variable(left),
+            Value::Immediate(0),
+            more,
+        );
+        self.branch(
+            Condition::Equal,
+            Value::Variable(right),
+            Value::Immediate(0),
+            done,
+        );
+        self.program.place_label(more);
+        self.arithmetic(
+            Arithmetic::Mul,
+            Value::Variable(bit_value),
+            Value::Immediate(2),
+            bit_value,
+        );
+        self.jump(loop_start);
+        self.program.place_label(done);
+        self.copy(result, c);
+    }
+
+    /// combined = left_bit <operation> right_bit for bits that are 0 or 1
+    fn combine(
+        &mut self,
+        operation: BitwiseOperation,
+        left_bit: VariableId,
+        right_bit: VariableId,
+        combined: VariableId,
+        temp: VariableId,
+    ) {
+        // both bits multiplied is AND
+        self.arithmetic(
+            Arithmetic::Mul,
+            Value::Variable(left_bit),
+            Value::Variable(right_bit),
+            combined,
+        );
+        if operation == BitwiseOperation::Xor {
+            // XOR is the sum minus AND twice
+            self.arithmetic(
+                Arithmetic::Mul,
+                Value::Variable(combined),
+                Value::Immediate(2),
+                combined,
+            );
+        }
+        if operation != BitwiseOperation::And {
+            // OR is the sum minus AND
+            self.arithmetic(
+                Arithmetic::Add,
+                Value::Variable(left_bit),
+                Value::Variable(right_bit),
+                temp,
+            );
+            self.arithmetic(
+                Arithmetic::Sub,
+                Value::Variable(temp),
+                Value::Variable(combined),
+                combined,
+            );
+        }
+    }
+
+    fn label(&mut self) -> LabelId {
+        self.program.new_label(LabelKind::Internal)
+    }
+
+    /// out = x <arithmetic> y
+    fn arithmetic(&mut self, arithmetic: Arithmetic, x: Value, y: Value, out: VariableId) {
+        self.program.push(
+            Operation::Arithmetic {
+                arithmetic,
+                a: x,
+                b: y,
+                destination: out,
+            },
+            self.source,
+        );
+    }
+
+    fn set(&mut self, variable: VariableId, value: u64) {
+        self.arithmetic(
+            Arithmetic::Add,
+            Value::Immediate(value),
+            Value::Immediate(0),
+            variable,
+        );
+    }
+
+    fn copy(&mut self, from: VariableId, to: VariableId) {
+        self.program.push(Operation::Copy { from, to }, self.source);
+    }
+
+    fn jump(&mut self, target: LabelId) {
+        self.program.push(Operation::Jump { target }, self.source);
+    }
+
+    fn branch(&mut self, condition: Condition, a: Value, b: Value, target: LabelId) {
+        self.program.push(
+            Operation::Branch {
+                condition,
+                a,
+                b,
+                target,
+            },
+            self.source,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        code_gen::{generate_code, generate_code_with_debug_info, CompileOptions},
+        emulator::Machine,
+        parsing::parse::{parse_string, parse_string_for_target},
+        target::Overflow,
+    };
+
+    const STEP_LIMIT: u64 = 100_000;
+
+    /// the word the operation gives, counts that do not fit in the word leave nothing
+    fn reference(target: &Target, operation: BitwiseOperation, a: u64, b: u64) -> u64 {
+        let shifts_out = b >= target.word_size as u64;
+        let word = match operation {
+            BitwiseOperation::And => a & b,
+            BitwiseOperation::Or => a | b,
+            BitwiseOperation::Xor => a ^ b,
+            BitwiseOperation::Not => !a,
+            BitwiseOperation::ShiftLeft | BitwiseOperation::ShiftRight if shifts_out => 0,
+            BitwiseOperation::ShiftLeft => a << b,
+            BitwiseOperation::ShiftRight => a >> b,
+            BitwiseOperation::Modulo => target.word(target.value(a) % target.value(b)),
+        };
+        word & target.mask()
+    }
+
+    fn target(word_size: u32, signed: bool, overflow: Overflow) -> Target {
+        Target {
+            word_size,
+            signed,
+            overflow,
+            ..Target::default()
+        }
+    }
+
+    /// the default target and targets where going past a word does not wrap
+    fn targets() -> Vec<Target> {
+        vec![
+            Target::default(),
+            target(16, false, Overflow::Trap),
+            target(16, false, Overflow::Saturate),
+            target(16, true, Overflow::Trap),
+            target(16, true, Overflow::Saturate),
+        ]
+    }
+
+    fn run(program: String) -> u64 {
+        let code = generate_code(parse_string(program)).unwrap();
+        Machine::new(&code).run(STEP_LIMIT).unwrap()
+    }
+
+    fn run_for_target(program: String, target: &Target) -> u64 {
+        let (parsed, ast_lines) = parse_string_for_target(program, target).unwrap();
+        let options = CompileOptions {
+            target: target.clone(),
+            ..CompileOptions::default()
+        };
+        let (code, _) = generate_code_with_debug_info(parsed, &ast_lines, &options).unwrap();
+        let mut machine = Machine::new(&code);
+        machine.target = target.clone();
+        machine.run(STEP_LIMIT).unwrap()
+    }
+
+    fn run_operation(target: &Target, operation: BitwiseOperation, a: u64, b: u64) -> u64 {
+        let instruction = match operation {
+            BitwiseOperation::And => "AND ?a ?b ?c",
+            BitwiseOperation::Or => "OR ?a ?b ?c",
+            BitwiseOperation::Xor => "XOR ?a ?b ?c",
+            BitwiseOperation::Not => "NOT ?a ?c",
+            BitwiseOperation::ShiftLeft => "SHL ?a ?b ?c",
+            BitwiseOperation::ShiftRight => "SHR ?a ?b ?c",
+            BitwiseOperation::Modulo => "MOD ?a ?b ?c",
+        };
+        run_for_target(
+            format!(
+                "DEF ?a {}\nDEF ?b {}\nDEF ?c 0\n{}\nEXIT ?c\n",
+                target.value(a),
+                target.value(b),
+                instruction
+            ),
+            target,
+        )
+    }
+
+    /// words with every bit pattern that is hard to get right
+    fn interesting_values(target: &Target) -> Vec<u64> {
+        let top = 1 << (target.word_size - 1);
+        let mut values: Vec<u64> = (0..=16).collect();
+        values.extend([
+            63,
+            64,
+            65,
+            255,
+            256,
+            40000,
+            0x5555_5555_5555_5555,
+            0xAAAA_AAAA_AAAA_AAAA,
+            top - 1,
+            top,
+            top + 1,
+            u64::MAX - 1,
+            u64::MAX,
+        ]);
+        let mut values: Vec<u64> = values.iter().map(|value| value & target.mask()).collect();
+        values.sort();
+        values.dedup();
+        values
+    }
+
+    fn check_against_reference(operation: BitwiseOperation) {
+        for target in targets() {
+            for a in interesting_values(&target) {
+                for b in interesting_values(&target) {
+                    if operation == BitwiseOperation::Modulo && b == 0 {
+                        continue;
+                    }
+                    assert_eq!(
+                        run_operation(&target, operation, a, b),
+                        reference(&target, operation, a, b),
+                        "{:?} {} {} on {:?}",
+                        operation,
+                        a,
+                        b,
+                        target
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn and_matches_reference() {
+        check_against_reference(BitwiseOperation::And);
+    }
+
+    #[test]
+    fn or_matches_reference() {
+        check_against_reference(BitwiseOperation::Or);
+    }
+
+    #[test]
+    fn xor_matches_reference() {
+        check_against_reference(BitwiseOperation::Xor);
+    }
+
+    #[test]
+    fn not_matches_reference() {
+        check_against_reference(BitwiseOperation::Not);
+    }
+
+    #[test]
+    fn shift_left_matches_reference() {
+        check_against_reference(BitwiseOperation::ShiftLeft);
+    }
+
+    #[test]
+    fn shift_right_matches_reference() {
+        check_against_reference(BitwiseOperation::ShiftRight);
+    }
+
+    #[test]
+    fn modulo_matches_reference() {
+        check_against_reference(BitwiseOperation::Modulo);
+    }
+
+    #[test]
+    fn output_can_be_an_input() {
+        assert_eq!(
+            run("DEF ?a 13\nDEF ?b 6\nXOR ?a ?b ?a\nMOD ?a ?b ?b\nEXIT ?b\n".to_owned()),
+            (13 ^ 6) % 6
+        );
+    }
+
+    #[test]
+    fn labels_after_bitwise_operations_are_correct() {
+        assert_eq!(
+            run(
+                "DEF ?a 6\nDEF ?b 3\nJMP :end\nAND ?a ?b ?a\nSHL ?a ?b ?a\n:end\nEXIT ?a\n"
+                    .to_owned()
+            ),
+            6
+        );
+    }
+
+    #[test]
+    fn words_that_can_not_hold_2_are_an_error() {
+        let target = target(2, true, Overflow::Wrap);
+        let (parsed, ast_lines) =
+            parse_string_for_target("DEF ?a 1\nAND ?a ?a ?a\nEXIT ?a\n".to_owned(), &target)
+                .unwrap();
+        let options = CompileOptions {
+            target,
+            ..CompileOptions::default()
+        };
+        assert!(generate_code_with_debug_info(parsed, &ast_lines, &options).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use super::ir::{LabelId, Operation, Program};
+
+/// which blocks of a program can run after which, by block index
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ControlFlowGraph {
+    pub successors: Vec<Vec<usize>>,
+    pub predecessors: Vec<Vec<usize>>,
+    /// blocks that can run first, the start of the program and the labels other objects can jump to
+    pub entries: Vec<usize>,
+}
+
+impl ControlFlowGraph {
+    pub fn new(program: &Program) -> ControlFlowGraph {
+        let blocks = block_of_labels(program);
+        let mut successors: Vec<Vec<usize>> = vec![vec![]; program.blocks.len()];
+        for (index, block) in program.blocks.iter().enumerate() {
+            if block.falls_through() && index + 1 < program.blocks.len() {
+                successors[index].push(index + 1);
+            }
+            // jumps to labels of other objects leave the program
+            let target = match block.instructions.last().map(|last| &last.operation) {
+                Some(Operation::Branch { target, .. } | Operation::Jump { target }) => {
+                    blocks.get(target)
+                }
+                _ => None,
+            };
+            if let Some(target) = target {
+                if !successors[index].contains(target) {
+                    successors[index].push(*target);
+                }
+            }
+        }
+        let mut predecessors: Vec<Vec<usize>> = vec![vec![]; program.blocks.len()];
+        for (index, block_successors) in successors.iter().enumerate() {
+            for successor in block_successors {
+                predecessors[*successor].push(index);
+            }
+        }
+        let mut entries = vec![];
+        if !program.blocks.is_empty() {
+            entries.push(0);
+        }
+        for name in &program.exports {
+            if let Some(block) = program
+                .named_label(name)
+                .and_then(|label| blocks.get(&label))
+            {
+                if !entries.contains(block) {
+                    entries.push(*block);
+                }
+            }
+        }
+        ControlFlowGraph {
+            successors,
+            predecessors,
+            entries,
+        }
+    }
+
+    /// if every block can be reached from an entry
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.successors.len()];
+        let mut todo = self.entries.clone();
+        while let Some(block) = todo.pop() {
+            if reachable[block] {
+                continue;
+            }
+            reachable[block] = true;
+            todo.extend(&self.successors[block]);
+        }
+        reachable
+    }
+}
+
+/// the index of the block every placed label points at
+pub fn block_of_labels(program: &Program) -> HashMap<LabelId, usize> {
+    program
+        .blocks
+        .iter()
+        .enumerate()
+        .flat_map(|(index, block)| block.labels.iter().map(move |label| (*label, index)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{code_gen::lower::lower, parsing::parse::parse_string, target::Target};
+
+    fn graph(program: &str, exports: Vec<String>) -> ControlFlowGraph {
+        let mut errors = vec![];
+        let program = lower(
+            &parse_string(program.to_owned()),
+            exports,
+            &[],
+            &Target::default(),
+            &mut errors,
+        );
+        assert!(errors.is_empty());
+        ControlFlowGraph::new(&program)
+    }
+
+    #[test]
+    fn connects_branches_jumps_and_fall_throughs() {
+        // blocks: DEF | :loop JZ | JMP | :end EXIT
+        let graph = graph(
+            "DEF ?a 1\n:loop\nJZ ?a :end\nJMP :loop\n:end\nEXIT ?a\n",
+            vec![],
+        );
+        assert_eq!(graph.successors, vec![vec![1], vec![2, 3], vec![1], vec![]]);
+        assert_eq!(
+            graph.predecessors,
+            vec![vec![], vec![0, 2], vec![1], vec![1]]
+        );
+        assert_eq!(graph.reachable(), vec![true; 4]);
+    }
+
+    #[test]
+    fn code_after_exit_is_unreachable_unless_exported() {
+        let program = "DEF ?a 1\nEXIT ?a\n:other\nEXIT ?a\n";
+        assert_eq!(graph(program, vec![]).reachable(), vec![true, false]);
+        assert_eq!(
+            graph(program, vec!["other".to_owned()]).reachable(),
+            vec![true, true]
+        );
+    }
+}
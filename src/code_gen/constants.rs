@@ -0,0 +1,380 @@
+use crate::target::Target;
+
+use super::{
+    cfg::{block_of_labels, ControlFlowGraph},
+    ir::{Arithmetic, Condition, Operation, Program, Value, VariableKind},
+};
+
+/// the value every variable is known to have, none if it can be different every time
+type Constants = Vec<Option<u64>>;
+
+/// finds the variables that always have the same value and puts the value into the operations.
+/// arithmetic on values that are known becomes a DEF of the result,
+/// branches that always or never jump become a JMP or are removed.
+/// the arithmetic is done like the target does it
+pub fn fold_constants(program: &mut Program, target: &Target) {
+    let shared = shared_variables(program);
+    let states = block_states(program, &shared, target);
+    for (block, state) in program.blocks.iter_mut().zip(states) {
+        let Some(mut state) = state else {
+            continue;
+        };
+        let instructions = std::mem::take(&mut block.instructions);
+        for mut instruction in instructions {
+            let operation = fold(&instruction.operation, &state, target);
+            transfer(&mut state, &operation, &shared, target);
+            let Some(operation) = operation else {
+                continue;
+            };
+            instruction.operation = operation;
+            block.instructions.push(instruction);
+        }
+    }
+}
+
+/// variables of other objects can change while their code runs and devices can change variables
+/// with a fixed address, so they are never constant
+fn shared_variables(program: &Program) -> Vec<bool> {
+    program
+        .variables
+        .iter()
+        .map(|kind| match kind {
+            VariableKind::External(_) | VariableKind::Fixed { .. } => true,
+            VariableKind::Named(name) => program.exports.contains(name),
+            VariableKind::Scratch => false,
+        })
+        .collect()
+}
+
+/// the constants at the start of every block, none if the block can never run
+fn block_states(program: &Program, shared: &[bool], target: &Target) -> Vec<Option<Constants>> {
+    let graph = ControlFlowGraph::new(program);
+    let blocks = block_of_labels(program);
+    let mut states: Vec<Option<Constants>> = vec![None; program.blocks.len()];
+    for entry in &graph.entries {
+        states[*entry] = Some(vec![None; program.variables.len()]);
+    }
+    let mut todo = graph.entries.clone();
+    while let Some(index) = todo.pop() {
+        let mut state = states[index].clone().unwrap();
+        let mut successors: Vec<usize> = vec![];
+        let mut falls_through = true;
+        for instruction in &program.blocks[index].instructions {
+            let operation = fold(&instruction.operation, &state, target);
+            transfer(&mut state, &operation, shared, target);
+            falls_through = !matches!(
+                operation,
+                Some(Operation::Jump { .. } | Operation::Exit { .. })
+            );
+            if let Some(Operation::Branch { target, .. } | Operation::Jump { target }) = operation {
+                successors.extend(blocks.get(&target));
+            }
+        }
+        if falls_through && index + 1 < program.blocks.len() {
+            successors.push(index + 1);
+        }
+        for successor in successors {
+            let merged = match &states[successor] {
+                None => state.clone(),
+                Some(old) => old
+                    .iter()
+                    .zip(&state)
+                    .map(|(old, new)| if old == new { *old } else { None })
+                    .collect(),
+            };
+            if states[successor].as_ref() != Some(&merged) {
+                states[successor] = Some(merged);
+                todo.push(successor);
+            }
+        }
+    }
+    states
+}
+
+/// the operation with the known values put in, none if it does nothing
+fn fold(operation: &Operation, state: &Constants, target: &Target) -> Option<Operation> {
+    let value = |value: &Value| match value {
+        Value::Variable(variable) => match state[variable.0] {
+            Some(constant) => Value::Immediate(constant),
+            None => *value,
+        },
+        Value::Immediate(_) => *value,
+    };
+    let folded = match operation {
+        Operation::Arithmetic {
+            arithmetic,
+            a,
+            b,
+            destination,
+        } => {
+            let (a, b) = (value(a), value(b));
+            match (a, b) {
+                (Value::Immediate(a), Value::Immediate(b)) => {
+                    match target.calculate(*arithmetic, a, b) {
+                        Ok(result) => Operation::Arithmetic {
+                            arithmetic: Arithmetic::Add,
+                            a: Value::Immediate(result),
+                            b: Value::Immediate(0),
+                            destination: *destination,
+                        },
+                        // dividing by 0 and overflows that trap stay an error when the program runs
+                        Err(_) => Operation::Arithmetic {
+                            arithmetic: *arithmetic,
+                            a: Value::Immediate(a),
+                            b: Value::Immediate(b),
+                            destination: *destination,
+                        },
+                    }
+                }
+                _ => Operation::Arithmetic {
+                    arithmetic: *arithmetic,
+                    a,
+                    b,
+                    destination: *destination,
+                },
+            }
+        }
+        Operation::Branch {
+            condition,
+            a,
+            b,
+            target: label,
+        } => {
+            let (a, b) = (value(a), value(b));
+            match (a, b) {
+                (Value::Immediate(a), Value::Immediate(b)) => {
+                    if !compare(*condition, a, b, target) {
+                        return None;
+                    }
+                    Operation::Jump { target: *label }
+                }
+                _ => Operation::Branch {
+                    condition: *condition,
+                    a,
+                    b,
+                    target: *label,
+                },
+            }
+        }
+        Operation::Yeet {
+            device,
+            instruction,
+            address,
+        } => Operation::Yeet {
+            device: value(device),
+            instruction: value(instruction),
+            address: value(address),
+        },
+        Operation::Yoink {
+            device,
+            instruction,
+            destination,
+        } => Operation::Yoink {
+            device: value(device),
+            instruction: value(instruction),
+            destination: *destination,
+        },
+        Operation::Copy { .. } | Operation::Jump { .. } | Operation::Exit { .. } => {
+            operation.clone()
+        }
+    };
+    Some(folded)
+}
+
+/// what the values of the variables are after the folded operation
+fn transfer(
+    state: &mut Constants,
+    operation: &Option<Operation>,
+    shared: &[bool],
+    target: &Target,
+) {
+    match operation {
+        Some(Operation::Arithmetic {
+            arithmetic,
+            a,
+            b,
+            destination,
+        }) => {
+            state[destination.0] = match (a, b) {
+                (Value::Immediate(a), Value::Immediate(b)) => {
+                    target.calculate(*arithmetic, *a, *b).ok()
+                }
+                _ => None,
+            }
+        }
+        Some(Operation::Copy { from, to }) => state[to.0] = state[from.0],
+        Some(Operation::Yoink { destination, .. }) => state[destination.0] = None,
+        _ => {}
+    }
+    for (constant, shared) in state.iter_mut().zip(shared) {
+        if *shared {
+            *constant = None;
+        }
+    }
+}
+
+fn compare(condition: Condition, a: u64, b: u64, target: &Target) -> bool {
+    match condition {
+        Condition::Equal => a == b,
+        Condition::NotEqual => a != b,
+        Condition::HigherThan => target.higher_than(a, b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        code_gen::{
+            compile_object, dead_code::remove_unreachable_blocks, ir::VariableId, link::link,
+            lower::lower, CompileOptions,
+        },
+        emulator::{Machine, Yeet},
+        parsing::parse::parse_string,
+    };
+
+    fn folded(program: &str) -> Program {
+        let mut errors = vec![];
+        let mut program = lower(
+            &parse_string(program.to_owned()),
+            vec![],
+            &[],
+            &Target::default(),
+            &mut errors,
+        );
+        fold_constants(&mut program, &Target::default());
+        remove_unreachable_blocks(&mut program);
+        program
+    }
+
+    fn operations(program: &Program) -> Vec<Operation> {
+        program
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .map(|instruction| instruction.operation.clone())
+            .collect()
+    }
+
+    fn run(program: &str, optimize: bool) -> (u64, Vec<Yeet>, usize) {
+        let object = compile_object(
+            parse_string(program.to_owned()),
+            &[],
+            &CompileOptions {
+                opt_level: u8::from(optimize),
+                ..CompileOptions::default()
+            },
+        )
+        .unwrap();
+        let code = link(vec![object], &Target::default()).unwrap();
+        let mut machine = Machine::new(&code);
+        let exit_code = machine.run(1_000_000).unwrap();
+        (exit_code, machine.yeets, code.len())
+    }
+
+    #[test]
+    fn folds_arithmetic_on_defines() {
+        let program = folded("DEF ?a 6\nDEF ?b 7\nMUL ?a ?b ?a\nSUB ?a ?b ?b\nEXIT ?b\n");
+        assert_eq!(
+            operations(&program)[2..4],
+            [
+                Operation::Arithmetic {
+                    arithmetic: Arithmetic::Add,
+                    a: Value::Immediate(42),
+                    b: Value::Immediate(0),
+                    destination: VariableId(0),
+                },
+                Operation::Arithmetic {
+                    arithmetic: Arithmetic::Add,
+                    a: Value::Immediate(35),
+                    b: Value::Immediate(0),
+                    destination: VariableId(1),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn values_that_change_in_a_loop_are_not_constant() {
+        let program = folded(
+            "DEF ?i 0\nDEF ?one 1\nDEF ?three 3\n:loop\nADD ?i ?one ?i\nJNE ?i ?three :loop\nEXIT ?i\n",
+        );
+        assert_eq!(
+            operations(&program)[3],
+            Operation::Arithmetic {
+                arithmetic: Arithmetic::Add,
+                a: Value::Variable(VariableId(0)),
+                b: Value::Immediate(1),
+                destination: VariableId(0),
+            }
+        );
+    }
+
+    #[test]
+    fn rewrites_branches_with_known_conditions() {
+        let program =
+            folded("DEF ?a 1\nDEF ?b 2\nJE ?a ?b :end\nJLT ?a ?b :end\nEXIT ?a\n:end\nEXIT ?b\n");
+        let operations = operations(&program);
+        // the JE never jumps and the JLT always does, so the first EXIT is gone too
+        assert_eq!(operations.len(), 4);
+        assert!(matches!(operations[2], Operation::Jump { .. }));
+    }
+
+    #[test]
+    fn folds_like_the_target() {
+        let mut errors = vec![];
+        let target = Target {
+            word_size: 8,
+            ..Target::default()
+        };
+        let mut program = lower(
+            &parse_string("DEF ?a 200\nDEF ?b 100\nADD ?a ?b ?a\nEXIT ?a\n".to_owned()),
+            vec![],
+            &[],
+            &target,
+            &mut errors,
+        );
+        fold_constants(&mut program, &target);
+        assert_eq!(
+            operations(&program)[2],
+            Operation::Arithmetic {
+                arithmetic: Arithmetic::Add,
+                a: Value::Immediate(44),
+                b: Value::Immediate(0),
+                destination: VariableId(0),
+            }
+        );
+    }
+
+    #[test]
+    fn dividing_by_zero_is_not_folded() {
+        let program = folded("DEF ?a 1\nDEF ?zero 0\nDIV ?a ?zero ?a\nEXIT ?a\n");
+        assert_eq!(
+            operations(&program)[2],
+            Operation::Arithmetic {
+                arithmetic: Arithmetic::Div,
+                a: Value::Immediate(1),
+                b: Value::Immediate(0),
+                destination: VariableId(0),
+            }
+        );
+    }
+
+    #[test]
+    fn folded_programs_do_the_same_thing() {
+        for program in [
+            include_str!("../../tests/snapshots/arithmetic.ass"),
+            include_str!("../../tests/snapshots/bitwise.ass"),
+            include_str!("../../tests/snapshots/blocks.ass"),
+            include_str!("../../tests/snapshots/branches.ass"),
+            include_str!("../../tests/snapshots/modules.ass"),
+            include_str!("../../tests/snapshots/yoink.ass"),
+            // copy_and_yeet is left out, its ?address depends on the length of the code
+        ] {
+            let (exit_code, yeets, length) = run(program, false);
+            let (folded_exit_code, folded_yeets, folded_length) = run(program, true);
+            assert_eq!((exit_code, yeets), (folded_exit_code, folded_yeets));
+            assert!(folded_length <= length, "{}", program);
+        }
+    }
+}
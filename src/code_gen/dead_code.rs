@@ -0,0 +1,86 @@
+use std::ops::RangeInclusive;
+
+use super::{cfg::ControlFlowGraph, ir::Program};
+
+/// removes the blocks that can never run.
+/// returns the first and last AST node of every piece of code that was removed,
+/// the jumps IF and ELSE make after an EXIT are left out because they are not in the source
+pub fn remove_unreachable_blocks(program: &mut Program) -> Vec<RangeInclusive<usize>> {
+    let reachable = ControlFlowGraph::new(program).reachable();
+    let mut removed: Vec<RangeInclusive<usize>> = vec![];
+    let mut in_removed_code = false;
+    for (block, reachable) in program.blocks.iter().zip(&reachable) {
+        if *reachable {
+            in_removed_code = false;
+            continue;
+        }
+        let (Some(first), Some(last)) = (block.instructions.first(), block.instructions.last())
+        else {
+            continue;
+        };
+        if block
+            .instructions
+            .iter()
+            .all(|instruction| instruction.generated)
+        {
+            continue;
+        }
+        match removed.last_mut() {
+            Some(code) if in_removed_code => *code = *code.start()..=last.source,
+            _ => removed.push(first.source..=last.source),
+        }
+        in_removed_code = true;
+    }
+    let mut reachable = reachable.into_iter();
+    program.blocks.retain(|_| reachable.next().unwrap());
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{code_gen::lower::lower, parsing::parse::parse_string, target::Target};
+
+    fn remove(program: &str) -> (Program, Vec<RangeInclusive<usize>>) {
+        let mut errors = vec![];
+        let mut program = lower(
+            &parse_string(program.to_owned()),
+            vec![],
+            &[],
+            &Target::default(),
+            &mut errors,
+        );
+        let removed = remove_unreachable_blocks(&mut program);
+        (program, removed)
+    }
+
+    #[test]
+    fn removes_code_that_is_jumped_over() {
+        let (program, removed) =
+            remove("DEF ?a 1\nJMP :end\nDEF ?b 2\nADD ?a ?b ?a\n:end\nEXIT ?a\n");
+        assert_eq!(removed, vec![2..=3]);
+        assert_eq!(program.blocks.len(), 2);
+    }
+
+    #[test]
+    fn removes_jumps_of_blocks_after_exit_without_a_warning() {
+        let (program, removed) = remove("DEF ?a 1\nIF ?a == ?a\nEXIT ?a\nELSE\nEXIT ?a\nENDIF\n");
+        assert_eq!(removed, vec![]);
+        assert_eq!(program.blocks.len(), 4);
+    }
+
+    #[test]
+    fn warns_about_jumps_from_the_source_after_exit() {
+        let (program, removed) = remove("DEF ?a 1\nJZ ?a :end\nEXIT ?a\nJMP :end\n:end\nEXIT ?a\n");
+        assert_eq!(removed, vec![3..=3]);
+        assert_eq!(program.blocks.len(), 3);
+    }
+
+    #[test]
+    fn keeps_code_that_is_jumped_to() {
+        let (program, removed) =
+            remove("DEF ?a 1\nJMP :end\n:back\nEXIT ?a\n:end\nJMP :back\nEXIT ?a\n");
+        assert_eq!(removed, vec![6..=6]);
+        assert_eq!(program.blocks.len(), 3);
+    }
+}
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+/// where the instructions of a program came from and where its symbols ended up
+#[derive(Debug, Clone)]
+pub struct DebugInfo {
+    /// ast index every raw instruction was generated from
+    pub instruction_sources: Vec<usize>,
+    /// cells every instruction takes
+    pub cell_width: u64,
+    /// address of the first instruction
+    pub code_origin: u64,
+    /// address of every variable by name, without the ?
+    pub variables: HashMap<String, u64>,
+    /// address of every label by name, without the :
+    pub labels: HashMap<String, u64>,
+}
+
+impl DebugInfo {
+    /// ast index of the instruction that runs when the program counter has this value
+    pub fn source_of(&self, program_counter: u64) -> Option<usize> {
+        let instruction = self.instruction_at(program_counter)?;
+        self.instruction_sources.get(instruction).copied()
+    }
+
+    /// program counter value where the code of an ast node starts,
+    /// none if the ast node generates no code
+    pub fn start_of(&self, source: usize) -> Option<u64> {
+        self.instruction_sources
+            .iter()
+            .position(|instruction_source| *instruction_source == source)
+            .map(|instruction| self.program_counter_of(instruction))
+    }
+
+    /// the instruction that runs when the program counter has this value
+    pub fn instruction_at(&self, program_counter: u64) -> Option<usize> {
+        let offset = program_counter.checked_sub(self.code_origin)?;
+        (offset / self.cell_width)
+            .checked_sub(1)
+            .map(|instruction| instruction as usize)
+    }
+
+    /// the program counter value that runs the instruction
+    pub fn program_counter_of(&self, instruction: usize) -> u64 {
+        self.code_origin + (instruction as u64 + 1) * self.cell_width
+    }
+
+    /// variable whose value is stored at the address
+    pub fn variable_at(&self, address: u64) -> Option<&str> {
+        self.variables
+            .iter()
+            .find(|(_, variable_address)| **variable_address == address)
+            .map(|(name, _)| name.as_str())
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+/// a variable before it has an address, layout gives it one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VariableId(pub usize);
+
+/// a place in the code before it has an address, layout gives it one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LabelId(pub usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariableKind {
+    /// made with DEF
+    Named(String),
+    /// only the compiler uses it
+    Scratch,
+    /// defined in another object, the linker gives it an address
+    External(String),
+    /// made with DEF ?name @address, like the registers of a device
+    Fixed { name: String, address: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LabelKind {
+    /// made with :name
+    Named(String),
+    /// made by the compiler, like the loops of the bitwise operations
+    Internal,
+    /// defined in another object, the linker gives it an address
+    External(String),
+}
+
+/// an operand that is read as a number
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    /// the value of a variable, patched into the instruction right before it runs
+    Variable(VariableId),
+    Immediate(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arithmetic {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Equal,
+    NotEqual,
+    HigherThan,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// destination = a <arithmetic> b
+    Arithmetic {
+        arithmetic: Arithmetic,
+        a: Value,
+        b: Value,
+        destination: VariableId,
+    },
+    Copy {
+        from: VariableId,
+        to: VariableId,
+    },
+    /// jumps to target if a <condition> b, otherwise goes to the next block
+    Branch {
+        condition: Condition,
+        a: Value,
+        b: Value,
+        target: LabelId,
+    },
+    Jump {
+        target: LabelId,
+    },
+    Exit {
+        code: VariableId,
+    },
+    /// sends the value at the address in `address` to a SECS device
+    Yeet {
+        device: Value,
+        instruction: Value,
+        address: Value,
+    },
+    /// reads a value from a SECS device into destination
+    Yoink {
+        device: Value,
+        instruction: Value,
+        destination: VariableId,
+    },
+}
+
+impl Operation {
+    /// the last operation of a block, the code after it starts a new block
+    pub fn ends_block(&self) -> bool {
+        matches!(
+            self,
+            Operation::Branch { .. } | Operation::Jump { .. } | Operation::Exit { .. }
+        )
+    }
+    /// the values the operation reads, in operand order
+    pub fn values(&self) -> Vec<Value> {
+        match *self {
+            Operation::Arithmetic { a, b, .. } | Operation::Branch { a, b, .. } => vec![a, b],
+            Operation::Yeet {
+                device,
+                instruction,
+                address,
+            } => vec![device, instruction, address],
+            Operation::Yoink {
+                device,
+                instruction,
+                ..
+            } => vec![device, instruction],
+            Operation::Copy { .. } | Operation::Jump { .. } | Operation::Exit { .. } => vec![],
+        }
+    }
+    /// the most operands a raw instruction of the operation has
+    pub fn operands(&self) -> usize {
+        match self {
+            Operation::Branch { .. } | Operation::Yeet { .. } | Operation::Yoink { .. } => 3,
+            Operation::Arithmetic { .. } | Operation::Copy { .. } => 2,
+            Operation::Jump { .. } | Operation::Exit { .. } => 1,
+        }
+    }
+    /// amount of raw instructions the operation is laid out as
+    pub fn length(&self) -> usize {
+        let patches = self
+            .values()
+            .iter()
+            .filter(|value| matches!(value, Value::Variable(_)))
+            .count();
+        match self {
+            // the arithmetic and the WRITE of the result
+            Operation::Arithmetic { .. } => patches + 2,
+            _ => patches + 1,
+        }
+    }
+}
+
+/// an operation and the AST node it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub operation: Operation,
+    pub source: usize,
+    /// made for an IF or WHILE instead of written in the source
+    pub generated: bool,
+}
+
+/// code that only runs from its start to its end,
+/// only the last instruction can jump and only the start can be jumped to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BasicBlock {
+    /// labels that point at the start of the block
+    pub labels: Vec<LabelId>,
+    pub instructions: Vec<Instruction>,
+}
+
+impl BasicBlock {
+    /// if the block can go on to the block after it
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self.instructions
+                .last()
+                .map(|instruction| &instruction.operation),
+            Some(Operation::Jump { .. } | Operation::Exit { .. })
+        )
+    }
+}
+
+/// a program with symbolic variables and labels,
+/// the blocks are laid out in order so a block falls through to the next one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Program {
+    pub blocks: Vec<BasicBlock>,
+    /// kind of every variable by VariableId
+    pub variables: Vec<VariableKind>,
+    /// kind of every label by LabelId
+    pub labels: Vec<LabelKind>,
+    /// names of the variables and labels other objects can use
+    pub exports: Vec<String>,
+    /// the AST node every named label is defined at
+    pub label_definitions: HashMap<LabelId, usize>,
+    /// address the code starts at, set with ORG
+    pub origin: Option<u64>,
+}
+
+impl Program {
+    pub fn new_variable(&mut self, kind: VariableKind) -> VariableId {
+        self.variables.push(kind);
+        VariableId(self.variables.len() - 1)
+    }
+    pub fn new_label(&mut self, kind: LabelKind) -> LabelId {
+        self.labels.push(kind);
+        LabelId(self.labels.len() - 1)
+    }
+    /// makes the label point at the code that is pushed next
+    pub fn place_label(&mut self, label: LabelId) {
+        match self.blocks.last_mut() {
+            Some(block) if block.instructions.is_empty() => block.labels.push(label),
+            _ => self.blocks.push(BasicBlock {
+                labels: vec![label],
+                instructions: vec![],
+            }),
+        }
+    }
+    pub fn push(&mut self, operation: Operation, source: usize) {
+        self.push_instruction(Instruction {
+            operation,
+            source,
+            generated: false,
+        });
+    }
+    /// like push for code that IF and WHILE need but the source does not have
+    pub fn push_generated(&mut self, operation: Operation, source: usize) {
+        self.push_instruction(Instruction {
+            operation,
+            source,
+            generated: true,
+        });
+    }
+    fn push_instruction(&mut self, instruction: Instruction) {
+        let starts_block = match self.blocks.last() {
+            Some(block) => block
+                .instructions
+                .last()
+                .is_some_and(|last| last.operation.ends_block()),
+            None => true,
+        };
+        if starts_block {
+            self.blocks.push(BasicBlock::default());
+        }
+        self.blocks
+            .last_mut()
+            .unwrap()
+            .instructions
+            .push(instruction);
+    }
+    /// the named variable, DEF of a name that already exists uses the same variable
+    pub fn named_variable(&self, name: &str) -> Option<VariableId> {
+        self.variables
+            .iter()
+            .position(|kind| match kind {
+                VariableKind::Named(named) | VariableKind::Fixed { name: named, .. } => {
+                    named == name
+                }
+                _ => false,
+            })
+            .map(VariableId)
+    }
+    /// the last label defined with the name
+    pub fn named_label(&self, name: &str) -> Option<LabelId> {
+        self.labels
+            .iter()
+            .rposition(|kind| matches!(kind, LabelKind::Named(named) if named == name))
+            .map(LabelId)
+    }
+}
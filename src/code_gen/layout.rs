@@ -0,0 +1,332 @@
+use crate::target::InstructionSet;
+
+use super::{
+    debug_info::DebugInfo,
+    ir::{Arithmetic, Condition, LabelKind, Operation, Program, Value, VariableId, VariableKind},
+    object::{ObjectFile, RelocationKind, Symbol, SymbolKind},
+    RawInstructions,
+};
+
+struct Variable {
+    ram_index: u64,
+    /// how the linker has to move ram_index
+    relocation: RelocationKind,
+}
+
+struct LabelAddress {
+    address: u64,
+    relocation: RelocationKind,
+}
+
+/// an operand of a raw instruction
+enum Slot<'a> {
+    /// the value of the variable gets copied into the operand right before the instruction
+    Patched(&'a Variable),
+    Fixed(u64, RelocationKind),
+}
+
+const UNUSED: Slot = Slot::Fixed(0, RelocationKind::Absolute);
+
+/// gives every variable and label an address and turns the program into raw instructions.
+/// the blocks are put after each other in order
+pub fn layout(program: &Program, instruction_set: &InstructionSet) -> (ObjectFile, DebugInfo) {
+    let mut ram_size = 0;
+    let variables: Vec<Variable> = program
+        .variables
+        .iter()
+        .map(|kind| match kind {
+            VariableKind::External(name) => Variable {
+                ram_index: 0,
+                relocation: RelocationKind::Variable(name.clone()),
+            },
+            VariableKind::Fixed { address, .. } => Variable {
+                ram_index: *address,
+                relocation: RelocationKind::Absolute,
+            },
+            VariableKind::Named(_) | VariableKind::Scratch => {
+                ram_size += 1;
+                Variable {
+                    ram_index: ram_size - 1,
+                    relocation: RelocationKind::Ram,
+                }
+            }
+        })
+        .collect();
+    // labels of blocks that were removed have no address
+    let mut addresses = vec![None; program.labels.len()];
+    let mut length = 0;
+    for block in &program.blocks {
+        for label in &block.labels {
+            addresses[label.0] = Some(instruction_set.jump_address(length));
+        }
+        length += block
+            .instructions
+            .iter()
+            .map(|instruction| instruction.operation.length())
+            .sum::<usize>();
+    }
+    let labels: Vec<LabelAddress> = program
+        .labels
+        .iter()
+        .zip(&addresses)
+        .map(|(kind, address)| match kind {
+            LabelKind::External(name) => LabelAddress {
+                address: 0,
+                relocation: RelocationKind::Label(name.clone()),
+            },
+            LabelKind::Named(_) | LabelKind::Internal => LabelAddress {
+                address: address.unwrap_or(0),
+                relocation: RelocationKind::Code,
+            },
+        })
+        .collect();
+
+    let mut object = ObjectFile {
+        origin: program.origin,
+        instruction_set: instruction_set.clone(),
+        ..ObjectFile::default()
+    };
+    let mut instruction_sources = vec![];
+    let value = |value: &Value| match value {
+        Value::Variable(variable) => Slot::Patched(&variables[variable.0]),
+        Value::Immediate(value) => Slot::Fixed(*value, RelocationKind::Absolute),
+    };
+    let address = |variable: &VariableId| {
+        let variable = &variables[variable.0];
+        Slot::Fixed(variable.ram_index, variable.relocation.clone())
+    };
+    for instruction in program.blocks.iter().flat_map(|block| &block.instructions) {
+        match &instruction.operation {
+            Operation::Arithmetic {
+                arithmetic,
+                a,
+                b,
+                destination,
+            } => {
+                let raw_instruction = match arithmetic {
+                    Arithmetic::Add => RawInstructions::ADD { a: 0, b: 0 },
+                    Arithmetic::Sub => RawInstructions::SUB { a: 0, b: 0 },
+                    Arithmetic::Mul => RawInstructions::MUL { a: 0, b: 0 },
+                    Arithmetic::Div => RawInstructions::DIV { a: 0, b: 0 },
+                };
+                push_patched(
+                    &mut object,
+                    instruction_set,
+                    raw_instruction,
+                    [value(a), value(b), UNUSED],
+                );
+                push_patched(
+                    &mut object,
+                    instruction_set,
+                    RawInstructions::WRITE { a: 0 },
+                    [address(destination), UNUSED, UNUSED],
+                );
+            }
+            Operation::Copy { from, to } => push_patched(
+                &mut object,
+                instruction_set,
+                RawInstructions::COPY { a: 0, b: 0 },
+                [address(from), address(to), UNUSED],
+            ),
+            Operation::Branch {
+                condition,
+                a,
+                b,
+                target,
+            } => {
+                let raw_instruction = match condition {
+                    Condition::Equal => RawInstructions::Equal { a: 0, b: 0, c: 0 },
+                    Condition::NotEqual => RawInstructions::NotEqual { a: 0, b: 0, c: 0 },
+                    Condition::HigherThan => RawInstructions::JHT { a: 0, b: 0, c: 0 },
+                };
+                let target = &labels[target.0];
+                push_patched(
+                    &mut object,
+                    instruction_set,
+                    raw_instruction,
+                    [
+                        value(a),
+                        value(b),
+                        Slot::Fixed(target.address, target.relocation.clone()),
+                    ],
+                );
+            }
+            Operation::Jump { target } => {
+                let target = &labels[target.0];
+                push_patched(
+                    &mut object,
+                    instruction_set,
+                    RawInstructions::JMP { a: 0 },
+                    [
+                        Slot::Fixed(target.address, target.relocation.clone()),
+                        UNUSED,
+                        UNUSED,
+                    ],
+                );
+            }
+            Operation::Exit { code } => push_patched(
+                &mut object,
+                instruction_set,
+                RawInstructions::EXIT { a: 0 },
+                [address(code), UNUSED, UNUSED],
+            ),
+            Operation::Yeet {
+                device,
+                instruction,
+                address,
+            } => push_patched(
+                &mut object,
+                instruction_set,
+                RawInstructions::YEET { a: 0, b: 0, c: 0 },
+                [value(device), value(instruction), value(address)],
+            ),
+            Operation::Yoink {
+                device,
+                instruction,
+                destination,
+            } => push_patched(
+                &mut object,
+                instruction_set,
+                RawInstructions::YOINK { a: 0, b: 0, c: 0 },
+                [value(device), value(instruction), address(destination)],
+            ),
+        }
+        instruction_sources.resize(object.raw_instructions.len(), instruction.source);
+    }
+    object.ram_size = ram_size;
+    object.fixed_variables = program
+        .variables
+        .iter()
+        .filter_map(|kind| match kind {
+            VariableKind::Fixed { name, address } => Some((name.clone(), *address)),
+            _ => None,
+        })
+        .collect();
+
+    for name in &program.exports {
+        if let Some(variable) = program.named_variable(name) {
+            let variable = &variables[variable.0];
+            object.symbols.push(Symbol {
+                name: name.clone(),
+                kind: match variable.relocation {
+                    RelocationKind::Absolute => SymbolKind::Fixed,
+                    _ => SymbolKind::Variable,
+                },
+                value: variable.ram_index,
+            });
+        }
+        if let Some(label) = program
+            .named_label(name)
+            .filter(|label| addresses[label.0].is_some())
+        {
+            object.symbols.push(Symbol {
+                name: name.clone(),
+                kind: SymbolKind::Label,
+                value: labels[label.0].address,
+            });
+        }
+    }
+    let debug_info = DebugInfo {
+        instruction_sources,
+        cell_width: instruction_set.cell_width,
+        code_origin: 0,
+        variables: program
+            .variables
+            .iter()
+            .zip(&variables)
+            .filter_map(|(kind, variable)| match kind {
+                VariableKind::Named(name) => Some((name.clone(), variable.ram_index)),
+                _ => None,
+            })
+            .collect(),
+        labels: program
+            .labels
+            .iter()
+            .zip(&addresses)
+            .filter_map(|(kind, address)| match (kind, address) {
+                (LabelKind::Named(name), Some(address)) => Some((name.clone(), *address)),
+                _ => None,
+            })
+            .collect(),
+    };
+    (object, debug_info)
+}
+
+/// pushes the instruction with the COPYs in front of it that patch its variable operands
+fn push_patched(
+    object: &mut ObjectFile,
+    instruction_set: &InstructionSet,
+    mut raw_instruction: RawInstructions,
+    slots: [Slot; 3],
+) {
+    let patches = slots
+        .iter()
+        .filter(|slot| matches!(slot, Slot::Patched(_)))
+        .count();
+    let instruction = object.raw_instructions.len() + patches;
+    let mut relocations = [
+        RelocationKind::Absolute,
+        RelocationKind::Absolute,
+        RelocationKind::Absolute,
+    ];
+    for (index, slot) in slots.into_iter().enumerate() {
+        let (value, relocation) = match slot {
+            Slot::Patched(variable) => {
+                object.push(
+                    RawInstructions::COPY {
+                        a: variable.ram_index,
+                        b: instruction_set.operand_address(instruction, index + 1),
+                    },
+                    [
+                        variable.relocation.clone(),
+                        RelocationKind::Code,
+                        RelocationKind::Absolute,
+                    ],
+                );
+                (variable.ram_index, variable.relocation.clone())
+            }
+            Slot::Fixed(value, relocation) => (value, relocation),
+        };
+        if let Some(operand) = raw_instruction.operand_mut(index + 1) {
+            *operand = value;
+        }
+        relocations[index] = relocation;
+    }
+    object.push(raw_instruction, relocations);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_point_at_the_start_of_their_block() {
+        let mut program = Program::default();
+        let a = program.new_variable(VariableKind::Named("a".to_owned()));
+        let end = program.new_label(LabelKind::Internal);
+        program.push(
+            Operation::Branch {
+                condition: Condition::Equal,
+                a: Value::Variable(a),
+                b: Value::Immediate(0),
+                target: end,
+            },
+            0,
+        );
+        program.push(Operation::Jump { target: end }, 1);
+        program.place_label(end);
+        program.push(Operation::Exit { code: a }, 2);
+        let (object, debug_info) = layout(&program, &InstructionSet::default());
+        assert_eq!(
+            object.raw_instructions,
+            vec![
+                RawInstructions::COPY { a: 0, b: 5 },
+                RawInstructions::Equal { a: 0, b: 0, c: 16 },
+                RawInstructions::JMP { a: 16 },
+                RawInstructions::EXIT { a: 0 },
+            ]
+        );
+        assert_eq!(object.ram_size, 1);
+        assert_eq!(debug_info.instruction_sources, vec![0, 0, 1, 2]);
+    }
+}
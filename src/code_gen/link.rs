@@ -0,0 +1,533 @@
+use std::{collections::HashMap, fmt::Display, ops::Range};
+
+use log::error;
+
+use crate::target::{InstructionSet, Target};
+
+use super::{
+    object::{CellLayout, ObjectFile, RelocationKind, SymbolKind},
+    RawInstructions,
+};
+
+#[derive(Debug)]
+pub struct LinkError {
+    pub type_of_error: LinkErrorType,
+    /// which of the linked objects the error is in, none if it is about all of them
+    pub object_num: Option<usize>,
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let err_message = match &self.type_of_error {
+            LinkErrorType::LabelDoesntExist { name } => {
+                format!("label {} is not exported by any object", name)
+            }
+            LinkErrorType::VariableDoesntExist { name } => {
+                format!("variable {} is not exported by any object", name)
+            }
+            LinkErrorType::DuplicateSymbol { name } => {
+                format!("{} is exported by more than one object", name)
+            }
+            LinkErrorType::InvalidRelocation { instruction } => {
+                format!("relocation for instruction {} is invalid", instruction)
+            }
+            LinkErrorType::MisplacedOrigin => "only the first object can have an ORG".to_owned(),
+            LinkErrorType::InstructionSetMismatch { object, target } => {
+                format!(
+                    "the object was compiled for instructions of {} but the instruction set has {}",
+                    object, target
+                )
+            }
+            LinkErrorType::OriginMismatch {
+                origin,
+                code_origin,
+            } => {
+                format!(
+                    "ORG {} does not match the code origin {} of the target",
+                    origin, code_origin
+                )
+            }
+            LinkErrorType::OutOfRam { needed, available } => {
+                format!(
+                    "the variables need {} cells but there are only {} cells of RAM",
+                    needed, available
+                )
+            }
+            LinkErrorType::CodeOverlapsRam { code, ram } => {
+                format!(
+                    "the code at {} to {} overlaps the RAM at {} to {}",
+                    code.start,
+                    code.end - 1,
+                    ram.start,
+                    ram.end - 1
+                )
+            }
+            LinkErrorType::FixedAddressInCode { name, address } => {
+                format!(
+                    "the address {} of variable {} is in the code",
+                    address, name
+                )
+            }
+            LinkErrorType::FixedAddressInRam { name, address } => {
+                format!(
+                    "the address {} of variable {} is given to another variable",
+                    address, name
+                )
+            }
+            LinkErrorType::OutOfMemory { end, last_address } => {
+                format!(
+                    "the program needs the addresses up to {} but the last address is {}",
+                    end - 1,
+                    last_address
+                )
+            }
+        };
+        match self.object_num {
+            Some(object_num) => write!(f, "link error in object {}: {}", object_num, err_message),
+            None => write!(f, "link error: {}", err_message),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LinkErrorType {
+    LabelDoesntExist {
+        name: String,
+    },
+    VariableDoesntExist {
+        name: String,
+    },
+    DuplicateSymbol {
+        name: String,
+    },
+    InvalidRelocation {
+        instruction: usize,
+    },
+    MisplacedOrigin,
+    /// the object was compiled with another --instruction-set
+    InstructionSetMismatch {
+        object: CellLayout,
+        target: CellLayout,
+    },
+    /// ORG and the target both say where the code starts
+    OriginMismatch {
+        origin: u64,
+        code_origin: u64,
+    },
+    OutOfRam {
+        needed: u64,
+        available: u64,
+    },
+    /// the addresses the code and the RAM use, end is not included
+    CodeOverlapsRam {
+        code: Range<u64>,
+        ram: Range<u64>,
+    },
+    FixedAddressInCode {
+        name: String,
+        address: u64,
+    },
+    /// the address is in the RAM the linker gives the variables without a fixed address
+    FixedAddressInRam {
+        name: String,
+        address: u64,
+    },
+    /// end is one past the last address the program uses
+    OutOfMemory {
+        end: u64,
+        last_address: u64,
+    },
+}
+
+/// puts the code of all objects after each other in order at the code origin, followed by the RAM
+/// of all objects unless the target has RAM somewhere else, and fills in every address.
+/// the first object is where the program starts.
+/// the objects have to be compiled for the cells of the instruction set of the target
+pub fn link(objects: Vec<ObjectFile>, target: &Target) -> Result<Vec<RawInstructions>, ()> {
+    let instruction_set = &target.instruction_set;
+    let mut errors = check_cells(&objects, instruction_set);
+    if !errors.is_empty() {
+        // the addresses of the objects can not be put together
+        for err in errors {
+            error!("{}", err);
+        }
+        return Err(());
+    }
+    let code_origin = code_origin(&objects, target, &mut errors);
+    let code_length: u64 = objects
+        .iter()
+        .map(|object| instruction_set.address(object.raw_instructions.len()))
+        .sum();
+    let ram_length: u64 = objects.iter().map(|object| object.ram_size).sum();
+    let ram_start = target.memory.ram_start(code_origin + code_length);
+    errors.extend(check_memory(
+        code_origin..code_origin + code_length,
+        ram_start..ram_start + ram_length,
+        target,
+    ));
+    errors.extend(check_fixed_variables(
+        &objects,
+        code_origin..code_origin + code_length,
+        ram_start..ram_start + ram_length,
+        target,
+    ));
+    let mut code_bases: Vec<u64> = vec![];
+    let mut ram_bases: Vec<u64> = vec![];
+    let mut code_index = code_origin;
+    let mut ram_index = ram_start;
+    for object in &objects {
+        code_bases.push(code_index);
+        ram_bases.push(ram_index);
+        code_index += instruction_set.address(object.raw_instructions.len());
+        ram_index += object.ram_size;
+    }
+
+    let mut labels: HashMap<String, u64> = HashMap::new();
+    let mut variables: HashMap<String, u64> = HashMap::new();
+    for (object_num, object) in objects.iter().enumerate() {
+        for symbol in &object.symbols {
+            let (table, address) = match symbol.kind {
+                SymbolKind::Label => (&mut labels, code_bases[object_num] + symbol.value),
+                SymbolKind::Variable => (&mut variables, ram_bases[object_num] + symbol.value),
+                SymbolKind::Fixed => (&mut variables, symbol.value),
+            };
+            if table.insert(symbol.name.clone(), address).is_some() {
+                errors.push(LinkError {
+                    type_of_error: LinkErrorType::DuplicateSymbol {
+                        name: symbol.name.clone(),
+                    },
+                    object_num: Some(object_num),
+                });
+            }
+        }
+    }
+
+    let mut raw_instructions: Vec<RawInstructions> = vec![];
+    for (object_num, mut object) in objects.into_iter().enumerate() {
+        for relocation in &object.relocations {
+            let address = match &relocation.kind {
+                RelocationKind::Absolute => continue,
+                RelocationKind::Code => Some(code_bases[object_num]),
+                RelocationKind::Ram => Some(ram_bases[object_num]),
+                RelocationKind::Label(name) => labels.get(name).copied().or_else(|| {
+                    errors.push(LinkError {
+                        type_of_error: LinkErrorType::LabelDoesntExist { name: name.clone() },
+                        object_num: Some(object_num),
+                    });
+                    None
+                }),
+                RelocationKind::Variable(name) => variables.get(name).copied().or_else(|| {
+                    errors.push(LinkError {
+                        type_of_error: LinkErrorType::VariableDoesntExist { name: name.clone() },
+                        object_num: Some(object_num),
+                    });
+                    None
+                }),
+            };
+            let Some(address) = address else {
+                continue;
+            };
+            let operand = object
+                .raw_instructions
+                .get_mut(relocation.instruction)
+                .and_then(|instruction| instruction.operand_mut(relocation.operand));
+            let Some(operand) = operand else {
+                errors.push(LinkError {
+                    type_of_error: LinkErrorType::InvalidRelocation {
+                        instruction: relocation.instruction,
+                    },
+                    object_num: Some(object_num),
+                });
+                continue;
+            };
+            // code and ram relocations are relative, symbols replace the placeholder
+            match relocation.kind {
+                RelocationKind::Code | RelocationKind::Ram => *operand += address,
+                _ => *operand = address,
+            }
+        }
+        raw_instructions.append(&mut object.raw_instructions);
+    }
+    if !errors.is_empty() {
+        for err in errors {
+            error!("{}", err);
+        }
+        return Err(());
+    }
+    Ok(raw_instructions)
+}
+
+/// an error for every object that was compiled for other cells than the instruction set has
+fn check_cells(objects: &[ObjectFile], instruction_set: &InstructionSet) -> Vec<LinkError> {
+    let cells = CellLayout::of(instruction_set);
+    objects
+        .iter()
+        .enumerate()
+        .map(|(object_num, object)| (object_num, CellLayout::of(&object.instruction_set)))
+        .filter(|(_, object_cells)| *object_cells != cells)
+        .map(|(object_num, object_cells)| LinkError {
+            type_of_error: LinkErrorType::InstructionSetMismatch {
+                object: object_cells,
+                target: cells.clone(),
+            },
+            object_num: Some(object_num),
+        })
+        .collect()
+}
+
+/// where the code starts, ORG in the first object or the code origin of the target
+fn code_origin(objects: &[ObjectFile], target: &Target, errors: &mut Vec<LinkError>) -> u64 {
+    for (object_num, object) in objects.iter().enumerate().skip(1) {
+        if object.origin.is_some() {
+            errors.push(LinkError {
+                type_of_error: LinkErrorType::MisplacedOrigin,
+                object_num: Some(object_num),
+            });
+        }
+    }
+    let origin = objects.first().and_then(|object| object.origin);
+    match (origin, target.memory.code_origin) {
+        (Some(origin), Some(code_origin)) if origin != code_origin => {
+            errors.push(LinkError {
+                type_of_error: LinkErrorType::OriginMismatch {
+                    origin,
+                    code_origin,
+                },
+                object_num: Some(0),
+            });
+            origin
+        }
+        (origin, code_origin) => origin.or(code_origin).unwrap_or(0),
+    }
+}
+
+/// the variables have to fit in the RAM, the code can not be in the RAM
+/// and every address has to fit in a word
+fn check_memory(code: Range<u64>, ram: Range<u64>, target: &Target) -> Vec<LinkError> {
+    let mut errors = vec![];
+    let mut error = |type_of_error: LinkErrorType| {
+        errors.push(LinkError {
+            type_of_error,
+            object_num: None,
+        })
+    };
+    let needed = ram.end - ram.start;
+    let ram = match target.memory.ram_size {
+        Some(available) => {
+            if needed > available {
+                error(LinkErrorType::OutOfRam { needed, available });
+            }
+            ram.start..ram.start + available.max(needed)
+        }
+        None => ram,
+    };
+    if code.start < ram.end && ram.start < code.end {
+        error(LinkErrorType::CodeOverlapsRam {
+            code: code.clone(),
+            ram: ram.clone(),
+        });
+    }
+    let end = code.end.max(ram.end);
+    if end > 0 && end - 1 > target.mask() {
+        error(LinkErrorType::OutOfMemory {
+            end,
+            last_address: target.mask(),
+        });
+    }
+    errors
+}
+
+/// variables with a fixed address can not be in the code or in the RAM of the other variables
+fn check_fixed_variables(
+    objects: &[ObjectFile],
+    code: Range<u64>,
+    ram: Range<u64>,
+    target: &Target,
+) -> Vec<LinkError> {
+    let mut errors = vec![];
+    for (object_num, object) in objects.iter().enumerate() {
+        for (name, address) in &object.fixed_variables {
+            let (name, address) = (name.clone(), *address);
+            let type_of_error = if code.contains(&address) {
+                LinkErrorType::FixedAddressInCode { name, address }
+            } else if ram.contains(&address) {
+                LinkErrorType::FixedAddressInRam { name, address }
+            } else if address > target.mask() {
+                LinkErrorType::OutOfMemory {
+                    end: address + 1,
+                    last_address: target.mask(),
+                }
+            } else {
+                continue;
+            };
+            errors.push(LinkError {
+                type_of_error,
+                object_num: Some(object_num),
+            });
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        code_gen::{
+            compile_object,
+            object::{object_to_string, parse_object},
+            raw_instructions_to_string, CompileOptions,
+        },
+        disassembler::disassemble_object,
+        emulator::Machine,
+        parsing::parse::parse_string,
+        target::MemoryMap,
+    };
+
+    fn object(program: &str) -> ObjectFile {
+        compile_object(
+            parse_string(program.to_owned()),
+            &[],
+            &CompileOptions::default(),
+        )
+        .unwrap()
+    }
+
+    fn target(memory: MemoryMap) -> Target {
+        Target {
+            memory,
+            ..Target::default()
+        }
+    }
+
+    const MATH: &str = "MODULE math\nDEF ?two 2\n:double\nEXPORT ?two\nEXPORT :double\nENDMODULE\n";
+
+    #[test]
+    fn objects_use_the_variables_other_objects_export() {
+        let main = "IMPORT math::two\nDEF ?one 1\nDEF ?sum 0\nADD ?two ?one ?sum\nEXIT ?sum\n";
+        // the code of math runs first and sets ?math::two before main reads it
+        let code = link(vec![object(MATH), object(main)], &Target::default()).unwrap();
+        assert_eq!(Machine::new(&code).run(1000), Ok(3));
+    }
+
+    #[test]
+    fn imports_have_to_be_exported_by_an_object() {
+        let link_main = |main: &str| link(vec![object(MATH), object(main)], &Target::default());
+        assert!(link_main("IMPORT math::three\nEXIT ?three\n").is_err());
+        assert!(link_main("JMP :math::halve\n").is_err());
+        assert!(link_main("JMP :math::double\n").is_ok());
+    }
+
+    #[test]
+    fn code_starts_at_the_org_and_ram_at_the_ram_base() {
+        let program = "ORG 0x100\nDEF ?a 2\nEXIT ?a\n";
+        let target = target(MemoryMap {
+            ram_base: Some(0x8000),
+            ..MemoryMap::default()
+        });
+        let code = link(vec![object(program)], &target).unwrap();
+        assert_eq!(
+            code,
+            vec![
+                RawInstructions::ADD { a: 2, b: 0 },
+                RawInstructions::WRITE { a: 0x8000 },
+                RawInstructions::EXIT { a: 0x8000 },
+            ]
+        );
+        let mut machine = Machine::for_target(&code, 0x100, target);
+        assert_eq!(machine.run(100), Ok(2));
+    }
+
+    #[test]
+    fn programs_have_to_fit_in_memory() {
+        let program = "DEF ?a 2\nDEF ?b 3\nEXIT ?a\n";
+        let link_with = |memory: MemoryMap| link(vec![object(program)], &target(memory));
+        assert!(link_with(MemoryMap {
+            ram_size: Some(1),
+            ..MemoryMap::default()
+        })
+        .is_err());
+        // the code takes the addresses 0 to 19
+        assert!(link_with(MemoryMap {
+            ram_base: Some(16),
+            ..MemoryMap::default()
+        })
+        .is_err());
+        assert!(link_with(MemoryMap {
+            code_origin: Some(0x100),
+            ram_base: Some(0),
+            ram_size: Some(2),
+        })
+        .is_ok());
+        assert!(link(vec![object(program), object("ORG 4\n")], &Target::default()).is_err());
+    }
+
+    #[test]
+    fn fixed_variables_keep_their_address() {
+        let program = "DEF ?status @0x4000\nEXIT ?status\n";
+        let code = link(vec![object(program)], &Target::default()).unwrap();
+        assert_eq!(code, vec![RawInstructions::EXIT { a: 0x4000 }]);
+        let overlapping = target(MemoryMap {
+            ram_base: Some(0x4000),
+            ..MemoryMap::default()
+        });
+        let program = "DEF ?status @0x4000\nDEF ?a 1\nEXIT ?status\n";
+        assert!(link(vec![object(program)], &overlapping).is_err());
+        assert!(link(
+            vec![object("DEF ?status @2\nEXIT ?status\n")],
+            &Target::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn objects_only_link_with_the_cells_they_were_compiled_for() {
+        let five_cells = Target {
+            instruction_set: crate::target::parse_instruction_set("cells 5\nopcode 4\n").unwrap(),
+            ..Target::default()
+        };
+        let options = CompileOptions {
+            target: five_cells.clone(),
+            ..CompileOptions::default()
+        };
+        let program = parse_string("DEF ?a 2\n:end\nEXIT ?a\n".to_owned());
+        let compiled = compile_object(program, &[], &options).unwrap();
+        let written = object_to_string(&compiled);
+        assert!(written.contains("\ncells 5\nopcode 4\noperands 1 2 3\n"));
+        let read = || parse_object(&written).unwrap();
+        assert_eq!(read().instruction_set, compiled.instruction_set);
+        assert!(link(vec![read()], &Target::default()).is_err());
+        assert!(link(vec![object(""), read()], &five_cells).is_err());
+        let code = link(vec![read()], &five_cells).unwrap();
+        assert_eq!(Machine::for_target(&code, 0, five_cells).run(100), Ok(2));
+    }
+
+    #[test]
+    fn objects_keep_the_opcodes_they_were_compiled_for() {
+        let renumbered = Target {
+            instruction_set: crate::target::parse_instruction_set("ADD 1\nWRITE 2\nEXIT 3\n")
+                .unwrap(),
+            ..Target::default()
+        };
+        let options = CompileOptions {
+            target: renumbered.clone(),
+            ..CompileOptions::default()
+        };
+        let program = parse_string("DEF ?a 2\nEXIT ?a\n".to_owned());
+        let written = object_to_string(&compile_object(program, &[], &options).unwrap());
+        assert!(written.contains("\ninstruction 1 2 0 0\ninstruction 2 0 0 0\n"));
+        let read = || parse_object(&written).unwrap();
+        assert_eq!(
+            disassemble_object(&read(), &renumbered.instruction_set),
+            "# ram 1\n     0: ADD 2 0\n     4: WRITE ram+0\n     8: EXIT ram+0\n"
+        );
+        // only the cells have to match, the program gets the opcodes of the target
+        let code = link(vec![read()], &renumbered).unwrap();
+        let cells = raw_instructions_to_string(code.clone(), &renumbered.instruction_set);
+        assert!(cells.starts_with("1\n2\n0\n0\n2\n12\n0\n0\n3\n12\n"));
+        assert_eq!(Machine::for_target(&code, 0, renumbered).run(100), Ok(2));
+        let code = link(vec![read()], &Target::default()).unwrap();
+        let cells = raw_instructions_to_string(code, &InstructionSet::default());
+        assert!(cells.starts_with("101\n2\n0\n0\n103\n12\n"));
+    }
+}
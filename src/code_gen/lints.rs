@@ -0,0 +1,277 @@
+use std::collections::HashSet;
+
+use super::{
+    cfg::ControlFlowGraph,
+    ir::{LabelKind, Operation, Program, Value, VariableId, VariableKind},
+    CodeGenerationWarningType,
+};
+
+/// finds variables that can be read before a DEF sets them, variables that are set but never read
+/// and labels that are never jumped to.
+/// returns the AST node every warning is about, sorted by node
+pub fn lint(program: &Program) -> Vec<(usize, CodeGenerationWarningType)> {
+    let mut warnings = uninitialized_reads(program);
+    warnings.extend(unread_variables(program));
+    warnings.extend(unused_labels(program));
+    warnings.sort_by_key(|(source, _)| *source);
+    warnings
+}
+
+/// variables other objects or devices can set, they always count as set
+fn shared_variables(program: &Program) -> Vec<bool> {
+    program
+        .variables
+        .iter()
+        .map(|kind| match kind {
+            VariableKind::External(_) | VariableKind::Fixed { .. } => true,
+            VariableKind::Named(name) => program.exports.contains(name),
+            VariableKind::Scratch => false,
+        })
+        .collect()
+}
+
+/// the variables the operation reads
+fn reads(operation: &Operation) -> Vec<VariableId> {
+    let mut reads: Vec<VariableId> = operation
+        .values()
+        .into_iter()
+        .filter_map(|value| match value {
+            Value::Variable(variable) => Some(variable),
+            Value::Immediate(_) => None,
+        })
+        .collect();
+    match operation {
+        Operation::Copy { from, .. } => reads.push(*from),
+        Operation::Exit { code } => reads.push(*code),
+        _ => {}
+    }
+    reads
+}
+
+/// the variable the operation sets
+fn write(operation: &Operation) -> Option<VariableId> {
+    match operation {
+        Operation::Arithmetic { destination, .. } | Operation::Yoink { destination, .. } => {
+            Some(*destination)
+        }
+        Operation::Copy { to, .. } => Some(*to),
+        _ => None,
+    }
+}
+
+/// which variables are set on every path to the start of every block, none if the block can never run
+fn set_variables(program: &Program) -> Vec<Option<Vec<bool>>> {
+    let graph = ControlFlowGraph::new(program);
+    let mut states: Vec<Option<Vec<bool>>> = vec![None; program.blocks.len()];
+    for entry in &graph.entries {
+        // other objects can jump to exported labels after setting anything
+        states[*entry] = Some(match entry {
+            0 => shared_variables(program),
+            _ => vec![true; program.variables.len()],
+        });
+    }
+    let mut todo = graph.entries.clone();
+    while let Some(index) = todo.pop() {
+        let mut state = states[index].clone().unwrap();
+        for instruction in &program.blocks[index].instructions {
+            if let Some(variable) = write(&instruction.operation) {
+                state[variable.0] = true;
+            }
+        }
+        for successor in &graph.successors[index] {
+            let merged: Vec<bool> = match &states[*successor] {
+                None => state.clone(),
+                Some(old) => old
+                    .iter()
+                    .zip(&state)
+                    .map(|(old, new)| *old && *new)
+                    .collect(),
+            };
+            if states[*successor].as_ref() != Some(&merged) {
+                states[*successor] = Some(merged);
+                todo.push(*successor);
+            }
+        }
+    }
+    states
+}
+
+fn uninitialized_reads(program: &Program) -> Vec<(usize, CodeGenerationWarningType)> {
+    let mut warned: HashSet<(usize, VariableId)> = HashSet::new();
+    let mut warnings = vec![];
+    for (block, state) in program.blocks.iter().zip(set_variables(program)) {
+        let Some(mut state) = state else {
+            continue;
+        };
+        for instruction in &block.instructions {
+            for variable in reads(&instruction.operation) {
+                let VariableKind::Named(name) = &program.variables[variable.0] else {
+                    continue;
+                };
+                if !state[variable.0] && warned.insert((instruction.source, variable)) {
+                    warnings.push((
+                        instruction.source,
+                        CodeGenerationWarningType::UninitializedVariable { name: name.clone() },
+                    ));
+                }
+            }
+            if let Some(variable) = write(&instruction.operation) {
+                state[variable.0] = true;
+            }
+        }
+    }
+    warnings
+}
+
+fn unread_variables(program: &Program) -> Vec<(usize, CodeGenerationWarningType)> {
+    let instructions = program.blocks.iter().flat_map(|block| &block.instructions);
+    // YEET sends the value at an address, if the address is a variable it can point at any variable
+    if instructions.clone().any(|instruction| {
+        matches!(
+            instruction.operation,
+            Operation::Yeet {
+                address: Value::Variable(_),
+                ..
+            }
+        )
+    }) {
+        return vec![];
+    }
+    let mut read = vec![false; program.variables.len()];
+    let mut first_write: Vec<Option<usize>> = vec![None; program.variables.len()];
+    for instruction in instructions {
+        for variable in reads(&instruction.operation) {
+            read[variable.0] = true;
+        }
+        if let Some(variable) = write(&instruction.operation) {
+            first_write[variable.0].get_or_insert(instruction.source);
+        }
+    }
+    program
+        .variables
+        .iter()
+        .enumerate()
+        .filter_map(|(index, kind)| match (kind, first_write[index]) {
+            (VariableKind::Named(name), Some(source))
+                if !read[index] && !program.exports.contains(name) =>
+            {
+                Some((
+                    source,
+                    CodeGenerationWarningType::UnreadVariable { name: name.clone() },
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// labels made by IF, WHILE and numeric labels have an `@` in their name and are left out
+fn unused_labels(program: &Program) -> Vec<(usize, CodeGenerationWarningType)> {
+    let mut targets = HashSet::new();
+    for instruction in program.blocks.iter().flat_map(|block| &block.instructions) {
+        if let Operation::Branch { target, .. } | Operation::Jump { target } = instruction.operation
+        {
+            targets.insert(target);
+        }
+    }
+    program
+        .blocks
+        .iter()
+        .flat_map(|block| &block.labels)
+        .filter_map(|label| match &program.labels[label.0] {
+            LabelKind::Named(name)
+                if !targets.contains(label)
+                    && !name.contains('@')
+                    && !program.exports.contains(name) =>
+            {
+                let source = *program.label_definitions.get(label)?;
+                Some((
+                    source,
+                    CodeGenerationWarningType::UnusedLabel { name: name.clone() },
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        code_gen::{dead_code::remove_unreachable_blocks, lower::lower},
+        parsing::parse::parse_string,
+        target::Target,
+    };
+
+    fn lints(program: &str) -> Vec<(usize, CodeGenerationWarningType)> {
+        let mut errors = vec![];
+        let mut program = lower(
+            &parse_string(program.to_owned()),
+            vec![],
+            &[],
+            &Target::default(),
+            &mut errors,
+        );
+        assert!(errors.is_empty());
+        remove_unreachable_blocks(&mut program);
+        lint(&program)
+    }
+
+    #[test]
+    fn warns_about_reads_before_a_def_on_some_path() {
+        let warnings = lints("DEF ?a 0\nJZ ?a :skip\nDEF ?b 1\n:skip\nEXIT ?b\n");
+        assert_eq!(
+            warnings,
+            vec![(
+                4,
+                CodeGenerationWarningType::UninitializedVariable {
+                    name: "b".to_owned()
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn a_def_on_every_path_is_fine() {
+        let warnings =
+            lints("DEF ?a 0\nJZ ?a :zero\nDEF ?b 1\nJMP :end\n:zero\nDEF ?b 2\n:end\nEXIT ?b\n");
+        assert_eq!(warnings, vec![]);
+    }
+
+    #[test]
+    fn warns_about_unread_variables_and_unused_labels() {
+        let warnings = lints("DEF ?a 0\nDEF ?unused 1\n:start\nEXIT ?a\n");
+        assert_eq!(
+            warnings,
+            vec![
+                (
+                    1,
+                    CodeGenerationWarningType::UnreadVariable {
+                        name: "unused".to_owned()
+                    }
+                ),
+                (
+                    2,
+                    CodeGenerationWarningType::UnusedLabel {
+                        name: "start".to_owned()
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshot_programs_have_no_warnings() {
+        for program in [
+            include_str!("../../tests/snapshots/arithmetic.ass"),
+            include_str!("../../tests/snapshots/blocks.ass"),
+            include_str!("../../tests/snapshots/branches.ass"),
+            include_str!("../../tests/snapshots/copy_and_yeet.ass"),
+            include_str!("../../tests/snapshots/yoink.ass"),
+            // bitwise is left out, it only exits with one of its results
+        ] {
+            assert_eq!(lints(program), vec![], "{}", program);
+        }
+    }
+}
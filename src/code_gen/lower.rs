@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+
+use crate::{
+    parsing::{
+        ast::{Address, AssASTTypes, Label},
+        blocks::is_generated_label,
+    },
+    target::Target,
+};
+
+use super::{
+    bitwise::{fits_bitwise, lower_bitwise, BitwiseOperation, SCRATCH_SIZE},
+    find_external_symbols,
+    ir::{
+        Arithmetic, Condition, LabelId, LabelKind, Operation, Program, Value, VariableId,
+        VariableKind,
+    },
+    CodeGenerationError, CodeGenerationErrorType,
+};
+
+/// names in scope while lowering
+struct Lowering<'a> {
+    program: Program,
+    variables: HashMap<String, VariableId>,
+    /// labels defined so far, a later defenition replaces an earlier one
+    labels: HashMap<String, LabelId>,
+    /// the first defenition of every label so jumps can go forwards
+    forward_labels: HashMap<String, LabelId>,
+    /// the label every label defenition places by AST node
+    definitions: HashMap<usize, LabelId>,
+    /// variables only the compiler uses, made the first time they are needed
+    scratch: Vec<VariableId>,
+    /// instructions the defines from the command line take, they are not code for ORG
+    define_instructions: usize,
+    target: &'a Target,
+    errors: &'a mut Vec<CodeGenerationError>,
+}
+
+/// turns the AST into symbolic code, variables and labels that do not exist are errors.
+/// the modules have to be resolved already, the defines are set before the first AST node
+pub fn lower(
+    parsed_types: &[AssASTTypes],
+    exports: Vec<String>,
+    defines: &[(String, u64)],
+    target: &Target,
+    errors: &mut Vec<CodeGenerationError>,
+) -> Program {
+    let mut lowering = Lowering {
+        program: Program {
+            exports,
+            ..Program::default()
+        },
+        variables: HashMap::new(),
+        labels: HashMap::new(),
+        forward_labels: HashMap::new(),
+        definitions: HashMap::new(),
+        scratch: vec![],
+        define_instructions: 0,
+        target,
+        errors,
+    };
+    let (external_variables, external_labels) = find_external_symbols(parsed_types);
+    for name in external_variables {
+        let variable = lowering
+            .program
+            .new_variable(VariableKind::External(name.clone()));
+        lowering.variables.insert(name, variable);
+    }
+    for name in external_labels {
+        let label = lowering
+            .program
+            .new_label(LabelKind::External(name.clone()));
+        lowering.forward_labels.insert(name, label);
+    }
+    for (source, parsed_type) in parsed_types.iter().enumerate() {
+        if let AssASTTypes::LabelDefenition(label) = parsed_type {
+            let id = lowering
+                .program
+                .new_label(LabelKind::Named(label.0.clone()));
+            lowering.definitions.insert(source, id);
+            lowering.program.label_definitions.insert(id, source);
+            lowering.forward_labels.entry(label.0.clone()).or_insert(id);
+        }
+    }
+    for (name, value) in defines {
+        let define = AssASTTypes::Define {
+            name: Label(name.clone()),
+            a: *value,
+        };
+        lowering.lower(0, &define);
+    }
+    lowering.define_instructions = lowering.instruction_count();
+    for (source, parsed_type) in parsed_types.iter().enumerate() {
+        lowering.lower(source, parsed_type);
+    }
+    lowering.program
+}
+
+impl Lowering<'_> {
+    fn instruction_count(&self) -> usize {
+        self.program
+            .blocks
+            .iter()
+            .map(|block| block.instructions.len())
+            .sum()
+    }
+
+    fn lower(&mut self, source: usize, parsed_type: &AssASTTypes) {
+        match parsed_type {
+            AssASTTypes::Define { name, a } => {
+                let variable = match self.variables.get(&name.0) {
+                    Some(variable) => *variable,
+                    None => {
+                        let variable = self
+                            .program
+                            .new_variable(VariableKind::Named(name.0.clone()));
+                        self.variables.insert(name.0.clone(), variable);
+                        variable
+                    }
+                };
+                self.program.push(
+                    Operation::Arithmetic {
+                        arithmetic: Arithmetic::Add,
+                        a: Value::Immediate(*a),
+                        b: Value::Immediate(0),
+                        destination: variable,
+                    },
+                    source,
+                );
+            }
+            AssASTTypes::DefineAt { name, address } => {
+                if self.variables.contains_key(&name.0) {
+                    self.errors.push(CodeGenerationError {
+                        instruction_num: source,
+                        type_of_error: CodeGenerationErrorType::VariableAlreadyHasAnAddress {
+                            name: name.0.clone(),
+                        },
+                    });
+                    return;
+                }
+                let variable = self.program.new_variable(VariableKind::Fixed {
+                    name: name.0.clone(),
+                    address: *address,
+                });
+                self.variables.insert(name.0.clone(), variable);
+            }
+            AssASTTypes::Add { a, b, c }
+            | AssASTTypes::Subtract { a, b, c }
+            | AssASTTypes::Multiply { a, b, c }
+            | AssASTTypes::Divide { a, b, c } => {
+                let (Some(a), Some(b), Some(c)) = (
+                    self.variable(a, source),
+                    self.variable(b, source),
+                    self.variable(c, source),
+                ) else {
+                    return;
+                };
+                let arithmetic = match parsed_type {
+                    AssASTTypes::Add { .. } => Arithmetic::Add,
+                    AssASTTypes::Subtract { .. } => Arithmetic::Sub,
+                    AssASTTypes::Multiply { .. } => Arithmetic::Mul,
+                    _ => Arithmetic::Div,
+                };
+                self.program.push(
+                    Operation::Arithmetic {
+                        arithmetic,
+                        a: Value::Variable(a),
+                        b: Value::Variable(b),
+                        destination: c,
+                    },
+                    source,
+                );
+            }
+            AssASTTypes::Exit { code } => {
+                let Some(code) = self.variable(code, source) else {
+                    return;
+                };
+                self.program.push(Operation::Exit { code }, source);
+            }
+            AssASTTypes::LabelDefenition(label) => {
+                let id = self.definitions[&source];
+                self.labels.insert(label.0.clone(), id);
+                self.program.place_label(id);
+            }
+            AssASTTypes::Jump { a } => {
+                let Some(target) = self.label(a, source) else {
+                    return;
+                };
+                if is_generated_label(&a.0) {
+                    self.program
+                        .push_generated(Operation::Jump { target }, source);
+                } else {
+                    self.program.push(Operation::Jump { target }, source);
+                }
+            }
+            AssASTTypes::Copy { a, b } => {
+                let (Some(from), Some(to)) = (self.variable(a, source), self.variable(b, source))
+                else {
+                    return;
+                };
+                self.program.push(Operation::Copy { from, to }, source);
+            }
+            AssASTTypes::Equal { a, b, c }
+            | AssASTTypes::NotEqual { a, b, c }
+            | AssASTTypes::JumpIfHigherThan { a, b, c }
+            | AssASTTypes::JumpIfLowerThan { a, b, c } => {
+                let (Some(a), Some(b), Some(target)) = (
+                    self.variable(a, source),
+                    self.variable(b, source),
+                    self.label(c, source),
+                ) else {
+                    return;
+                };
+                let (condition, a, b) = match parsed_type {
+                    AssASTTypes::Equal { .. } => (Condition::Equal, a, b),
+                    AssASTTypes::NotEqual { .. } => (Condition::NotEqual, a, b),
+                    AssASTTypes::JumpIfHigherThan { .. } => (Condition::HigherThan, a, b),
+                    // a < b is b > a
+                    _ => (Condition::HigherThan, b, a),
+                };
+                self.program.push(
+                    Operation::Branch {
+                        condition,
+                        a: Value::Variable(a),
+                        b: Value::Variable(b),
+                        target,
+                    },
+                    source,
+                );
+            }
+            // a >= b is skipping the jump if b > a
+            // and a <= b is skipping the jump if a > b
+            AssASTTypes::JumpIfHigherOrEqual { a, b, c }
+            | AssASTTypes::JumpIfLowerOrEqual { a, b, c } => {
+                let (Some(a), Some(b), Some(target)) = (
+                    self.variable(a, source),
+                    self.variable(b, source),
+                    self.label(c, source),
+                ) else {
+                    return;
+                };
+                let (higher, lower) = match parsed_type {
+                    AssASTTypes::JumpIfHigherOrEqual { .. } => (b, a),
+                    _ => (a, b),
+                };
+                let skip = self.program.new_label(LabelKind::Internal);
+                self.program.push(
+                    Operation::Branch {
+                        condition: Condition::HigherThan,
+                        a: Value::Variable(higher),
+                        b: Value::Variable(lower),
+                        target: skip,
+                    },
+                    source,
+                );
+                self.program.push(Operation::Jump { target }, source);
+                self.program.place_label(skip);
+            }
+            // compares against a 0 that is never patched
+            AssASTTypes::JumpIfZero { a, b } | AssASTTypes::JumpIfNotZero { a, b } => {
+                let (Some(a), Some(target)) = (self.variable(a, source), self.label(b, source))
+                else {
+                    return;
+                };
+                let condition = match parsed_type {
+                    AssASTTypes::JumpIfZero { .. } => Condition::Equal,
+                    _ => Condition::NotEqual,
+                };
+                self.program.push(
+                    Operation::Branch {
+                        condition,
+                        a: Value::Variable(a),
+                        b: Value::Immediate(0),
+                        target,
+                    },
+                    source,
+                );
+            }
+            AssASTTypes::Yeet { a, b, c } => {
+                let (Some(a), Some(b), Some(c)) = (
+                    self.variable(a, source),
+                    self.variable(b, source),
+                    self.variable(c, source),
+                ) else {
+                    return;
+                };
+                self.program.push(
+                    Operation::Yeet {
+                        device: Value::Variable(a),
+                        instruction: Value::Variable(b),
+                        address: Value::Variable(c),
+                    },
+                    source,
+                );
+            }
+            AssASTTypes::Yoink { a, b, c } => {
+                let (Some(a), Some(b), Some(c)) = (
+                    self.variable(a, source),
+                    self.variable(b, source),
+                    self.variable(c, source),
+                ) else {
+                    return;
+                };
+                self.program.push(
+                    Operation::Yoink {
+                        device: Value::Variable(a),
+                        instruction: Value::Variable(b),
+                        destination: c,
+                    },
+                    source,
+                );
+            }
+            AssASTTypes::And { a, b, c }
+            | AssASTTypes::Or { a, b, c }
+            | AssASTTypes::Xor { a, b, c }
+            | AssASTTypes::ShiftLeft { a, b, c }
+            | AssASTTypes::ShiftRight { a, b, c }
+            | AssASTTypes::Modulo { a, b, c } => {
+                let (Some(a), Some(b), Some(c)) = (
+                    self.variable(a, source),
+                    self.variable(b, source),
+                    self.variable(c, source),
+                ) else {
+                    return;
+                };
+                let operation = match parsed_type {
+                    AssASTTypes::And { .. } => BitwiseOperation::And,
+                    AssASTTypes::Or { .. } => BitwiseOperation::Or,
+                    AssASTTypes::Xor { .. } => BitwiseOperation::Xor,
+                    AssASTTypes::ShiftLeft { .. } => BitwiseOperation::ShiftLeft,
+                    AssASTTypes::ShiftRight { .. } => BitwiseOperation::ShiftRight,
+                    _ => BitwiseOperation::Modulo,
+                };
+                if operation != BitwiseOperation::Modulo && !fits_bitwise(self.target) {
+                    self.errors.push(CodeGenerationError {
+                        instruction_num: source,
+                        type_of_error: CodeGenerationErrorType::WordTooSmallForBitwise,
+                    });
+                    return;
+                }
+                if self.scratch.is_empty() {
+                    self.scratch = (0..SCRATCH_SIZE)
+                        .map(|_| self.program.new_variable(VariableKind::Scratch))
+                        .collect();
+                }
+                lower_bitwise(
+                    &mut self.program,
+                    self.target,
+                    source,
+                    operation,
+                    a,
+                    Some(b),
+                    c,
+                    &self.scratch,
+                );
+            }
+            AssASTTypes::Not { a, b } => {
+                let (Some(a), Some(b)) = (self.variable(a, source), self.variable(b, source))
+                else {
+                    return;
+                };
+                lower_bitwise(
+                    &mut self.program,
+                    self.target,
+                    source,
+                    BitwiseOperation::Not,
+                    a,
+                    None,
+                    b,
+                    &self.scratch,
+                );
+            }
+            AssASTTypes::Origin(address) => {
+                let has_code = self.instruction_count() > self.define_instructions;
+                if self.program.origin.is_some() || has_code {
+                    self.errors.push(CodeGenerationError {
+                        instruction_num: source,
+                        type_of_error: CodeGenerationErrorType::MisplacedOrigin,
+                    });
+                    return;
+                }
+                self.program.origin = Some(*address);
+            }
+            // handled by resolve_modules
+            AssASTTypes::ModuleStart(_)
+            | AssASTTypes::ModuleEnd
+            | AssASTTypes::Export(_)
+            | AssASTTypes::Import(_) => {}
+        }
+    }
+
+    fn variable(&mut self, name: &Address, source: usize) -> Option<VariableId> {
+        let variable = self.variables.get(&name.0).copied();
+        if variable.is_none() {
+            self.errors.push(CodeGenerationError {
+                instruction_num: source,
+                type_of_error: CodeGenerationErrorType::VariableDoesntExist {
+                    name: name.0.to_owned(),
+                },
+            });
+        }
+        variable
+    }
+
+    fn label(&mut self, name: &Label, source: usize) -> Option<LabelId> {
+        let label = self
+            .labels
+            .get(&name.0)
+            .or_else(|| self.forward_labels.get(&name.0))
+            .copied();
+        if label.is_none() {
+            self.errors.push(CodeGenerationError {
+                instruction_num: source,
+                type_of_error: CodeGenerationErrorType::LabelDoesntExist {
+                    name: name.0.to_owned(),
+                },
+            });
+        }
+        label
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::parse::parse_string;
+
+    fn lower_program(program: &str) -> (Program, Vec<CodeGenerationError>) {
+        let mut errors = vec![];
+        let program = lower(
+            &parse_string(program.to_owned()),
+            vec![],
+            &[],
+            &Target::default(),
+            &mut errors,
+        );
+        (program, errors)
+    }
+
+    #[test]
+    fn splits_blocks_at_labels_and_jumps() {
+        let (program, errors) =
+            lower_program("DEF ?a 1\n:loop\nJZ ?a :end\nJMP :loop\n:end\nEXIT ?a\n");
+        assert!(errors.is_empty());
+        let lengths: Vec<usize> = program
+            .blocks
+            .iter()
+            .map(|block| block.instructions.len())
+            .collect();
+        assert_eq!(lengths, vec![1, 1, 1, 1]);
+        assert_eq!(
+            program.blocks[1].labels,
+            vec![program.named_label("loop").unwrap()]
+        );
+        assert_eq!(
+            program.blocks[2].instructions[0].operation,
+            Operation::Jump {
+                target: program.named_label("loop").unwrap()
+            }
+        );
+        assert!(!program.blocks[2].falls_through());
+        assert_eq!(
+            program.blocks[3].labels,
+            vec![program.named_label("end").unwrap()]
+        );
+    }
+
+    #[test]
+    fn jumps_go_to_the_last_label_before_them_or_the_first_after() {
+        let (program, _) = lower_program("JMP :a\n:a\n:a\nJMP :a\n");
+        let targets: Vec<Operation> = program
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .map(|instruction| instruction.operation.clone())
+            .collect();
+        assert_eq!(
+            targets,
+            vec![
+                Operation::Jump { target: LabelId(0) },
+                Operation::Jump { target: LabelId(1) },
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_symbols_are_errors() {
+        let (_, errors) = lower_program("DEF ?a 1\nJE ?a ?b :nowhere\nCPY ?c ?a\n");
+        let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "error at instruction 1: variable b does not exist",
+                "error at instruction 1: label nowhere does not exist",
+                "error at instruction 2: variable c does not exist",
+            ]
+        );
+    }
+}
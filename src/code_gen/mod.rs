@@ -1,13 +1,42 @@
 #![allow(unused_doc_comments, dead_code)]
 
-use std::{collections::HashMap, fmt::Display};
+use std::{collections::HashSet, fmt::Display, ops::RangeInclusive};
 
-use log::error;
+use log::{error, warn};
 
-use crate::parsing::ast::AssASTTypes;
-struct Variable {
-    ram_index: u64,
-}
+use crate::{
+    parsing::ast::AssASTTypes,
+    target::{InstructionSet, Target},
+};
+
+use self::{
+    constants::fold_constants,
+    dead_code::remove_unreachable_blocks,
+    debug_info::DebugInfo,
+    ir::{Arithmetic, Program},
+    layout::layout,
+    link::link,
+    lints::lint,
+    lower::lower,
+    modules::resolve_modules,
+    object::ObjectFile,
+    optimize::optimize,
+    ranges::check_ranges,
+};
+mod bitwise;
+pub mod cfg;
+mod constants;
+mod dead_code;
+pub mod debug_info;
+pub mod ir;
+mod layout;
+pub mod link;
+mod lints;
+mod lower;
+mod modules;
+pub mod object;
+pub mod optimize;
+mod ranges;
 #[derive(Debug)]
 pub struct CodeGenerationError {
     pub type_of_error: CodeGenerationErrorType,
@@ -23,6 +52,39 @@ impl Display for CodeGenerationError {
             CodeGenerationErrorType::LabelDoesntExist { name } => {
                 format!("label {} does not exist", name.clone())
             }
+            CodeGenerationErrorType::PrivateSymbol { name } => {
+                format!(
+                    "{} is private to its module, EXPORT it to use it here",
+                    name
+                )
+            }
+            CodeGenerationErrorType::ShadowedSymbol { name } => {
+                format!("importing {} shadows a symbol with the same name", name)
+            }
+            CodeGenerationErrorType::ExportOutsideOfModule { name } => {
+                format!("cannot export {} outside of a MODULE", name)
+            }
+            CodeGenerationErrorType::VariableAndLabelWithSameName { name } => {
+                format!("{} is both a variable and a label", name)
+            }
+            CodeGenerationErrorType::VariableAlreadyHasAnAddress { name } => {
+                format!(
+                    "variable {} already has an address, DEF it with @ before anything else",
+                    name
+                )
+            }
+            CodeGenerationErrorType::MisplacedOrigin => {
+                "ORG can only be used once, before any code".to_owned()
+            }
+            CodeGenerationErrorType::WordTooSmallForBitwise => {
+                "AND, OR, XOR, SHL and SHR need words that can hold 2".to_owned()
+            }
+            CodeGenerationErrorType::TooManyOperands { needed, available } => {
+                format!(
+                    "this needs instructions with {} operands but the instruction set only has cells for {}",
+                    needed, available
+                )
+            }
         };
         write!(
             f,
@@ -31,453 +93,318 @@ impl Display for CodeGenerationError {
         )
     }
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum CodeGenerationErrorType {
     VariableDoesntExist { name: String },
     LabelDoesntExist { name: String },
+    PrivateSymbol { name: String },
+    ShadowedSymbol { name: String },
+    ExportOutsideOfModule { name: String },
+    VariableAndLabelWithSameName { name: String },
+    VariableAlreadyHasAnAddress { name: String },
+    MisplacedOrigin,
+    WordTooSmallForBitwise,
+    TooManyOperands { needed: usize, available: usize },
+}
+#[derive(Debug)]
+pub struct CodeGenerationWarning {
+    pub type_of_warning: CodeGenerationWarningType,
+    /// the first and last AST node the warning is about
+    pub instructions: RangeInclusive<usize>,
+    /// the source lines of those nodes, when they are known
+    pub lines: Option<RangeInclusive<usize>>,
 }
-pub fn generate_code(parsed_types: Vec<AssASTTypes>) -> Result<Vec<RawInstructions>,()> {
-    let mut already_existing_variables_registers: HashMap<String, Variable> = HashMap::new();
-    let mut already_existing_labels: HashMap<String, u64> = HashMap::new();
-    let mut errors: Vec<CodeGenerationError> = vec![];
-    let mut ram_registry_index: u64 = calculate_variable_offset(&parsed_types);
-    let mut raw_instructions: Vec<RawInstructions> = vec![];
-    parsed_types
-        .iter()
-        .enumerate()
-        .for_each(|(line, ast_type)| match ast_type {
-            /// LENGTH = 2
-            AssASTTypes::Define { name, a } => {
-                let var_index =
-                    if let Some(varible) = already_existing_variables_registers.get(&name.0) {
-                        varible.ram_index
-                    } else {
-                        ram_registry_index += 1;
-                        ram_registry_index - 1
-                    };
-                already_existing_variables_registers.insert(
-                    name.0.clone(),
-                    Variable {
-                        ram_index: var_index,
-                    },
-                );
-                raw_instructions.push(RawInstructions::ADD { a: *a, b: 0 });
-                raw_instructions.push(RawInstructions::WRITE { a: var_index });
-            }
-            /// LENGTH = 4
-            AssASTTypes::Add { a, b, c } => {
-                let a_var = already_existing_variables_registers.get_key_value(&a.0);
-                let b_var = already_existing_variables_registers.get_key_value(&b.0);
-                let c_var = already_existing_variables_registers.get_key_value(&c.0);
-                let mut errored = false;
-                check_for_errors_with_3_vars(a_var, &mut errors, line, a, &mut errored, b_var, b, c_var, c);
-                if errored {
-                    return;
-                }
-                let a = a_var.unwrap();
-                let b = b_var.unwrap();
-                let c = c_var.unwrap();
-                raw_instructions.push(RawInstructions::COPY {
-                    a: a.1.ram_index,
-                    b: ((raw_instructions.len() + 2) * 4 + 1) as u64,
-                });
-                raw_instructions.push(RawInstructions::COPY {
-                    a: b.1.ram_index,
-                    b: ((raw_instructions.len() + 1) * 4 + 2) as u64,
-                });
-                raw_instructions.push(RawInstructions::ADD {
-                    a: a.1.ram_index,
-                    b: b.1.ram_index,
-                });
-                raw_instructions.push(RawInstructions::WRITE { a: c.1.ram_index });
-            }
-            /// LENGTH = 4
-            AssASTTypes::Subtract { a, b, c } => {
-                let a_var = already_existing_variables_registers.get_key_value(&a.0);
-                let b_var = already_existing_variables_registers.get_key_value(&b.0);
-                let c_var = already_existing_variables_registers.get_key_value(&c.0);
-                let mut errored = false;
-                check_for_errors_with_3_vars(a_var, &mut errors, line, a, &mut errored, b_var, b, c_var, c);
-                if errored {
-                    return;
-                }
-                let a = a_var.unwrap();
-                let b = b_var.unwrap();
-                let c = c_var.unwrap();
 
-                raw_instructions.push(RawInstructions::COPY {
-                    a: a.1.ram_index,
-                    b: ((raw_instructions.len() + 2) * 4 + 1) as u64,
-                });
-                raw_instructions.push(RawInstructions::COPY {
-                    a: b.1.ram_index,
-                    b: ((raw_instructions.len() + 1) * 4 + 2) as u64,
-                });
-                raw_instructions.push(RawInstructions::SUB {
-                    a: a.1.ram_index,
-                    b: b.1.ram_index,
-                });
-                raw_instructions.push(RawInstructions::WRITE { a: c.1.ram_index });
-            }
-            /// LENGTH = 4
-            AssASTTypes::Multiply { a, b, c } => {
-                let a_var = already_existing_variables_registers.get_key_value(&a.0);
-                let b_var = already_existing_variables_registers.get_key_value(&b.0);
-                let c_var = already_existing_variables_registers.get_key_value(&c.0);
-                let mut errored = false;
-                check_for_errors_with_3_vars(a_var, &mut errors, line, a, &mut errored, b_var, b, c_var, c);
-                if errored {
-                    return;
-                }
-                let a = a_var.unwrap();
-                let b = b_var.unwrap();
-                let c = c_var.unwrap();
-                raw_instructions.push(RawInstructions::COPY {
-                    a: a.1.ram_index,
-                    b: ((raw_instructions.len() + 2) * 4 + 1) as u64,
-                });
-                raw_instructions.push(RawInstructions::COPY {
-                    a: b.1.ram_index,
-                    b: ((raw_instructions.len() + 1) * 4 + 2) as u64,
-                });
-                raw_instructions.push(RawInstructions::MUL {
-                    a: a.1.ram_index,
-                    b: b.1.ram_index,
-                });
-                raw_instructions.push(RawInstructions::WRITE { a: c.1.ram_index });
-            }
-            /// len = 1
-            AssASTTypes::Exit { code } => {
-                let a_var = already_existing_variables_registers.get_key_value(&code.0);
-                if a_var.is_none() {
-                    errors.push(CodeGenerationError {
-                        instruction_num: line,
-                        type_of_error: CodeGenerationErrorType::VariableDoesntExist {
-                            name: code.0.to_owned(),
-                        },
-                    });
-                    return;
-                }
-                let a = a_var.unwrap();
-                raw_instructions.push(RawInstructions::EXIT { a: a.1.ram_index })
-            }
-            /// len = 0
-            AssASTTypes::LabelDefenition(label) => {
-                already_existing_labels.insert(
-                    label.0.to_owned(),
-                    ((raw_instructions.len() + 1) * 4) as u64,
-                );
-            }
-            // len = 1
-            AssASTTypes::Jump { a } => {
-                let a_var = already_existing_labels.get_key_value(&a.0);
-                if a_var.is_none() {
-                    errors.push(CodeGenerationError {
-                        instruction_num: line,
-                        type_of_error: CodeGenerationErrorType::LabelDoesntExist {
-                            name: a.0.to_owned(),
-                        },
-                    });
-                    return;
-                }
-                let (_, a) = a_var.unwrap();
-                raw_instructions.push(RawInstructions::JMP { a: *a })
-            }
-            // len = 1
-            AssASTTypes::Copy { a, b } => {
-                let a_var = already_existing_variables_registers.get_key_value(&a.0);
-                let b_var = already_existing_variables_registers.get_key_value(&b.0);
-                if !(a_var.is_some() && b_var.is_some()) {
-                    if a_var.is_none() {
-                        errors.push(CodeGenerationError {
-                            instruction_num: line,
-                            type_of_error: CodeGenerationErrorType::VariableDoesntExist {
-                                name: a.0.to_owned(),
-                            },
-                        });
-                    }
-                    if b_var.is_none() {
-                        errors.push(CodeGenerationError {
-                            instruction_num: line,
-                            type_of_error: CodeGenerationErrorType::VariableDoesntExist {
-                                name: b.0.to_owned(),
-                            },
-                        });
-                    }
-                }
-                let a = a_var.unwrap();
-                let b = b_var.unwrap();
+impl CodeGenerationWarning {
+    /// `ast_lines` has the source line of every AST node, it can be empty
+    fn new(
+        type_of_warning: CodeGenerationWarningType,
+        instructions: RangeInclusive<usize>,
+        ast_lines: &[usize],
+    ) -> CodeGenerationWarning {
+        let lines = match (
+            ast_lines.get(*instructions.start()),
+            ast_lines.get(*instructions.end()),
+        ) {
+            (Some(start), Some(end)) => Some(*start..=*end),
+            _ => None,
+        };
+        CodeGenerationWarning {
+            type_of_warning,
+            instructions,
+            lines,
+        }
+    }
+}
 
-                raw_instructions.push(RawInstructions::COPY {
-                    a: a.1.ram_index,
-                    b: b.1.ram_index,
-                })
+impl Display for CodeGenerationWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match &self.type_of_warning {
+            CodeGenerationWarningType::UnreachableCode => {
+                "this code can never run, it was removed".to_owned()
             }
-            // len = 1
-            AssASTTypes::NotEqual { a, b, c } => {
-                let a_var = already_existing_variables_registers.get_key_value(&a.0);
-                let b_var = already_existing_variables_registers.get_key_value(&b.0);
-                let c_var = already_existing_labels.get_key_value(&c.0);
-                if !(a_var.is_some() && b_var.is_some() && c_var.is_some()) {
-                    if a_var.is_none() {
-                        errors.push(CodeGenerationError {
-                            instruction_num: line,
-                            type_of_error: CodeGenerationErrorType::VariableDoesntExist {
-                                name: a.0.to_owned(),
-                            },
-                        });
-                    }
-                    if b_var.is_none() {
-                        errors.push(CodeGenerationError {
-                            instruction_num: line,
-                            type_of_error: CodeGenerationErrorType::VariableDoesntExist {
-                                name: b.0.to_owned(),
-                            },
-                        });
-                    }
-                    if c_var.is_none() {
-                        errors.push(CodeGenerationError {
-                            instruction_num: line,
-                            type_of_error: CodeGenerationErrorType::LabelDoesntExist {
-                                name: c.0.to_owned(),
-                            },
-                        });
-                    }
-                }
-                let (_, a) = a_var.unwrap();
-                let (_, b) = b_var.unwrap();
-                let (_, c) = c_var.unwrap();
-                raw_instructions.push(RawInstructions::COPY {
-                    a: a.ram_index,
-                    b: ((raw_instructions.len() + 2) * 4 + 1) as u64,
-                });
-                raw_instructions.push(RawInstructions::COPY {
-                    a: b.ram_index,
-                    b: ((raw_instructions.len() + 1) * 4 + 2) as u64,
-                });
-                raw_instructions.push(RawInstructions::NotEqual {
-                    a: a.ram_index,
-                    b: b.ram_index,
-                    c: *c,
-                })
+            CodeGenerationWarningType::UninitializedVariable { name } => {
+                format!("variable {} can be read before a DEF sets it", name)
             }
-            // len = 1
-            AssASTTypes::Equal { a, b, c } => {
-                let a_var = already_existing_variables_registers.get_key_value(&a.0);
-                let b_var = already_existing_variables_registers.get_key_value(&b.0);
-                let c_var = already_existing_labels.get_key_value(&c.0);
-                if !(a_var.is_some() && b_var.is_some() && c_var.is_some()) {
-                    if a_var.is_none() {
-                        errors.push(CodeGenerationError {
-                            instruction_num: line,
-                            type_of_error: CodeGenerationErrorType::VariableDoesntExist {
-                                name: a.0.to_owned(),
-                            },
-                        });
-                    }
-                    if b_var.is_none() {
-                        errors.push(CodeGenerationError {
-                            instruction_num: line,
-                            type_of_error: CodeGenerationErrorType::VariableDoesntExist {
-                                name: b.0.to_owned(),
-                            },
-                        });
-                    }
-                    if c_var.is_none() {
-                        errors.push(CodeGenerationError {
-                            instruction_num: line,
-                            type_of_error: CodeGenerationErrorType::LabelDoesntExist {
-                                name: c.0.to_owned(),
-                            },
-                        });
-                    }
-                }
-                let (_, a) = a_var.unwrap();
-                let (_, b) = b_var.unwrap();
-                let (_, c) = c_var.unwrap();
-                raw_instructions.push(RawInstructions::COPY {
-                    a: a.ram_index,
-                    b: ((raw_instructions.len() + 2) * 4 + 1) as u64,
-                });
-                raw_instructions.push(RawInstructions::COPY {
-                    a: b.ram_index,
-                    b: ((raw_instructions.len() + 1) * 4 + 2) as u64,
-                });
-                raw_instructions.push(RawInstructions::Equal {
-                    a: a.ram_index,
-                    b: b.ram_index,
-                    c: *c,
-                })
+            CodeGenerationWarningType::UnreadVariable { name } => {
+                format!("variable {} is set but never read", name)
             }
-            AssASTTypes::Divide { a, b, c } => {
-                let a_var = already_existing_variables_registers.get_key_value(&a.0);
-                let b_var = already_existing_variables_registers.get_key_value(&b.0);
-                let c_var = already_existing_variables_registers.get_key_value(&c.0);
-                let mut errored = false;
-                check_for_errors_with_3_vars(a_var, &mut errors, line, a, &mut errored, b_var, b, c_var, c);
-                if errored {
-                    return;
-                }
-                let a = a_var.unwrap();
-                let b = b_var.unwrap();
-                let c = c_var.unwrap();
-                raw_instructions.push(RawInstructions::COPY {
-                    a: a.1.ram_index,
-                    b: ((raw_instructions.len() + 2) * 4 + 1) as u64,
-                });
-                raw_instructions.push(RawInstructions::COPY {
-                    a: b.1.ram_index,
-                    b: ((raw_instructions.len() + 1) * 4 + 2) as u64,
-                });
-                raw_instructions.push(RawInstructions::DIV {
-                    a: a.1.ram_index,
-                    b: b.1.ram_index,
-                });
-                raw_instructions.push(RawInstructions::WRITE { a: c.1.ram_index });
+            CodeGenerationWarningType::UnusedLabel { name } => {
+                format!("label {} is never jumped to", name)
             }
-            AssASTTypes::Yeet { a, b, c } => {
-                let a_var = already_existing_variables_registers.get_key_value(&a.0);
-                let b_var = already_existing_variables_registers.get_key_value(&b.0);
-                let c_var = already_existing_variables_registers.get_key_value(&c.0);
-                let mut errored = false;
-                check_for_errors_with_3_vars(a_var, &mut errors, line, a, &mut errored, b_var, b, c_var, c);
-                if errored {
-                    return;
-                }
-                let a = a_var.unwrap();
-                let b = b_var.unwrap();
-                let c = c_var.unwrap();
-                raw_instructions.push(RawInstructions::COPY {
-                    a: a.1.ram_index,
-                    b: ((raw_instructions.len() + 2) * 4 + 1) as u64,
-                });
-                raw_instructions.push(RawInstructions::COPY {
-                    a: b.1.ram_index,
-                    b: ((raw_instructions.len() + 1) * 4 + 2) as u64,
-                });
-                raw_instructions.push(RawInstructions::COPY {
-                    a: c.1.ram_index,
-                    b: ((raw_instructions.len() + 1) * 4 + 3) as u64,
-                });
-                raw_instructions.push(RawInstructions::YEET {
-                    a: a.1.ram_index,
-                    b: b.1.ram_index,
-                    c: c.1.ram_index,
-                });
+            CodeGenerationWarningType::DivisionByZero { always: true } => {
+                "this always divides by 0".to_owned()
             }
-            AssASTTypes::JumpIfHigherThan { a, b, c } => {
-                let a_var = already_existing_variables_registers.get_key_value(&a.0);
-                let b_var = already_existing_variables_registers.get_key_value(&b.0);
-                let c_var = already_existing_labels.get_key_value(&c.0);
-                if !(a_var.is_some() && b_var.is_some() && c_var.is_some()) {
-                    if a_var.is_none() {
-                        errors.push(CodeGenerationError {
-                            instruction_num: line,
-                            type_of_error: CodeGenerationErrorType::VariableDoesntExist {
-                                name: a.0.to_owned(),
-                            },
-                        });
-                    }
-                    if b_var.is_none() {
-                        errors.push(CodeGenerationError {
-                            instruction_num: line,
-                            type_of_error: CodeGenerationErrorType::VariableDoesntExist {
-                                name: b.0.to_owned(),
-                            },
-                        });
-                    }
-                    if c_var.is_none() {
-                        errors.push(CodeGenerationError {
-                            instruction_num: line,
-                            type_of_error: CodeGenerationErrorType::LabelDoesntExist {
-                                name: c.0.to_owned(),
-                            },
-                        });
-                    }
-                }
-                let (_, a) = a_var.unwrap();
-                let (_, b) = b_var.unwrap();
-                let (_, c) = c_var.unwrap();
-                raw_instructions.push(RawInstructions::COPY {
-                    a: a.ram_index,
-                    b: ((raw_instructions.len() + 2) * 4 + 1) as u64,
-                });
-                raw_instructions.push(RawInstructions::COPY {
-                    a: b.ram_index,
-                    b: ((raw_instructions.len() + 1) * 4 + 2) as u64,
-                });
-                raw_instructions.push(RawInstructions::JHT {
-                    a: a.ram_index,
-                    b: b.ram_index,
-                    c: *c,
-                })
+            CodeGenerationWarningType::DivisionByZero { always: false } => {
+                "the divisor can be 0".to_owned()
             }
-        });
+            CodeGenerationWarningType::Overflow { arithmetic } => {
+                let name = match arithmetic {
+                    Arithmetic::Add => "ADD",
+                    Arithmetic::Sub => "SUB",
+                    Arithmetic::Mul => "MUL",
+                    Arithmetic::Div => "DIV",
+                };
+                format!("this {} can go past the word size", name)
+            }
+        };
+        let (what, range) = match &self.lines {
+            Some(lines) => ("line", lines),
+            None => ("instruction", &self.instructions),
+        };
+        if range.start() == range.end() {
+            write!(f, "warning at {} {}: {}", what, range.start(), message)
+        } else {
+            write!(
+                f,
+                "warning at {}s {} to {}: {}",
+                what,
+                range.start(),
+                range.end(),
+                message
+            )
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodeGenerationWarningType {
+    UnreachableCode,
+    UninitializedVariable {
+        name: String,
+    },
+    UnreadVariable {
+        name: String,
+    },
+    UnusedLabel {
+        name: String,
+    },
+    /// always is true when the divisor can only be 0
+    DivisionByZero {
+        always: bool,
+    },
+    Overflow {
+        arithmetic: Arithmetic,
+    },
+}
+/// how the compiler should compile
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// 0 leaves the code as written, 1 puts values that are always the same into the code
+    /// and folds them, 2 also removes instructions that do nothing
+    pub opt_level: u8,
+    /// the words and instructions of the machine
+    pub target: Target,
+    /// variables set before the program starts, like a DEF at the top of it
+    pub defines: Vec<(String, u64)>,
+}
+/// generates the code of a program that does not need anything from other objects
+pub fn generate_code(parsed_types: Vec<AssASTTypes>) -> Result<Vec<RawInstructions>, ()> {
+    let options = CompileOptions::default();
+    link(
+        vec![compile_object(parsed_types, &[], &options)?],
+        &options.target,
+    )
+}
+/// like generate_code but also says where every instruction and symbol came from.
+/// `ast_lines` has the source line of every AST node for the warnings
+pub fn generate_code_with_debug_info(
+    parsed_types: Vec<AssASTTypes>,
+    ast_lines: &[usize],
+    options: &CompileOptions,
+) -> Result<(Vec<RawInstructions>, DebugInfo), ()> {
+    let (object, mut debug_info) =
+        compile_object_with_debug_info(parsed_types, ast_lines, options)?;
+    // like the linker puts a single object
+    let target = &options.target;
+    let code_origin = object.origin.or(target.memory.code_origin).unwrap_or(0);
+    let code_length = target
+        .instruction_set
+        .address(object.raw_instructions.len());
+    let ram_base = target.memory.ram_start(code_origin + code_length);
+    debug_info.code_origin = code_origin;
+    debug_info
+        .labels
+        .values_mut()
+        .for_each(|address| *address += code_origin);
+    debug_info
+        .variables
+        .values_mut()
+        .for_each(|address| *address += ram_base);
+    debug_info
+        .variables
+        .extend(object.fixed_variables.iter().cloned());
+    Ok((link(vec![object], target)?, debug_info))
+}
+/// generates code that still has to be linked.
+/// variables and labels with a module name that this file does not define are left for the linker
+pub fn compile_object(
+    parsed_types: Vec<AssASTTypes>,
+    ast_lines: &[usize],
+    options: &CompileOptions,
+) -> Result<ObjectFile, ()> {
+    Ok(compile_object_with_debug_info(parsed_types, ast_lines, options)?.0)
+}
+/// debug info addresses are relative to the object, like the addresses in it
+fn compile_object_with_debug_info(
+    parsed_types: Vec<AssASTTypes>,
+    ast_lines: &[usize],
+    options: &CompileOptions,
+) -> Result<(ObjectFile, DebugInfo), ()> {
+    let mut errors: Vec<CodeGenerationError> = vec![];
+    let (parsed_types, exported) = resolve_modules(parsed_types, &mut errors);
+    let mut exported: Vec<String> = exported.into_iter().collect();
+    exported.sort();
+    let mut program = lower(
+        &parsed_types,
+        exported,
+        &options.defines,
+        &options.target,
+        &mut errors,
+    );
+    errors.extend(check_operands(&program, &options.target.instruction_set));
     if !errors.is_empty() {
         for err in errors {
             error!("{}", err);
-            return Err(());
         }
+        return Err(());
     }
-    Ok(raw_instructions)
-}
-
-fn check_for_errors_with_3_vars(a_var: Option<(&String, &Variable)>, errors: &mut Vec<CodeGenerationError>, line: usize, a: &crate::parsing::ast::Address, errored: &mut bool, b_var: Option<(&String, &Variable)>, b: &crate::parsing::ast::Address, c_var: Option<(&String, &Variable)>, c: &crate::parsing::ast::Address) {
-    if a_var.is_none() {
-        errors.push(CodeGenerationError {
-            instruction_num: line,
-            type_of_error: CodeGenerationErrorType::VariableDoesntExist {
-                name: a.0.to_owned(),
-            },
-        });
-        *errored = true;
+    for removed in remove_unreachable_blocks(&mut program) {
+        let warning = CodeGenerationWarning::new(
+            CodeGenerationWarningType::UnreachableCode,
+            removed,
+            ast_lines,
+        );
+        warn!("{}", warning);
+    }
+    let mut warnings = lint(&program);
+    warnings.extend(check_ranges(&program, &options.target));
+    // defines from the command line do not have to be used
+    warnings.retain(|(_, type_of_warning)| match type_of_warning {
+        CodeGenerationWarningType::UnreadVariable { name } => {
+            !options.defines.iter().any(|(defined, _)| defined == name)
+        }
+        _ => true,
+    });
+    warnings.sort_by_key(|(source, _)| *source);
+    for (source, type_of_warning) in warnings {
+        let warning = CodeGenerationWarning::new(type_of_warning, source..=source, ast_lines);
+        warn!("{}", warning);
     }
-    if b_var.is_none() {
-        errors.push(CodeGenerationError {
-            instruction_num: line,
-            type_of_error: CodeGenerationErrorType::VariableDoesntExist {
-                name: b.0.to_owned(),
-            },
-        });
-        *errored = true;
+    if options.opt_level >= 1 {
+        fold_constants(&mut program, &options.target);
+        // only code that could never run in the source is warned about
+        remove_unreachable_blocks(&mut program);
     }
-    if c_var.is_none() {
-        errors.push(CodeGenerationError {
-            instruction_num: line,
-            type_of_error: CodeGenerationErrorType::VariableDoesntExist {
-                name: c.0.to_owned(),
-            },
-        });
-        *errored = true;
+    let (mut object, mut debug_info) = layout(&program, &options.target.instruction_set);
+    if options.opt_level >= 2 {
+        object = optimize(object, Some(&mut debug_info), &options.target);
     }
+    Ok((object, debug_info))
 }
 
-fn calculate_variable_offset(instructions: &[AssASTTypes]) -> u64 {
-    let mut calc = 0;
-    instructions
-        .iter()
-        .for_each(|parsed_type| match parsed_type {
-            AssASTTypes::Add { a: _, b: _, c: _ } => calc += 4,
-            AssASTTypes::Define { name: _, a: _ } => {
-                calc += 2;
+/// an error for every AST node that needs more operands than the instructions have cells for
+fn check_operands(program: &Program, instruction_set: &InstructionSet) -> Vec<CodeGenerationError> {
+    let available = instruction_set.operand_slots.len();
+    let mut errors: Vec<CodeGenerationError> = vec![];
+    for instruction in program.blocks.iter().flat_map(|block| &block.instructions) {
+        let needed = instruction.operation.operands();
+        let reported = errors
+            .last()
+            .is_some_and(|error| error.instruction_num == instruction.source);
+        if needed > available && !reported {
+            errors.push(CodeGenerationError {
+                instruction_num: instruction.source,
+                type_of_error: CodeGenerationErrorType::TooManyOperands { needed, available },
+            });
+        }
+    }
+    errors
+}
+/// variables and labels that are used with a module name but never defined in this file
+fn find_external_symbols(instructions: &[AssASTTypes]) -> (HashSet<String>, HashSet<String>) {
+    let mut defined: HashSet<&str> = HashSet::new();
+    for parsed_type in instructions {
+        match parsed_type {
+            AssASTTypes::Define { name, .. } | AssASTTypes::DefineAt { name, .. } => {
+                defined.insert(&name.0);
+            }
+            AssASTTypes::LabelDefenition(label) => {
+                defined.insert(&label.0);
             }
-            AssASTTypes::Exit { code: _ } => {
-                calc += 1;
+            _ => {}
+        }
+    }
+    let mut variables: HashSet<String> = HashSet::new();
+    let mut labels: HashSet<String> = HashSet::new();
+    let add = |set: &mut HashSet<String>, name: &String| {
+        if name.contains("::") && !defined.contains(name.as_str()) {
+            set.insert(name.clone());
+        }
+    };
+    for parsed_type in instructions {
+        match parsed_type {
+            AssASTTypes::Add { a, b, c }
+            | AssASTTypes::Subtract { a, b, c }
+            | AssASTTypes::Multiply { a, b, c }
+            | AssASTTypes::Divide { a, b, c }
+            | AssASTTypes::Yeet { a, b, c }
+            | AssASTTypes::Yoink { a, b, c }
+            | AssASTTypes::And { a, b, c }
+            | AssASTTypes::Or { a, b, c }
+            | AssASTTypes::Xor { a, b, c }
+            | AssASTTypes::ShiftLeft { a, b, c }
+            | AssASTTypes::ShiftRight { a, b, c }
+            | AssASTTypes::Modulo { a, b, c } => {
+                add(&mut variables, &a.0);
+                add(&mut variables, &b.0);
+                add(&mut variables, &c.0);
             }
-            AssASTTypes::Subtract { a: _, b: _, c: _ } => calc += 4,
-            AssASTTypes::Multiply { a: _, b: _, c: _ } => calc += 4,
-            AssASTTypes::LabelDefenition(_l) => {}
-            AssASTTypes::Jump { a: _ } => calc += 1,
-            AssASTTypes::Copy { a: _, b: _ } => calc += 1,
-            AssASTTypes::NotEqual { a: _, b: _, c: _ } => calc += 3,
-            AssASTTypes::Equal { a: _, b: _, c: _ } => calc += 3,
-            AssASTTypes::Divide { a: _, b: _, c: _ } => calc += 4,
-            AssASTTypes::Yeet { a: _, b: _, c: _ } => calc += 4,
-            AssASTTypes::JumpIfHigherThan { a: _, b: _, c: _ } => calc += 3,
-        });
-    calc * 4
+            AssASTTypes::Equal { a, b, c }
+            | AssASTTypes::NotEqual { a, b, c }
+            | AssASTTypes::JumpIfHigherThan { a, b, c }
+            | AssASTTypes::JumpIfLowerThan { a, b, c }
+            | AssASTTypes::JumpIfHigherOrEqual { a, b, c }
+            | AssASTTypes::JumpIfLowerOrEqual { a, b, c } => {
+                add(&mut variables, &a.0);
+                add(&mut variables, &b.0);
+                add(&mut labels, &c.0);
+            }
+            AssASTTypes::Copy { a, b } | AssASTTypes::Not { a, b } => {
+                add(&mut variables, &a.0);
+                add(&mut variables, &b.0);
+            }
+            AssASTTypes::JumpIfZero { a, b } | AssASTTypes::JumpIfNotZero { a, b } => {
+                add(&mut variables, &a.0);
+                add(&mut labels, &b.0);
+            }
+            AssASTTypes::Exit { code } => add(&mut variables, &code.0),
+            AssASTTypes::Jump { a } => add(&mut labels, &a.0),
+            _ => {}
+        }
+    }
+    (variables, labels)
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum RawInstructions {
     ADD { a: u64, b: u64 },
@@ -491,126 +418,296 @@ pub enum RawInstructions {
     NotEqual { a: u64, b: u64, c: u64 },
     DIV { a: u64, b: u64 },
     YEET { a: u64, b: u64, c: u64 },
+    YOINK { a: u64, b: u64, c: u64 },
     JHT { a: u64, b: u64, c: u64 },
 }
-pub fn raw_instructions_to_string(instructions: Vec<RawInstructions>) -> String {
+impl RawInstructions {
+    /// the opcode the instruction has in the instruction set
+    pub fn opcode(&self, instruction_set: &InstructionSet) -> u64 {
+        instruction_set.opcodes[self.name()]
+    }
+    /// name of the variant, like in the Debug output
+    pub fn name(&self) -> &'static str {
+        match self {
+            RawInstructions::ADD { a: _, b: _ } => "ADD",
+            RawInstructions::COPY { a: _, b: _ } => "COPY",
+            RawInstructions::WRITE { a: _ } => "WRITE",
+            RawInstructions::JMP { a: _ } => "JMP",
+            RawInstructions::SUB { a: _, b: _ } => "SUB",
+            RawInstructions::MUL { a: _, b: _ } => "MUL",
+            RawInstructions::Equal { a: _, b: _, c: _ } => "Equal",
+            RawInstructions::NotEqual { a: _, b: _, c: _ } => "NotEqual",
+            RawInstructions::EXIT { a: _ } => "EXIT",
+            RawInstructions::JHT { a: _, b: _, c: _ } => "JHT",
+            RawInstructions::YEET { a: _, b: _, c: _ } => "YEET",
+            RawInstructions::YOINK { a: _, b: _, c: _ } => "YOINK",
+            RawInstructions::DIV { a: _, b: _ } => "DIV",
+        }
+    }
+    /// the a, b and c operand, unused operands are 0
+    pub fn operands(&self) -> [u64; 3] {
+        match *self {
+            RawInstructions::WRITE { a }
+            | RawInstructions::EXIT { a }
+            | RawInstructions::JMP { a } => [a, 0, 0],
+            RawInstructions::ADD { a, b }
+            | RawInstructions::COPY { a, b }
+            | RawInstructions::SUB { a, b }
+            | RawInstructions::MUL { a, b }
+            | RawInstructions::DIV { a, b } => [a, b, 0],
+            RawInstructions::Equal { a, b, c }
+            | RawInstructions::NotEqual { a, b, c }
+            | RawInstructions::YEET { a, b, c }
+            | RawInstructions::YOINK { a, b, c }
+            | RawInstructions::JHT { a, b, c } => [a, b, c],
+        }
+    }
+    /// operand 1, 2 or 3, none if the instruction does not use that operand
+    pub fn operand_mut(&mut self, operand: usize) -> Option<&mut u64> {
+        match (self, operand) {
+            (RawInstructions::WRITE { a }, 1)
+            | (RawInstructions::EXIT { a }, 1)
+            | (RawInstructions::JMP { a }, 1)
+            | (RawInstructions::ADD { a, b: _ }, 1)
+            | (RawInstructions::COPY { a, b: _ }, 1)
+            | (RawInstructions::SUB { a, b: _ }, 1)
+            | (RawInstructions::MUL { a, b: _ }, 1)
+            | (RawInstructions::DIV { a, b: _ }, 1)
+            | (RawInstructions::Equal { a, b: _, c: _ }, 1)
+            | (RawInstructions::NotEqual { a, b: _, c: _ }, 1)
+            | (RawInstructions::YEET { a, b: _, c: _ }, 1)
+            | (RawInstructions::YOINK { a, b: _, c: _ }, 1)
+            | (RawInstructions::JHT { a, b: _, c: _ }, 1) => Some(a),
+            (RawInstructions::ADD { a: _, b }, 2)
+            | (RawInstructions::COPY { a: _, b }, 2)
+            | (RawInstructions::SUB { a: _, b }, 2)
+            | (RawInstructions::MUL { a: _, b }, 2)
+            | (RawInstructions::DIV { a: _, b }, 2)
+            | (RawInstructions::Equal { a: _, b, c: _ }, 2)
+            | (RawInstructions::NotEqual { a: _, b, c: _ }, 2)
+            | (RawInstructions::YEET { a: _, b, c: _ }, 2)
+            | (RawInstructions::YOINK { a: _, b, c: _ }, 2)
+            | (RawInstructions::JHT { a: _, b, c: _ }, 2) => Some(b),
+            (RawInstructions::Equal { a: _, b: _, c }, 3)
+            | (RawInstructions::NotEqual { a: _, b: _, c }, 3)
+            | (RawInstructions::YEET { a: _, b: _, c }, 3)
+            | (RawInstructions::YOINK { a: _, b: _, c }, 3)
+            | (RawInstructions::JHT { a: _, b: _, c }, 3) => Some(c),
+            _ => None,
+        }
+    }
+    /// turns an opcode of the instruction set and its operands back into an instruction
+    pub fn from_operands(
+        opcode: u64,
+        [a, b, c]: [u64; 3],
+        instruction_set: &InstructionSet,
+    ) -> Option<RawInstructions> {
+        let (name, _) = instruction_set
+            .opcodes
+            .iter()
+            .find(|(_, used)| **used == opcode)?;
+        let instruction = match name.as_str() {
+            "ADD" => RawInstructions::ADD { a, b },
+            "COPY" => RawInstructions::COPY { a, b },
+            "WRITE" => RawInstructions::WRITE { a },
+            "JMP" => RawInstructions::JMP { a },
+            "SUB" => RawInstructions::SUB { a, b },
+            "MUL" => RawInstructions::MUL { a, b },
+            "Equal" => RawInstructions::Equal { a, b, c },
+            "NotEqual" => RawInstructions::NotEqual { a, b, c },
+            "EXIT" => RawInstructions::EXIT { a },
+            "JHT" => RawInstructions::JHT { a, b, c },
+            "YEET" => RawInstructions::YEET { a, b, c },
+            "DIV" => RawInstructions::DIV { a, b },
+            "YOINK" => RawInstructions::YOINK { a, b, c },
+            _ => return None,
+        };
+        Some(instruction)
+    }
+}
+/// the cells of the program, one number per line
+pub fn raw_instructions_to_string(
+    instructions: Vec<RawInstructions>,
+    instruction_set: &InstructionSet,
+) -> String {
     let mut string = String::new();
-    instructions
+    for cell in instructions
         .iter()
-        .for_each(|instruction| string.push_str(&raw_instruction_to_string(instruction)));
+        .flat_map(|instruction| instruction_set.encode(instruction))
+    {
+        string.push_str(&cell.to_string());
+        string.push('\n');
+    }
     string
 }
-pub fn raw_instruction_to_string(instruction: &RawInstructions) -> String {
-    let mut str = String::new();
-    match instruction {
-        RawInstructions::ADD { a, b } => {
-            str.push_str("101\n");
-            str.push_str(a.to_string().as_str());
-            str.push('\n');
-            str.push_str(b.to_string().as_str());
-            str.push('\n');
-            str.push_str("0\n");
-            str
-        }
-        RawInstructions::WRITE { a } => {
-            str.push_str("103\n");
-            str.push_str(a.to_string().as_str());
-            str.push('\n');
-            str.push_str("0\n");
-            str.push_str("0\n");
-            str
-        }
-        RawInstructions::COPY { a, b } => {
-            str.push_str("102\n");
-            str.push_str(a.to_string().as_str());
-            str.push('\n');
-            str.push_str(b.to_string().as_str());
-            str.push('\n');
-            str.push_str("0\n");
-            str
-        }
-        RawInstructions::EXIT { a } => {
-            str.push_str("109\n");
-            str.push_str(a.to_string().as_str());
-            str.push('\n');
-            str.push_str("0\n");
-            str.push_str("0\n");
-            str
-        }
-        RawInstructions::SUB { a, b } => {
-            str.push_str("105\n");
-            str.push_str(a.to_string().as_str());
-            str.push('\n');
-            str.push_str(b.to_string().as_str());
-            str.push('\n');
-            str.push_str("0\n");
-            str
-        }
-        RawInstructions::MUL { a, b } => {
-            str.push_str("106\n");
-            str.push_str(a.to_string().as_str());
-            str.push('\n');
-            str.push_str(b.to_string().as_str());
-            str.push('\n');
-            str.push_str("0\n");
-            str
-        }
-        RawInstructions::JMP { a } => {
-            str.push_str("104\n");
-            str.push_str(a.to_string().as_str());
-            str.push('\n');
-            str.push_str("0\n");
-            str.push_str("0\n");
-            str
-        }
-        RawInstructions::Equal { a, b, c } => {
-            str.push_str("107\n");
-            str.push_str(a.to_string().as_str());
-            str.push('\n');
-            str.push_str(b.to_string().as_str());
-            str.push('\n');
-            str.push_str(c.to_string().as_str());
-            str.push('\n');
-            str
-        }
-        RawInstructions::NotEqual { a, b, c } => {
-            str.push_str("108\n");
-            str.push_str(a.to_string().as_str());
-            str.push('\n');
-            str.push_str(b.to_string().as_str());
-            str.push('\n');
-            str.push_str(c.to_string().as_str());
-            str.push('\n');
-            str
-        }
-        RawInstructions::DIV { a, b } => {
-            str.push_str("112\n");
-            str.push_str(a.to_string().as_str());
-            str.push('\n');
-            str.push_str(b.to_string().as_str());
-            str.push('\n');
-            str.push_str("0\n");
-            str
-        }
-        RawInstructions::YEET { a, b, c } => {
-            str.push_str("111\n");
-            str.push_str(a.to_string().as_str());
-            str.push('\n');
-            str.push_str(b.to_string().as_str());
-            str.push('\n');
-            str.push_str(c.to_string().as_str());
-            str.push('\n');
-            str
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::parse::parse_string;
+
+    fn compile(program: &str) -> Vec<RawInstructions> {
+        generate_code(parse_string(program.to_owned())).unwrap()
+    }
+
+    #[test]
+    fn jump_if_lower_than_swaps_operands_of_jht() {
+        let code = compile("DEF ?a 1\nDEF ?b 2\n:l\nJLT ?a ?b :l\n");
+        // 7 instructions so ?a is at 28 and ?b at 29, :l points at instruction 4
+        assert_eq!(
+            code[4..],
+            [
+                RawInstructions::COPY { a: 29, b: 25 },
+                RawInstructions::COPY { a: 28, b: 26 },
+                RawInstructions::JHT {
+                    a: 29,
+                    b: 28,
+                    c: 20
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn jump_if_higher_or_equal_skips_jump_if_lower() {
+        let code = compile("DEF ?a 1\nDEF ?b 2\n:l\nJGE ?a ?b :l\n");
+        assert_eq!(
+            code[4..],
+            [
+                RawInstructions::COPY { a: 33, b: 25 },
+                RawInstructions::COPY { a: 32, b: 26 },
+                RawInstructions::JHT {
+                    a: 33,
+                    b: 32,
+                    c: 36
+                },
+                RawInstructions::JMP { a: 20 },
+            ]
+        );
+    }
+
+    #[test]
+    fn jump_if_lower_or_equal_skips_jump_if_higher() {
+        let code = compile("DEF ?a 1\nDEF ?b 2\n:l\nJLE ?a ?b :l\n");
+        assert_eq!(
+            code[4..],
+            [
+                RawInstructions::COPY { a: 32, b: 25 },
+                RawInstructions::COPY { a: 33, b: 26 },
+                RawInstructions::JHT {
+                    a: 32,
+                    b: 33,
+                    c: 36
+                },
+                RawInstructions::JMP { a: 20 },
+            ]
+        );
+    }
+
+    #[test]
+    fn jump_if_zero_compares_against_unpatched_zero() {
+        let code = compile("DEF ?a 1\n:l\nJZ ?a :l\nJNZ ?a :l\n");
+        assert_eq!(
+            code[2..],
+            [
+                RawInstructions::COPY { a: 24, b: 13 },
+                RawInstructions::Equal { a: 24, b: 0, c: 12 },
+                RawInstructions::COPY { a: 24, b: 21 },
+                RawInstructions::NotEqual { a: 24, b: 0, c: 12 },
+            ]
+        );
+    }
+
+    #[test]
+    fn new_branches_can_jump_forwards() {
+        let code = compile("DEF ?a 1\nJZ ?a :end\nJLT ?a ?a :end\n:end\nEXIT ?a\n");
+        assert_eq!(code[3], RawInstructions::Equal { a: 32, b: 0, c: 32 });
+        assert_eq!(
+            code[6],
+            RawInstructions::JHT {
+                a: 32,
+                b: 32,
+                c: 32
+            }
+        );
+    }
+
+    #[test]
+    fn defines_are_set_before_the_program_starts() {
+        let options = CompileOptions {
+            defines: vec![("answer".to_owned(), 42), ("unused".to_owned(), 1)],
+            ..CompileOptions::default()
+        };
+        let object = compile_object(
+            parse_string("ORG 8\nEXIT ?answer\n".to_owned()),
+            &[],
+            &options,
+        )
+        .unwrap();
+        let code = link(vec![object], &options.target).unwrap();
+        let mut machine = crate::emulator::Machine::for_target(&code, 8, options.target);
+        assert_eq!(machine.run(100), Ok(42));
+    }
+
+    #[test]
+    fn programs_run_the_same_on_other_instruction_sets() {
+        let instruction_set = crate::target::parse_instruction_set(
+            "cells 6\nopcode 5\noperands 3 0 1\nADD 1\nJMP 2\nEXIT 113\nYOINK 109\n",
+        )
+        .unwrap();
+        let run = |program: &str, target: Target, opt_level: u8| {
+            let options = CompileOptions {
+                opt_level,
+                target,
+                ..CompileOptions::default()
+            };
+            let object = compile_object(parse_string(program.to_owned()), &[], &options).unwrap();
+            let code = link(vec![object], &options.target).unwrap();
+            let mut machine = crate::emulator::Machine::for_target(&code, 0, options.target);
+            let exit_code = machine.run(100_000).unwrap();
+            (exit_code, machine.yeets)
+        };
+        for program in [
+            include_str!("../../tests/snapshots/arithmetic.ass"),
+            include_str!("../../tests/snapshots/bitwise.ass"),
+            include_str!("../../tests/snapshots/blocks.ass"),
+            include_str!("../../tests/snapshots/branches.ass"),
+            include_str!("../../tests/snapshots/modules.ass"),
+        ] {
+            let retargeted = Target {
+                instruction_set: instruction_set.clone(),
+                ..Target::default()
+            };
+            let expected = run(program, Target::default(), 0);
+            assert_eq!(run(program, retargeted.clone(), 0), expected);
+            assert_eq!(run(program, retargeted, 2), expected);
         }
-        RawInstructions::JHT { a, b, c } => {
-            str.push_str("110\n");
-            str.push_str(a.to_string().as_str());
-            str.push('\n');
-            str.push_str(b.to_string().as_str());
-            str.push('\n');
-            str.push_str(c.to_string().as_str());
-            str.push('\n');
-            str
+    }
+
+    #[test]
+    fn instructions_without_c_can_only_run_code_that_does_not_need_it() {
+        let target = Target {
+            instruction_set: crate::target::parse_instruction_set("cells 3\noperands 1 2\n")
+                .unwrap(),
+            ..Target::default()
+        };
+        let program = include_str!("../../tests/snapshots/arithmetic.ass");
+        for opt_level in [0, 2] {
+            let options = CompileOptions {
+                opt_level,
+                target: target.clone(),
+                ..CompileOptions::default()
+            };
+            let object = compile_object(parse_string(program.to_owned()), &[], &options).unwrap();
+            let code = link(vec![object], &target).unwrap();
+            let mut machine = crate::emulator::Machine::for_target(&code, 0, target.clone());
+            assert_eq!(machine.run(100_000), Ok(5));
         }
+        let options = CompileOptions {
+            target,
+            ..CompileOptions::default()
+        };
+        let branch = parse_string("DEF ?a 1\n:l\nJZ ?a :l\n".to_owned());
+        assert!(compile_object(branch, &[], &options).is_err());
     }
 }
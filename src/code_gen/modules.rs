@@ -0,0 +1,472 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::parsing::ast::{Address, AssASTTypes, Label};
+
+use super::{CodeGenerationError, CodeGenerationErrorType};
+
+/// the name of the module code outside of any MODULE belongs to
+const ROOT_MODULE: &str = "";
+
+#[derive(Default)]
+struct Module {
+    /// every variable defined in the module without its module name
+    variables: HashSet<String>,
+    /// every label defined in the module without its module name
+    labels: HashSet<String>,
+    exported: HashSet<String>,
+    /// short name to full name
+    imports: HashMap<String, String>,
+}
+
+impl Module {
+    fn defines(&self, name: &str) -> bool {
+        self.variables.contains(name) || self.labels.contains(name)
+    }
+}
+
+/// gives every variable and label its full `module::name` name
+///
+/// symbols defined inside a module are private unless they are exported.
+/// bare names are looked up in the current module first, then in the imports of
+/// the current module and last in the root module.
+/// names that cant be found are left alone so code gen can complain about them.
+/// also returns the full name of every exported symbol
+pub fn resolve_modules(
+    parsed_types: Vec<AssASTTypes>,
+    errors: &mut Vec<CodeGenerationError>,
+) -> (Vec<AssASTTypes>, HashSet<String>) {
+    let modules = collect_modules(&parsed_types, errors);
+    let mut current_module = ROOT_MODULE.to_owned();
+    let mut resolved: Vec<AssASTTypes> = vec![];
+    for (line, parsed_type) in parsed_types.into_iter().enumerate() {
+        let scope = current_module.clone();
+        let mut resolve = |name: String| resolve_name(&modules, &scope, name, line, errors);
+        let resolved_type = match parsed_type {
+            AssASTTypes::Define { name, a } => AssASTTypes::Define {
+                name: Label(full_name(&current_module, &name.0)),
+                a,
+            },
+            AssASTTypes::DefineAt { name, address } => AssASTTypes::DefineAt {
+                name: Label(full_name(&current_module, &name.0)),
+                address,
+            },
+            AssASTTypes::LabelDefenition(label) => {
+                AssASTTypes::LabelDefenition(Label(full_name(&current_module, &label.0)))
+            }
+            AssASTTypes::Add { a, b, c } => AssASTTypes::Add {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Address(resolve(c.0)),
+            },
+            AssASTTypes::Subtract { a, b, c } => AssASTTypes::Subtract {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Address(resolve(c.0)),
+            },
+            AssASTTypes::Multiply { a, b, c } => AssASTTypes::Multiply {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Address(resolve(c.0)),
+            },
+            AssASTTypes::Divide { a, b, c } => AssASTTypes::Divide {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Address(resolve(c.0)),
+            },
+            AssASTTypes::Yeet { a, b, c } => AssASTTypes::Yeet {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Address(resolve(c.0)),
+            },
+            AssASTTypes::Yoink { a, b, c } => AssASTTypes::Yoink {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Address(resolve(c.0)),
+            },
+            AssASTTypes::And { a, b, c } => AssASTTypes::And {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Address(resolve(c.0)),
+            },
+            AssASTTypes::Or { a, b, c } => AssASTTypes::Or {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Address(resolve(c.0)),
+            },
+            AssASTTypes::Xor { a, b, c } => AssASTTypes::Xor {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Address(resolve(c.0)),
+            },
+            AssASTTypes::ShiftLeft { a, b, c } => AssASTTypes::ShiftLeft {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Address(resolve(c.0)),
+            },
+            AssASTTypes::ShiftRight { a, b, c } => AssASTTypes::ShiftRight {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Address(resolve(c.0)),
+            },
+            AssASTTypes::Modulo { a, b, c } => AssASTTypes::Modulo {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Address(resolve(c.0)),
+            },
+            AssASTTypes::Not { a, b } => AssASTTypes::Not {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+            },
+            AssASTTypes::Equal { a, b, c } => AssASTTypes::Equal {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Label(resolve(c.0)),
+            },
+            AssASTTypes::NotEqual { a, b, c } => AssASTTypes::NotEqual {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Label(resolve(c.0)),
+            },
+            AssASTTypes::JumpIfHigherThan { a, b, c } => AssASTTypes::JumpIfHigherThan {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Label(resolve(c.0)),
+            },
+            AssASTTypes::JumpIfLowerThan { a, b, c } => AssASTTypes::JumpIfLowerThan {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Label(resolve(c.0)),
+            },
+            AssASTTypes::JumpIfHigherOrEqual { a, b, c } => AssASTTypes::JumpIfHigherOrEqual {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Label(resolve(c.0)),
+            },
+            AssASTTypes::JumpIfLowerOrEqual { a, b, c } => AssASTTypes::JumpIfLowerOrEqual {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+                c: Label(resolve(c.0)),
+            },
+            AssASTTypes::JumpIfZero { a, b } => AssASTTypes::JumpIfZero {
+                a: Address(resolve(a.0)),
+                b: Label(resolve(b.0)),
+            },
+            AssASTTypes::JumpIfNotZero { a, b } => AssASTTypes::JumpIfNotZero {
+                a: Address(resolve(a.0)),
+                b: Label(resolve(b.0)),
+            },
+            AssASTTypes::Copy { a, b } => AssASTTypes::Copy {
+                a: Address(resolve(a.0)),
+                b: Address(resolve(b.0)),
+            },
+            AssASTTypes::Jump { a } => AssASTTypes::Jump {
+                a: Label(resolve(a.0)),
+            },
+            AssASTTypes::Exit { code } => AssASTTypes::Exit {
+                code: Address(resolve(code.0)),
+            },
+            AssASTTypes::ModuleStart(name) => {
+                current_module = name.0.clone();
+                AssASTTypes::ModuleStart(name)
+            }
+            AssASTTypes::ModuleEnd => {
+                current_module = ROOT_MODULE.to_owned();
+                AssASTTypes::ModuleEnd
+            }
+            other => other,
+        };
+        resolved.push(resolved_type);
+    }
+    let exported = modules
+        .iter()
+        .flat_map(|(module_name, module)| {
+            module
+                .exported
+                .iter()
+                .map(|name| full_name(module_name, name))
+        })
+        .collect();
+    (resolved, exported)
+}
+
+/// first pass, finds what every module defines, exports and imports
+fn collect_modules(
+    parsed_types: &[AssASTTypes],
+    errors: &mut Vec<CodeGenerationError>,
+) -> HashMap<String, Module> {
+    let mut modules: HashMap<String, Module> = HashMap::new();
+    modules.insert(ROOT_MODULE.to_owned(), Module::default());
+    let mut current_module = ROOT_MODULE.to_owned();
+    for (line, parsed_type) in parsed_types.iter().enumerate() {
+        let module = modules.get_mut(&current_module).unwrap();
+        let (name, clashes) = match parsed_type {
+            AssASTTypes::ModuleStart(name) => {
+                current_module = name.0.clone();
+                modules.entry(current_module.clone()).or_default();
+                continue;
+            }
+            AssASTTypes::ModuleEnd => {
+                current_module = ROOT_MODULE.to_owned();
+                continue;
+            }
+            AssASTTypes::Define { name, .. } | AssASTTypes::DefineAt { name, .. } => {
+                module.variables.insert(name.0.clone());
+                (&name.0, module.labels.contains(&name.0))
+            }
+            AssASTTypes::LabelDefenition(label) => {
+                module.labels.insert(label.0.clone());
+                (&label.0, module.variables.contains(&label.0))
+            }
+            _ => continue,
+        };
+        // both would get the same full name, outside of modules names are not changed
+        if clashes && current_module != ROOT_MODULE {
+            errors.push(CodeGenerationError {
+                instruction_num: line,
+                type_of_error: CodeGenerationErrorType::VariableAndLabelWithSameName {
+                    name: full_name(&current_module, name),
+                },
+            });
+        }
+    }
+    current_module = ROOT_MODULE.to_owned();
+    for (line, parsed_type) in parsed_types.iter().enumerate() {
+        match parsed_type {
+            AssASTTypes::ModuleStart(name) => current_module = name.0.clone(),
+            AssASTTypes::ModuleEnd => current_module = ROOT_MODULE.to_owned(),
+            AssASTTypes::Export(name) => {
+                let name = strip_sigil(&name.0);
+                if current_module == ROOT_MODULE {
+                    errors.push(CodeGenerationError {
+                        instruction_num: line,
+                        type_of_error: CodeGenerationErrorType::ExportOutsideOfModule {
+                            name: name.to_owned(),
+                        },
+                    });
+                    continue;
+                }
+                let module = modules.get_mut(&current_module).unwrap();
+                if !module.defines(name) {
+                    errors.push(CodeGenerationError {
+                        instruction_num: line,
+                        type_of_error: CodeGenerationErrorType::VariableDoesntExist {
+                            name: full_name(&current_module, name),
+                        },
+                    });
+                    continue;
+                }
+                module.exported.insert(name.to_owned());
+            }
+            _ => {}
+        }
+    }
+    current_module = ROOT_MODULE.to_owned();
+    for (line, parsed_type) in parsed_types.iter().enumerate() {
+        match parsed_type {
+            AssASTTypes::ModuleStart(name) => current_module = name.0.clone(),
+            AssASTTypes::ModuleEnd => current_module = ROOT_MODULE.to_owned(),
+            AssASTTypes::Import(path) => {
+                let path = strip_sigil(&path.0);
+                let Some((module_name, name)) = path.rsplit_once("::") else {
+                    errors.push(CodeGenerationError {
+                        instruction_num: line,
+                        type_of_error: CodeGenerationErrorType::VariableDoesntExist {
+                            name: path.to_owned(),
+                        },
+                    });
+                    continue;
+                };
+                match modules.get(module_name) {
+                    // the module is in another file, the linker checks it
+                    None => {}
+                    Some(module) if module.exported.contains(name) => {}
+                    Some(module) if module.defines(name) => {
+                        errors.push(CodeGenerationError {
+                            instruction_num: line,
+                            type_of_error: CodeGenerationErrorType::PrivateSymbol {
+                                name: path.to_owned(),
+                            },
+                        });
+                        continue;
+                    }
+                    _ => {
+                        errors.push(CodeGenerationError {
+                            instruction_num: line,
+                            type_of_error: CodeGenerationErrorType::VariableDoesntExist {
+                                name: path.to_owned(),
+                            },
+                        });
+                        continue;
+                    }
+                }
+                let module = modules.get_mut(&current_module).unwrap();
+                let already_imported = module
+                    .imports
+                    .get(name)
+                    .is_some_and(|imported| imported != path);
+                if module.defines(name) || already_imported {
+                    errors.push(CodeGenerationError {
+                        instruction_num: line,
+                        type_of_error: CodeGenerationErrorType::ShadowedSymbol {
+                            name: path.to_owned(),
+                        },
+                    });
+                    continue;
+                }
+                module.imports.insert(name.to_owned(), path.to_owned());
+            }
+            _ => {}
+        }
+    }
+    modules
+}
+
+fn resolve_name(
+    modules: &HashMap<String, Module>,
+    current_module: &str,
+    name: String,
+    line: usize,
+    errors: &mut Vec<CodeGenerationError>,
+) -> String {
+    let module = &modules[current_module];
+    if let Some((module_name, symbol)) = name.rsplit_once("::") {
+        if module_name == current_module {
+            return name;
+        }
+        if let Some(other_module) = modules.get(module_name) {
+            if other_module.defines(symbol) && !other_module.exported.contains(symbol) {
+                errors.push(CodeGenerationError {
+                    instruction_num: line,
+                    type_of_error: CodeGenerationErrorType::PrivateSymbol { name: name.clone() },
+                });
+            }
+        }
+        return name;
+    }
+    if module.defines(&name) {
+        return full_name(current_module, &name);
+    }
+    if let Some(imported) = module.imports.get(&name) {
+        return imported.clone();
+    }
+    name
+}
+
+fn full_name(module: &str, name: &str) -> String {
+    if module == ROOT_MODULE {
+        return name.to_owned();
+    }
+    format!("{}::{}", module, name)
+}
+
+/// EXPORT and IMPORT accept names written like ?variable or :label
+fn strip_sigil(name: &str) -> &str {
+    name.strip_prefix(['?', ':']).unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::parse::parse_string;
+
+    const MATH: &str =
+        "MODULE math\nDEF ?two 2\nDEF ?secret 3\n:end\nEXPORT ?two\nEXPORT :end\nENDMODULE\n";
+
+    fn resolve(program: &str) -> (Vec<AssASTTypes>, Vec<CodeGenerationErrorType>) {
+        let mut errors = vec![];
+        let (resolved, _) = resolve_modules(parse_string(program.to_owned()), &mut errors);
+        let errors = errors.into_iter().map(|err| err.type_of_error).collect();
+        (resolved, errors)
+    }
+
+    #[test]
+    fn qualified_and_imported_names_resolve_in_every_operand() {
+        let (resolved, errors) = resolve(&format!(
+            "{}DEF ?z 2\nJE ?z ?math::two :math::end\nIMPORT math::two\nJNE ?two ?z :math::end\n",
+            MATH
+        ));
+        assert!(errors.is_empty());
+        let name = |name: &str| Address(name.to_owned());
+        assert_eq!(
+            resolved[resolved.len() - 3..],
+            [
+                AssASTTypes::Equal {
+                    a: name("z"),
+                    b: name("math::two"),
+                    c: Label("math::end".to_owned()),
+                },
+                AssASTTypes::Import(Label("math::two".to_owned())),
+                AssASTTypes::NotEqual {
+                    a: name("math::two"),
+                    b: name("z"),
+                    c: Label("math::end".to_owned()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn private_symbols_can_not_be_used_outside_their_module() {
+        let (_, errors) = resolve(&format!("{}EXIT ?math::secret\n", MATH));
+        assert_eq!(
+            errors,
+            vec![CodeGenerationErrorType::PrivateSymbol {
+                name: "math::secret".to_owned()
+            }]
+        );
+        let (_, errors) = resolve(&format!("{}IMPORT math::secret\n", MATH));
+        assert_eq!(
+            errors,
+            vec![CodeGenerationErrorType::PrivateSymbol {
+                name: "math::secret".to_owned()
+            }]
+        );
+    }
+
+    #[test]
+    fn imports_can_not_shadow_other_names() {
+        let (_, errors) = resolve(&format!("{}DEF ?two 1\nIMPORT math::two\n", MATH));
+        assert_eq!(
+            errors,
+            vec![CodeGenerationErrorType::ShadowedSymbol {
+                name: "math::two".to_owned()
+            }]
+        );
+    }
+
+    #[test]
+    fn exports_have_to_be_defined_in_a_module() {
+        let (_, errors) = resolve("DEF ?a 1\nEXPORT ?a\n");
+        assert_eq!(
+            errors,
+            vec![CodeGenerationErrorType::ExportOutsideOfModule {
+                name: "a".to_owned()
+            }]
+        );
+        let (_, errors) = resolve("MODULE math\nEXPORT ?missing\nENDMODULE\nIMPORT math::gone\n");
+        assert_eq!(
+            errors,
+            vec![
+                CodeGenerationErrorType::VariableDoesntExist {
+                    name: "math::missing".to_owned()
+                },
+                CodeGenerationErrorType::VariableDoesntExist {
+                    name: "math::gone".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn variables_and_labels_in_a_module_need_different_names() {
+        let (_, errors) = resolve("MODULE math\nDEF ?end 1\n:end\nEXIT ?end\nENDMODULE\n");
+        assert_eq!(
+            errors,
+            vec![CodeGenerationErrorType::VariableAndLabelWithSameName {
+                name: "math::end".to_owned()
+            }]
+        );
+        let (_, errors) = resolve("DEF ?end 1\n:end\nEXIT ?end\n");
+        assert!(errors.is_empty());
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt::{Display, Write};
+
+use log::error;
+
+use crate::target::InstructionSet;
+
+use super::RawInstructions;
+
+/// first line of every object file
+const OBJECT_HEADER: &str = "ass-object 3";
+
+/// code that has not been given its final addresses yet
+///
+/// code addresses are calculated as if the code starts at address 0
+/// and variables are numbered from 0, the relocations say which operands
+/// need to be moved once the linker knows where everything goes
+#[derive(Debug, Default)]
+pub struct ObjectFile {
+    pub raw_instructions: Vec<RawInstructions>,
+    pub relocations: Vec<Relocation>,
+    /// amount of variables this object needs room for
+    pub ram_size: u64,
+    /// exported symbols other objects can link against
+    pub symbols: Vec<Symbol>,
+    /// address the code has to start at, set with ORG
+    pub origin: Option<u64>,
+    /// name and address of every variable DEF gave a fixed address
+    pub fixed_variables: Vec<(String, u64)>,
+    /// the instruction set the object was compiled for, the code addresses depend on its cells
+    pub instruction_set: InstructionSet,
+}
+
+/// where the opcode and the operands are in the cells of an instruction.
+/// the opcodes are only needed to read an object, so this is all objects have to agree on
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellLayout {
+    pub cell_width: u64,
+    pub opcode_slot: u64,
+    pub operand_slots: Vec<u64>,
+}
+
+impl CellLayout {
+    pub fn of(instruction_set: &InstructionSet) -> CellLayout {
+        CellLayout {
+            cell_width: instruction_set.cell_width,
+            opcode_slot: instruction_set.opcode_slot,
+            operand_slots: instruction_set.operand_slots.clone(),
+        }
+    }
+}
+
+impl Display for CellLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operands: Vec<String> = self.operand_slots.iter().map(u64::to_string).collect();
+        write!(
+            f,
+            "{} cells with the opcode in cell {} and the operands in cells {}",
+            self.cell_width,
+            self.opcode_slot,
+            operands.join(" ")
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Relocation {
+    pub instruction: usize,
+    /// 1, 2 or 3 for the a, b and c operand
+    pub operand: usize,
+    pub kind: RelocationKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelocationKind {
+    /// the operand is not an address and stays as is
+    Absolute,
+    /// an address in the code of this object, moves with the code
+    Code,
+    /// a variable of this object, moves with the RAM of this object
+    Ram,
+    /// the address of a label from another object
+    Label(String),
+    /// the address of a variable from another object
+    Variable(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// address in the code for labels, number of the variable for variables,
+    /// the address for fixed variables
+    pub value: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Label,
+    Variable,
+    /// a variable with a fixed address, it does not move with the RAM
+    Fixed,
+}
+
+impl ObjectFile {
+    /// adds an instruction with the relocation of each of its operands
+    pub fn push(&mut self, instruction: RawInstructions, relocations: [RelocationKind; 3]) {
+        for (index, kind) in relocations.into_iter().enumerate() {
+            if kind == RelocationKind::Absolute {
+                continue;
+            }
+            self.relocations.push(Relocation {
+                instruction: self.raw_instructions.len(),
+                operand: index + 1,
+                kind,
+            });
+        }
+        self.raw_instructions.push(instruction);
+    }
+}
+
+pub fn object_to_string(object: &ObjectFile) -> String {
+    let mut string = String::new();
+    writeln!(string, "{}", OBJECT_HEADER).unwrap();
+    let instruction_set = &object.instruction_set;
+    writeln!(string, "cells {}", instruction_set.cell_width).unwrap();
+    writeln!(string, "opcode {}", instruction_set.opcode_slot).unwrap();
+    let operands: Vec<String> = instruction_set
+        .operand_slots
+        .iter()
+        .map(u64::to_string)
+        .collect();
+    writeln!(string, "operands {}", operands.join(" ")).unwrap();
+    let mut opcodes: Vec<(&String, &u64)> = instruction_set.opcodes.iter().collect();
+    opcodes.sort_by_key(|(_, opcode)| **opcode);
+    for (name, opcode) in opcodes {
+        writeln!(string, "opcode-of {} {}", name, opcode).unwrap();
+    }
+    writeln!(string, "ram {}", object.ram_size).unwrap();
+    if let Some(origin) = object.origin {
+        writeln!(string, "origin {}", origin).unwrap();
+    }
+    for (name, address) in &object.fixed_variables {
+        writeln!(string, "fixed {} {}", name, address).unwrap();
+    }
+    for instruction in &object.raw_instructions {
+        let [a, b, c] = instruction.operands();
+        writeln!(
+            string,
+            "instruction {} {} {} {}",
+            instruction.opcode(instruction_set),
+            a,
+            b,
+            c
+        )
+        .unwrap();
+    }
+    for relocation in &object.relocations {
+        let kind = match &relocation.kind {
+            RelocationKind::Absolute => continue,
+            RelocationKind::Code => "code".to_owned(),
+            RelocationKind::Ram => "ram".to_owned(),
+            RelocationKind::Label(name) => format!("label {}", name),
+            RelocationKind::Variable(name) => format!("variable {}", name),
+        };
+        writeln!(
+            string,
+            "relocation {} {} {}",
+            relocation.instruction, relocation.operand, kind
+        )
+        .unwrap();
+    }
+    for symbol in &object.symbols {
+        let kind = match symbol.kind {
+            SymbolKind::Label => "label",
+            SymbolKind::Variable => "variable",
+            SymbolKind::Fixed => "fixed",
+        };
+        writeln!(string, "symbol {} {} {}", kind, symbol.name, symbol.value).unwrap();
+    }
+    string
+}
+
+/// object files start with a header, programs with a number
+pub fn is_object_file(string: &str) -> bool {
+    string.lines().next() == Some(OBJECT_HEADER)
+}
+
+pub fn parse_object(string: &str) -> Result<ObjectFile, ()> {
+    let mut lines = string.lines();
+    if lines.next() != Some(OBJECT_HEADER) {
+        error!(
+            "not an object file, expected it to start with {}",
+            OBJECT_HEADER
+        );
+        return Err(());
+    }
+    let mut object = ObjectFile::default();
+    // the opcodes can only be read once all of the opcode-of lines are known
+    let mut instructions: Vec<(usize, [&str; 4])> = vec![];
+    for (line_num, line) in lines.enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let parsed = match words.as_slice() {
+            [] => Some(()),
+            ["cells", width] => width
+                .parse()
+                .ok()
+                .map(|width| object.instruction_set.cell_width = width),
+            ["opcode", slot] => slot
+                .parse()
+                .ok()
+                .map(|slot| object.instruction_set.opcode_slot = slot),
+            ["operands", slots @ ..] if (2..=3).contains(&slots.len()) => slots
+                .iter()
+                .map(|slot| slot.parse().ok())
+                .collect::<Option<Vec<u64>>>()
+                .map(|slots| object.instruction_set.operand_slots = slots),
+            ["opcode-of", name, opcode] if object.instruction_set.opcodes.contains_key(*name) => {
+                opcode.parse().ok().map(|opcode| {
+                    object
+                        .instruction_set
+                        .opcodes
+                        .insert(name.to_string(), opcode);
+                })
+            }
+            ["ram", size] => size.parse().ok().map(|size| object.ram_size = size),
+            ["origin", origin] => origin
+                .parse()
+                .ok()
+                .map(|origin| object.origin = Some(origin)),
+            ["fixed", name, address] => address.parse().ok().map(|address| {
+                object.fixed_variables.push((name.to_string(), address));
+            }),
+            ["instruction", opcode, a, b, c] => {
+                instructions.push((line_num, [opcode, a, b, c]));
+                Some(())
+            }
+            ["relocation", instruction, operand, kind @ ..] => {
+                let kind = match kind {
+                    ["code"] => Some(RelocationKind::Code),
+                    ["ram"] => Some(RelocationKind::Ram),
+                    ["label", name] => Some(RelocationKind::Label(name.to_string())),
+                    ["variable", name] => Some(RelocationKind::Variable(name.to_string())),
+                    _ => None,
+                };
+                match (instruction.parse(), operand.parse(), kind) {
+                    (Ok(instruction), Ok(operand @ 1..=3), Some(kind)) => {
+                        object.relocations.push(Relocation {
+                            instruction,
+                            operand,
+                            kind,
+                        });
+                        Some(())
+                    }
+                    _ => None,
+                }
+            }
+            ["symbol", kind, name, value] => {
+                let kind = match *kind {
+                    "label" => Some(SymbolKind::Label),
+                    "variable" => Some(SymbolKind::Variable),
+                    "fixed" => Some(SymbolKind::Fixed),
+                    _ => None,
+                };
+                match (kind, value.parse()) {
+                    (Some(kind), Ok(value)) => {
+                        object.symbols.push(Symbol {
+                            name: name.to_string(),
+                            kind,
+                            value,
+                        });
+                        Some(())
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        if parsed.is_none() {
+            // +2 because of the header and lines counting from 1
+            error!("invalid object file line {}: {}", line_num + 2, line);
+            return Err(());
+        }
+    }
+    for (line_num, [opcode, a, b, c]) in instructions {
+        let Some(instruction) = parse_instruction(opcode, [a, b, c], &object.instruction_set)
+        else {
+            error!(
+                "invalid object file line {}: instruction {} {} {} {}",
+                line_num + 2,
+                opcode,
+                a,
+                b,
+                c
+            );
+            return Err(());
+        };
+        object.raw_instructions.push(instruction);
+    }
+    Ok(object)
+}
+
+fn parse_instruction(
+    opcode: &str,
+    operands: [&str; 3],
+    instruction_set: &InstructionSet,
+) -> Option<RawInstructions> {
+    let opcode = opcode.parse().ok()?;
+    let mut parsed_operands = [0; 3];
+    for (parsed, operand) in parsed_operands.iter_mut().zip(operands) {
+        *parsed = operand.parse().ok()?;
+    }
+    RawInstructions::from_operands(opcode, parsed_operands, instruction_set)
+}
@@ -0,0 +1,616 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::target::{InstructionSet, Target};
+
+use super::{
+    debug_info::DebugInfo,
+    ir::Arithmetic,
+    object::{ObjectFile, RelocationKind, SymbolKind},
+    RawInstructions,
+};
+
+/// a memory cell, cells with different relocations are never the same cell
+type Cell = (RelocationKind, u64);
+
+/// removes instructions that do nothing from an object, see the README for which ones
+///
+/// code addresses change when instructions are removed, so the object is left alone
+/// if it uses code addresses for anything other than jumps and patches.
+/// the debug info of the object is moved along with its instructions
+pub fn optimize(
+    mut object: ObjectFile,
+    mut debug_info: Option<&mut DebugInfo>,
+    target: &Target,
+) -> ObjectFile {
+    let instruction_set = &target.instruction_set;
+    loop {
+        let relocations = operand_relocations(&object);
+        if !only_static_code_addresses(&object, &relocations, instruction_set) {
+            return object;
+        }
+        let removed = find_removable(&object, &relocations, target);
+        if !removed.contains(&true) {
+            return object;
+        }
+        if let Some(debug_info) = debug_info.as_deref_mut() {
+            remove_debug_info(debug_info, &removed, instruction_set);
+        }
+        object = remove_instructions(object, &relocations, &removed, instruction_set);
+    }
+}
+
+/// the relocation of the a, b and c operand of every instruction
+fn operand_relocations(object: &ObjectFile) -> Vec<[RelocationKind; 3]> {
+    let mut relocations = vec![
+        [
+            RelocationKind::Absolute,
+            RelocationKind::Absolute,
+            RelocationKind::Absolute
+        ];
+        object.raw_instructions.len()
+    ];
+    for relocation in &object.relocations {
+        relocations[relocation.instruction][relocation.operand - 1] = relocation.kind.clone();
+    }
+    relocations
+}
+
+/// operands that are the address of the instruction to jump to
+fn is_jump_operand(instruction: &RawInstructions, operand: usize) -> bool {
+    matches!(
+        (instruction, operand),
+        (RawInstructions::JMP { .. }, 1)
+            | (RawInstructions::Equal { .. }, 3)
+            | (RawInstructions::NotEqual { .. }, 3)
+            | (RawInstructions::JHT { .. }, 3)
+    )
+}
+
+/// operands that are the address of the cell the instruction writes to
+fn is_write_operand(instruction: &RawInstructions, operand: usize) -> bool {
+    matches!(
+        (instruction, operand),
+        (RawInstructions::WRITE { .. }, 1)
+            | (RawInstructions::COPY { .. }, 2)
+            | (RawInstructions::YOINK { .. }, 3)
+    )
+}
+
+/// code cells that COPY reads or writes, which are the patches generate_code makes
+fn patched_cells(object: &ObjectFile, relocations: &[[RelocationKind; 3]]) -> HashSet<u64> {
+    let mut cells = HashSet::new();
+    for (instruction, raw_instruction) in object.raw_instructions.iter().enumerate() {
+        for operand in 1..=3 {
+            if relocations[instruction][operand - 1] == RelocationKind::Code
+                && !is_jump_operand(raw_instruction, operand)
+            {
+                cells.insert(raw_instruction.operands()[operand - 1]);
+            }
+        }
+    }
+    cells
+}
+
+/// true if every code address is a jump target or a patch of an operand
+/// that is not a jump target or a write address
+fn only_static_code_addresses(
+    object: &ObjectFile,
+    relocations: &[[RelocationKind; 3]],
+    instruction_set: &InstructionSet,
+) -> bool {
+    for (instruction, raw_instruction) in object.raw_instructions.iter().enumerate() {
+        for operand in 1..=3 {
+            let code = relocations[instruction][operand - 1] == RelocationKind::Code;
+            let copy = matches!(raw_instruction, RawInstructions::COPY { .. });
+            if code && !is_jump_operand(raw_instruction, operand) && !copy {
+                return false;
+            }
+        }
+    }
+    patched_cells(object, relocations).into_iter().all(|cell| {
+        let Some((instruction, operand)) = instruction_set.operand_at(cell) else {
+            return false;
+        };
+        let Some(patched) = object.raw_instructions.get(instruction) else {
+            return false;
+        };
+        !is_jump_operand(patched, operand) && !is_write_operand(patched, operand)
+    })
+}
+
+/// instructions something can jump to, including the start and exported labels
+fn jump_targets(
+    object: &ObjectFile,
+    relocations: &[[RelocationKind; 3]],
+    instruction_set: &InstructionSet,
+) -> HashSet<usize> {
+    let mut targets = HashSet::from([0]);
+    for (instruction, raw_instruction) in object.raw_instructions.iter().enumerate() {
+        for operand in 1..=3 {
+            if relocations[instruction][operand - 1] == RelocationKind::Code
+                && is_jump_operand(raw_instruction, operand)
+            {
+                targets.extend(
+                    instruction_set.instruction_at(raw_instruction.operands()[operand - 1]),
+                );
+            }
+        }
+    }
+    for symbol in &object.symbols {
+        if symbol.kind == SymbolKind::Label {
+            targets.extend(instruction_set.instruction_at(symbol.value));
+        }
+    }
+    targets
+}
+
+/// instructions and operands that use a cell of the instruction, not counting jumps to it
+fn code_references(
+    object: &ObjectFile,
+    relocations: &[[RelocationKind; 3]],
+    instruction_set: &InstructionSet,
+    instruction: usize,
+) -> Vec<(usize, usize)> {
+    let mut references = vec![];
+    for (index, raw_instruction) in object.raw_instructions.iter().enumerate() {
+        for operand in 1..=3 {
+            if relocations[index][operand - 1] == RelocationKind::Code
+                && !is_jump_operand(raw_instruction, operand)
+                && raw_instruction.operands()[operand - 1] / instruction_set.cell_width
+                    == instruction as u64
+            {
+                references.push((index, operand));
+            }
+        }
+    }
+    references
+}
+
+fn find_removable(
+    object: &ObjectFile,
+    relocations: &[[RelocationKind; 3]],
+    target: &Target,
+) -> Vec<bool> {
+    let instruction_set = &target.instruction_set;
+    let instructions = &object.raw_instructions;
+    let patched = patched_cells(object, relocations);
+    let is_patched = |instruction: usize| {
+        (1..=instruction_set.operand_slots.len())
+            .any(|operand| patched.contains(&instruction_set.operand_address(instruction, operand)))
+    };
+    let targets = jump_targets(object, relocations, instruction_set);
+    let mut removed = vec![false; instructions.len()];
+    // the value of the accumulator if it is known
+    let mut accumulator: Option<u64> = None;
+    // patches that already happened, the code cell with the cell it was copied from
+    let mut patches: HashMap<u64, Cell> = HashMap::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        if targets.contains(&index) {
+            accumulator = None;
+            patches.clear();
+        }
+        let next = instruction_set.jump_address(index + 1);
+        let cell = |operand: usize| -> Cell {
+            (
+                relocations[index][operand - 1].clone(),
+                instruction.operands()[operand - 1],
+            )
+        };
+        match *instruction {
+            // jumps to the next instruction
+            RawInstructions::JMP { a }
+                if a == next
+                    && relocations[index][0] == RelocationKind::Code
+                    && !is_patched(index) =>
+            {
+                removed[index] = true;
+            }
+            RawInstructions::Equal { c, .. }
+            | RawInstructions::NotEqual { c, .. }
+            | RawInstructions::JHT { c, .. }
+                if c == next && relocations[index][2] == RelocationKind::Code =>
+            {
+                // the patches of a branch that is removed are not needed either,
+                // as long as nothing else uses the cells of the branch
+                let references = code_references(object, relocations, instruction_set, index);
+                let only_patches = references.iter().all(|(patch, operand)| {
+                    *operand == 2
+                        && matches!(instructions[*patch], RawInstructions::COPY { .. })
+                        && !is_patched(*patch)
+                });
+                if only_patches {
+                    removed[index] = true;
+                    for (patch, _) in references {
+                        removed[patch] = true;
+                    }
+                }
+            }
+            RawInstructions::ADD { a, b }
+            | RawInstructions::SUB { a, b }
+            | RawInstructions::MUL { a, b }
+            | RawInstructions::DIV { a, b } => {
+                let immediates = relocations[index][0] == RelocationKind::Absolute
+                    && relocations[index][1] == RelocationKind::Absolute
+                    && !is_patched(index);
+                let arithmetic = match instruction {
+                    RawInstructions::ADD { .. } => Arithmetic::Add,
+                    RawInstructions::SUB { .. } => Arithmetic::Sub,
+                    RawInstructions::MUL { .. } => Arithmetic::Mul,
+                    _ => Arithmetic::Div,
+                };
+                // like the machine calculates it, nothing is known if it stops the program
+                let value = if immediates {
+                    target.calculate(arithmetic, a, b).ok()
+                } else {
+                    None
+                };
+                if value.is_some() && value == accumulator {
+                    // the accumulator already has the value, like after an earlier DEF of it
+                    removed[index] = true;
+                }
+                accumulator = value;
+            }
+            // only operands that are not written to are patched so a patch changes nothing else
+            RawInstructions::COPY { b, .. } if relocations[index][1] == RelocationKind::Code => {
+                if relocations[index][0] == RelocationKind::Absolute {
+                    // a variable with a fixed address, a device can change it at any time
+                    patches.remove(&b);
+                } else if patches.get(&b) == Some(&cell(1)) && !is_patched(index) {
+                    // the cell was already patched with the same value
+                    removed[index] = true;
+                } else {
+                    patches.insert(b, cell(1));
+                }
+            }
+            RawInstructions::COPY { .. }
+            | RawInstructions::WRITE { .. }
+            | RawInstructions::YOINK { .. } => {
+                let operand = match instruction {
+                    RawInstructions::COPY { .. } => 2,
+                    RawInstructions::WRITE { .. } => 1,
+                    _ => 3,
+                };
+                let written = cell(operand);
+                patches.retain(|patched_cell, source| {
+                    *source != written && (RelocationKind::Code, *patched_cell) != written
+                });
+            }
+            RawInstructions::JMP { .. } | RawInstructions::EXIT { .. } => {
+                // the next instruction only runs if something jumps to it
+                accumulator = None;
+                patches.clear();
+            }
+            _ => {}
+        }
+    }
+    removed
+}
+
+/// new index of every instruction, removed instructions get the index of the next one that stays
+fn new_indices(removed: &[bool]) -> Vec<usize> {
+    let mut new_index = vec![0; removed.len() + 1];
+    for index in 0..removed.len() {
+        new_index[index + 1] = new_index[index] + usize::from(!removed[index]);
+    }
+    new_index
+}
+
+/// forgets where the removed instructions came from and moves the labels
+fn remove_debug_info(
+    debug_info: &mut DebugInfo,
+    removed: &[bool],
+    instruction_set: &InstructionSet,
+) {
+    let new_index = new_indices(removed);
+    let mut index = 0;
+    debug_info.instruction_sources.retain(|_| {
+        index += 1;
+        !removed[index - 1]
+    });
+    for address in debug_info.labels.values_mut() {
+        if let Some(instruction) = instruction_set.instruction_at(*address) {
+            *address = instruction_set.jump_address(new_index[instruction]);
+        }
+    }
+}
+
+/// removes the instructions and moves every code address to where its instruction ends up
+fn remove_instructions(
+    object: ObjectFile,
+    relocations: &[[RelocationKind; 3]],
+    removed: &[bool],
+    instruction_set: &InstructionSet,
+) -> ObjectFile {
+    let new_index = new_indices(removed);
+    let width = instruction_set.cell_width;
+    let jump = |address: u64| {
+        instruction_set.jump_address(new_index[instruction_set.instruction_at(address).unwrap()])
+    };
+    let cell = |address: u64| {
+        instruction_set.address(new_index[(address / width) as usize]) + address % width
+    };
+    let mut optimized = ObjectFile {
+        ram_size: object.ram_size,
+        origin: object.origin,
+        fixed_variables: object.fixed_variables,
+        instruction_set: object.instruction_set,
+        ..Default::default()
+    };
+    for (index, mut instruction) in object.raw_instructions.into_iter().enumerate() {
+        if removed[index] {
+            continue;
+        }
+        for operand in 1..=3 {
+            if relocations[index][operand - 1] != RelocationKind::Code {
+                continue;
+            }
+            let is_jump = is_jump_operand(&instruction, operand);
+            let value = instruction.operand_mut(operand).unwrap();
+            *value = if is_jump { jump(*value) } else { cell(*value) };
+        }
+        optimized.push(instruction, relocations[index].clone());
+    }
+    optimized.symbols = object
+        .symbols
+        .into_iter()
+        .map(|mut symbol| {
+            if symbol.kind == SymbolKind::Label {
+                symbol.value = jump(symbol.value);
+            }
+            symbol
+        })
+        .collect();
+    optimized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        code_gen::{compile_object, generate_code_with_debug_info, link::link, CompileOptions},
+        emulator::{Machine, Yeet},
+        parsing::parse::{parse_string, parse_string_with_lines},
+        target::Target,
+    };
+
+    const STEP_LIMIT: u64 = 100_000;
+
+    fn compile(program: &str, optimized: bool) -> Vec<RawInstructions> {
+        let object = compile_object(
+            parse_string(program.to_owned()),
+            &[],
+            &CompileOptions::default(),
+        )
+        .unwrap();
+        let object = if optimized {
+            optimize(object, None, &Target::default())
+        } else {
+            object
+        };
+        link(vec![object], &Target::default()).unwrap()
+    }
+
+    fn run(code: &[RawInstructions]) -> (u64, Vec<Yeet>) {
+        let mut machine = Machine::new(code);
+        let exit_code = machine.run(STEP_LIMIT).unwrap();
+        (exit_code, machine.yeets)
+    }
+
+    /// the optimized program has to do the same thing in fewer or as many instructions
+    fn assert_equivalent(program: &str) -> (Vec<RawInstructions>, Vec<RawInstructions>) {
+        let code = compile(program, false);
+        let optimized = compile(program, true);
+        assert_eq!(run(&code), run(&optimized), "{}", program);
+        assert!(optimized.len() <= code.len());
+        (code, optimized)
+    }
+
+    #[test]
+    fn snapshot_programs_do_the_same_when_optimized() {
+        for program in [
+            include_str!("../../tests/snapshots/arithmetic.ass"),
+            include_str!("../../tests/snapshots/bitwise.ass"),
+            include_str!("../../tests/snapshots/blocks.ass"),
+            include_str!("../../tests/snapshots/branches.ass"),
+            include_str!("../../tests/snapshots/copy_and_yeet.ass"),
+            include_str!("../../tests/snapshots/modules.ass"),
+            include_str!("../../tests/snapshots/yoink.ass"),
+        ] {
+            assert_equivalent(program);
+        }
+    }
+
+    #[test]
+    fn defines_of_the_same_value_share_the_add() {
+        let (code, optimized) =
+            assert_equivalent("DEF ?a 0\nDEF ?b 0\nDEF ?c 1\nADD ?a ?c ?b\nDEF ?d 0\nEXIT ?b\n");
+        assert_eq!(code.len() - optimized.len(), 1);
+        assert_eq!(
+            &optimized[..3],
+            &[
+                RawInstructions::ADD { a: 0, b: 0 },
+                RawInstructions::WRITE { a: 48 },
+                RawInstructions::WRITE { a: 49 },
+            ]
+        );
+    }
+
+    #[test]
+    fn defines_after_a_label_keep_their_add() {
+        let program = "DEF ?i 0\nDEF ?one 1\n:loop\nDEF ?one 1\nADD ?i ?one ?i\nDEF ?three 3\nJNE ?i ?three :loop\nEXIT ?i\n";
+        let (code, optimized) = assert_equivalent(program);
+        assert_eq!(code.len(), optimized.len());
+    }
+
+    #[test]
+    fn jumps_to_the_next_instruction_are_removed() {
+        let program = "DEF ?a 3\nDEF ?b 4\nJMP :next\n:next\nJE ?a ?b :also_next\n:also_next\nJHT ?b ?a :end\nEXIT ?b\n:end\nEXIT ?a\n";
+        let (code, optimized) = assert_equivalent(program);
+        // the JMP and the JE with its two patches
+        assert_eq!(code.len() - optimized.len(), 4);
+        assert_eq!(run(&optimized).0, 3);
+    }
+
+    #[test]
+    fn repeated_patches_are_removed() {
+        let mut object = ObjectFile::default();
+        let absolute = || {
+            [
+                RelocationKind::Absolute,
+                RelocationKind::Absolute,
+                RelocationKind::Absolute,
+            ]
+        };
+        let ram = || {
+            [
+                RelocationKind::Ram,
+                RelocationKind::Absolute,
+                RelocationKind::Absolute,
+            ]
+        };
+        let patch = || {
+            [
+                RelocationKind::Ram,
+                RelocationKind::Code,
+                RelocationKind::Absolute,
+            ]
+        };
+        // patches ?0 into the ADD twice, the second time it is still the same value
+        object.push(RawInstructions::ADD { a: 5, b: 0 }, absolute());
+        object.push(RawInstructions::WRITE { a: 0 }, ram());
+        object.push(RawInstructions::COPY { a: 0, b: 13 }, patch());
+        object.push(RawInstructions::ADD { a: 0, b: 1 }, absolute());
+        object.push(RawInstructions::COPY { a: 0, b: 13 }, patch());
+        object.push(RawInstructions::WRITE { a: 1 }, ram());
+        object.push(RawInstructions::EXIT { a: 1 }, ram());
+        object.ram_size = 2;
+        let optimized = optimize(object, None, &Target::default());
+        assert_eq!(
+            optimized.raw_instructions,
+            vec![
+                RawInstructions::ADD { a: 5, b: 0 },
+                RawInstructions::WRITE { a: 0 },
+                RawInstructions::COPY { a: 0, b: 13 },
+                RawInstructions::ADD { a: 0, b: 1 },
+                RawInstructions::WRITE { a: 1 },
+                RawInstructions::EXIT { a: 1 },
+            ]
+        );
+        assert_eq!(
+            run(&link(vec![optimized], &Target::default()).unwrap()).0,
+            6
+        );
+    }
+
+    #[test]
+    fn exported_labels_move_with_their_code() {
+        let library =
+            "MODULE lib\nDEF ?a 7\nJMP :start\n:start\nEXIT ?a\nEXPORT :start\nENDMODULE\n";
+        let main = "JMP :lib::start\n";
+        let objects = |optimized: bool| {
+            [main, library]
+                .map(|program| {
+                    let object = compile_object(
+                        parse_string(program.to_owned()),
+                        &[],
+                        &CompileOptions::default(),
+                    )
+                    .unwrap();
+                    if optimized {
+                        optimize(object, None, &Target::default())
+                    } else {
+                        object
+                    }
+                })
+                .into_iter()
+                .collect::<Vec<ObjectFile>>()
+        };
+        let code = link(objects(false), &Target::default()).unwrap();
+        let optimized = link(objects(true), &Target::default()).unwrap();
+        assert_eq!(optimized.len(), code.len() - 1);
+        assert_eq!(run(&optimized), run(&code));
+    }
+
+    #[test]
+    fn debug_info_moves_with_the_code() {
+        let (ast, ast_lines) =
+            parse_string_with_lines("DEF ?a 1\nJMP :end\n:end\nEXIT ?a\n".to_owned());
+        let options = CompileOptions {
+            opt_level: 2,
+            ..CompileOptions::default()
+        };
+        let (code, debug_info) = generate_code_with_debug_info(ast, &ast_lines, &options).unwrap();
+        // the JMP to the next instruction is gone
+        assert_eq!(code.len(), 3);
+        assert_eq!(debug_info.instruction_sources, vec![0, 0, 3]);
+        assert_eq!(debug_info.labels["end"], debug_info.program_counter_of(2));
+    }
+
+    #[test]
+    fn patches_from_fixed_addresses_stay() {
+        let mut object = ObjectFile::default();
+        let absolute = || {
+            [
+                RelocationKind::Absolute,
+                RelocationKind::Absolute,
+                RelocationKind::Absolute,
+            ]
+        };
+        let patch = || {
+            [
+                RelocationKind::Absolute,
+                RelocationKind::Code,
+                RelocationKind::Absolute,
+            ]
+        };
+        // a device can change the cell at 0x4000 between the two patches
+        object.push(RawInstructions::COPY { a: 0x4000, b: 9 }, patch());
+        object.push(RawInstructions::COPY { a: 0x4000, b: 9 }, patch());
+        object.push(RawInstructions::ADD { a: 0, b: 1 }, absolute());
+        object.push(RawInstructions::EXIT { a: 0x4000 }, absolute());
+        let optimized = optimize(object, None, &Target::default());
+        assert_eq!(optimized.raw_instructions.len(), 4);
+    }
+
+    #[test]
+    fn the_accumulator_is_calculated_like_the_target() {
+        let target = Target {
+            word_size: 16,
+            signed: true,
+            ..Target::default()
+        };
+        let immediates = || {
+            [
+                RelocationKind::Absolute,
+                RelocationKind::Absolute,
+                RelocationKind::Absolute,
+            ]
+        };
+        let ram = || {
+            [
+                RelocationKind::Ram,
+                RelocationKind::Absolute,
+                RelocationKind::Absolute,
+            ]
+        };
+        let object = || {
+            let mut object = ObjectFile::default();
+            object.push(RawInstructions::ADD { a: 0x7fff, b: 0 }, immediates());
+            object.push(RawInstructions::WRITE { a: 0 }, ram());
+            // -2 / 2 is -1, unsigned 64 bit words would make it 0x7fff like the ADD
+            object.push(RawInstructions::DIV { a: 0xfffe, b: 2 }, immediates());
+            object.push(RawInstructions::WRITE { a: 1 }, ram());
+            object.push(RawInstructions::EXIT { a: 1 }, ram());
+            object.ram_size = 2;
+            object
+        };
+        let run = |object: ObjectFile| {
+            let code = link(vec![object], &target).unwrap();
+            Machine::for_target(&code, 0, target.clone()).run(STEP_LIMIT)
+        };
+        let optimized = optimize(object(), None, &target);
+        assert_eq!(optimized.raw_instructions.len(), 5);
+        assert_eq!(run(optimized), run(object()));
+        assert_eq!(run(object()), Ok(0xffff));
+    }
+}
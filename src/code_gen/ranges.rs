@@ -0,0 +1,472 @@
+use crate::target::{Overflow, Target};
+
+use super::{
+    cfg::{block_of_labels, ControlFlowGraph},
+    ir::{Arithmetic, Condition, Operation, Program, Value, VariableKind},
+    CodeGenerationWarningType,
+};
+
+/// a block can get a new state this often before its ranges are made as big as they can get,
+/// so loops do not have to run for every value
+const WIDEN_AFTER: usize = 3;
+
+/// all the numbers a variable can hold at an instruction, signed words can be below 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Range {
+    min: i128,
+    max: i128,
+}
+
+impl Range {
+    /// nothing is known about the value
+    fn any(target: &Target) -> Range {
+        Range {
+            min: target.min(),
+            max: target.max(),
+        }
+    }
+    fn exactly(value: i128) -> Range {
+        Range {
+            min: value,
+            max: value,
+        }
+    }
+    fn is_known(&self, target: &Target) -> bool {
+        *self != Range::any(target)
+    }
+    fn join(&self, other: &Range) -> Range {
+        Range {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+    /// like join but a bound that moved goes all the way
+    fn widen(&self, new: &Range, target: &Target) -> Range {
+        Range {
+            min: if new.min < self.min {
+                target.min()
+            } else {
+                self.min
+            },
+            max: if new.max > self.max {
+                target.max()
+            } else {
+                self.max
+            },
+        }
+    }
+}
+
+/// the range of every variable, by VariableId
+type Ranges = Vec<Range>;
+
+/// finds divisions where the divisor can be 0 and arithmetic that can go past the word size of the target.
+/// only values with a known range are checked, a value from YOINK or another object can be anything.
+/// returns the AST node every warning is about, sorted by node
+pub fn check_ranges(program: &Program, target: &Target) -> Vec<(usize, CodeGenerationWarningType)> {
+    let shared = shared_variables(program);
+    let mut warnings = vec![];
+    for (block, state) in program
+        .blocks
+        .iter()
+        .zip(block_states(program, &shared, target))
+    {
+        let Some(mut state) = state else {
+            continue;
+        };
+        for instruction in &block.instructions {
+            if let Some(warning) = check(program, &instruction.operation, &state, target) {
+                let warning = (instruction.source, warning);
+                if !warnings.contains(&warning) {
+                    warnings.push(warning);
+                }
+            }
+            transfer(&mut state, &instruction.operation, &shared, target);
+        }
+    }
+    warnings.sort_by_key(|(source, _)| *source);
+    warnings
+}
+
+/// variables of other objects and devices can change while the code runs, so they can always be anything
+fn shared_variables(program: &Program) -> Vec<bool> {
+    program
+        .variables
+        .iter()
+        .map(|kind| match kind {
+            VariableKind::External(_) | VariableKind::Fixed { .. } => true,
+            VariableKind::Named(name) => program.exports.contains(name),
+            VariableKind::Scratch => false,
+        })
+        .collect()
+}
+
+/// the ranges at the start of every block, none if the block can never run
+fn block_states(program: &Program, shared: &[bool], target: &Target) -> Vec<Option<Ranges>> {
+    let graph = ControlFlowGraph::new(program);
+    let blocks = block_of_labels(program);
+    let mut states: Vec<Option<Ranges>> = vec![None; program.blocks.len()];
+    let mut updates = vec![0; program.blocks.len()];
+    for entry in &graph.entries {
+        states[*entry] = Some(vec![Range::any(target); program.variables.len()]);
+    }
+    let mut todo = graph.entries.clone();
+    while let Some(index) = todo.pop() {
+        let mut state = states[index].clone().unwrap();
+        let block = &program.blocks[index];
+        let mut successors: Vec<(usize, Ranges)> = vec![];
+        let mut falls_through = block.falls_through();
+        for instruction in &block.instructions {
+            match &instruction.operation {
+                Operation::Branch {
+                    condition,
+                    a,
+                    b,
+                    target: label,
+                } => {
+                    if let (Some(block), Some(taken)) = (
+                        blocks.get(label),
+                        refine(&state, *condition, true, a, b, target),
+                    ) {
+                        successors.push((*block, taken));
+                    }
+                    match refine(&state, *condition, false, a, b, target) {
+                        Some(not_taken) => state = not_taken,
+                        // the branch always jumps
+                        None => falls_through = false,
+                    }
+                }
+                Operation::Jump { target: label } => {
+                    successors.extend(blocks.get(label).map(|block| (*block, state.clone())));
+                }
+                operation => transfer(&mut state, operation, shared, target),
+            }
+        }
+        if falls_through && index + 1 < program.blocks.len() {
+            successors.push((index + 1, state));
+        }
+        for (successor, state) in successors {
+            let merged = match &states[successor] {
+                None => state,
+                Some(old) if updates[successor] >= WIDEN_AFTER => old
+                    .iter()
+                    .zip(&state)
+                    .map(|(old, new)| old.widen(new, target))
+                    .collect(),
+                Some(old) => old
+                    .iter()
+                    .zip(&state)
+                    .map(|(old, new)| old.join(new))
+                    .collect(),
+            };
+            if states[successor].as_ref() != Some(&merged) {
+                states[successor] = Some(merged);
+                updates[successor] += 1;
+                todo.push(successor);
+            }
+        }
+    }
+    states
+}
+
+fn range(state: &Ranges, value: &Value, target: &Target) -> Range {
+    match value {
+        Value::Variable(variable) => state[variable.0],
+        Value::Immediate(word) => Range::exactly(target.value(*word)),
+    }
+}
+
+/// the ranges after the branch jumped or did not jump, none if that can not happen
+fn refine(
+    state: &Ranges,
+    condition: Condition,
+    taken: bool,
+    a: &Value,
+    b: &Value,
+    target: &Target,
+) -> Option<Ranges> {
+    let (a_range, b_range) = (range(state, a, target), range(state, b, target));
+    let (a_range, b_range) = match (condition, taken) {
+        (Condition::Equal, true) | (Condition::NotEqual, false) => {
+            let both = Range {
+                min: a_range.min.max(b_range.min),
+                max: a_range.max.min(b_range.max),
+            };
+            (both, both)
+        }
+        (Condition::Equal, false) | (Condition::NotEqual, true) => {
+            (exclude(a_range, b_range)?, exclude(b_range, a_range)?)
+        }
+        (Condition::HigherThan, true) => (
+            Range {
+                min: a_range.min.max(b_range.min + 1),
+                max: a_range.max,
+            },
+            Range {
+                min: b_range.min,
+                max: b_range.max.min(a_range.max - 1),
+            },
+        ),
+        (Condition::HigherThan, false) => (
+            Range {
+                min: a_range.min,
+                max: a_range.max.min(b_range.max),
+            },
+            Range {
+                min: b_range.min.max(a_range.min),
+                max: b_range.max,
+            },
+        ),
+    };
+    if a_range.min > a_range.max || b_range.min > b_range.max {
+        return None;
+    }
+    let mut state = state.clone();
+    if let Value::Variable(variable) = a {
+        state[variable.0] = a_range;
+    }
+    if let Value::Variable(variable) = b {
+        state[variable.0] = b_range;
+    }
+    Some(state)
+}
+
+/// the range without the value other has, if other only has one
+fn exclude(range: Range, other: Range) -> Option<Range> {
+    if other.min != other.max {
+        return Some(range);
+    }
+    match range {
+        Range { min, max } if min == other.min && max == other.min => None,
+        Range { min, max } if min == other.min => Some(Range { min: min + 1, max }),
+        Range { min, max } if max == other.min => Some(Range { min, max: max - 1 }),
+        _ => Some(range),
+    }
+}
+
+/// the ranges after the operation, branches are handled by refine
+fn transfer(state: &mut Ranges, operation: &Operation, shared: &[bool], target: &Target) {
+    match operation {
+        Operation::Arithmetic {
+            arithmetic,
+            a,
+            b,
+            destination,
+        } => {
+            let (a, b) = (range(state, a, target), range(state, b, target));
+            state[destination.0] = calculate(*arithmetic, a, b, target).0;
+        }
+        Operation::Copy { from, to } => state[to.0] = state[from.0],
+        Operation::Yoink { destination, .. } => state[destination.0] = Range::any(target),
+        _ => {}
+    }
+    for (range, shared) in state.iter_mut().zip(shared) {
+        if *shared {
+            *range = Range::any(target);
+        }
+    }
+}
+
+/// the range of the result and if it can go past the word size, like the target does it
+fn calculate(arithmetic: Arithmetic, a: Range, b: Range, target: &Target) -> (Range, bool) {
+    let results: Vec<i128> = match arithmetic {
+        Arithmetic::Add => vec![a.min + b.min, a.max + b.max],
+        Arithmetic::Sub => vec![a.min - b.max, a.max - b.min],
+        Arithmetic::Mul => [a.min, a.max]
+            .iter()
+            .flat_map(|a| [b.min, b.max].map(|b| a.saturating_mul(b)))
+            .collect(),
+        Arithmetic::Div => {
+            // the biggest results come from the ends of the divisor or from dividing by 1 or -1
+            let divisors: Vec<i128> = [b.min, b.max, -1, 1]
+                .into_iter()
+                .filter(|divisor| *divisor != 0 && (b.min..=b.max).contains(divisor))
+                .collect();
+            [a.min, a.max]
+                .iter()
+                .flat_map(|a| divisors.iter().map(move |divisor| a / divisor))
+                .collect()
+        }
+    };
+    let (Some(min), Some(max)) = (results.iter().min(), results.iter().max()) else {
+        // dividing by 0 stops the program
+        return (Range::any(target), false);
+    };
+    let overflows = *min < target.min() || *max > target.max();
+    let range = match (overflows, target.overflow) {
+        (false, _) => Range {
+            min: *min,
+            max: *max,
+        },
+        (true, Overflow::Saturate) => Range {
+            min: (*min).clamp(target.min(), target.max()),
+            max: (*max).clamp(target.min(), target.max()),
+        },
+        (true, Overflow::Wrap | Overflow::Trap) => Range::any(target),
+    };
+    (range, overflows)
+}
+
+/// the warning about the arithmetic, only arithmetic the program wrote itself is checked for overflows.
+/// the bitwise operations are made so they never go past a word
+fn check(
+    program: &Program,
+    operation: &Operation,
+    state: &Ranges,
+    target: &Target,
+) -> Option<CodeGenerationWarningType> {
+    let Operation::Arithmetic {
+        arithmetic,
+        a,
+        b,
+        destination,
+    } = operation
+    else {
+        return None;
+    };
+    let (a_range, b_range) = (range(state, a, target), range(state, b, target));
+    if *arithmetic == Arithmetic::Div
+        && b_range.is_known(target)
+        && (b_range.min..=b_range.max).contains(&0)
+    {
+        return Some(CodeGenerationWarningType::DivisionByZero {
+            always: b_range == Range::exactly(0),
+        });
+    }
+    let uses_scratch = [*a, *b, Value::Variable(*destination)]
+        .iter()
+        .any(|value| {
+            matches!(value, Value::Variable(variable) if program.variables[variable.0] == VariableKind::Scratch)
+        });
+    let (_, overflows) = calculate(*arithmetic, a_range, b_range, target);
+    if overflows && a_range.is_known(target) && b_range.is_known(target) && !uses_scratch {
+        return Some(CodeGenerationWarningType::Overflow {
+            arithmetic: *arithmetic,
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{code_gen::lower::lower, parsing::parse::parse_string};
+
+    fn warnings(program: &str) -> Vec<(usize, CodeGenerationWarningType)> {
+        warnings_for(program, &Target::default())
+    }
+
+    fn warnings_for(program: &str, target: &Target) -> Vec<(usize, CodeGenerationWarningType)> {
+        let mut errors = vec![];
+        let program = lower(
+            &parse_string(program.to_owned()),
+            vec![],
+            &[],
+            target,
+            &mut errors,
+        );
+        assert!(errors.is_empty());
+        check_ranges(&program, target)
+    }
+
+    #[test]
+    fn warns_about_divisors_that_can_be_zero() {
+        assert_eq!(
+            warnings("DEF ?a 6\nDEF ?zero 0\nDIV ?a ?zero ?a\nEXIT ?a\n"),
+            vec![(
+                2,
+                CodeGenerationWarningType::DivisionByZero { always: true }
+            )]
+        );
+        assert_eq!(
+            warnings(
+                "DEF ?a 6\nDEF ?b 0\nYOINK ?a ?a ?b\nJZ ?b :skip\nDEF ?b 2\n:skip\nMOD ?a ?b ?a\nEXIT ?a\n"
+            ),
+            vec![(6, CodeGenerationWarningType::DivisionByZero { always: false })]
+        );
+    }
+
+    #[test]
+    fn warns_about_arithmetic_that_wraps_around() {
+        assert_eq!(
+            warnings("DEF ?a 1\nDEF ?b 2\nSUB ?a ?b ?a\nMUL ?b ?b ?b\nEXIT ?a\n"),
+            vec![(
+                2,
+                CodeGenerationWarningType::Overflow {
+                    arithmetic: Arithmetic::Sub
+                }
+            )]
+        );
+        assert_eq!(
+            warnings("DEF ?a 18446744073709551615\nDEF ?one 1\nADD ?a ?one ?a\nEXIT ?a\n"),
+            vec![(
+                2,
+                CodeGenerationWarningType::Overflow {
+                    arithmetic: Arithmetic::Add
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn overflows_depend_on_the_target() {
+        let program = "DEF ?a 60000\nDEF ?b 10000\nADD ?a ?b ?a\nEXIT ?a\n";
+        assert_eq!(warnings(program), vec![]);
+        let target = Target {
+            word_size: 16,
+            ..Target::default()
+        };
+        assert_eq!(
+            warnings_for(program, &target),
+            vec![(
+                2,
+                CodeGenerationWarningType::Overflow {
+                    arithmetic: Arithmetic::Add
+                }
+            )]
+        );
+        let signed = Target {
+            word_size: 16,
+            signed: true,
+            ..Target::default()
+        };
+        // 1 - 2 is -1 on a signed target
+        assert_eq!(
+            warnings_for("DEF ?a 1\nDEF ?b 2\nSUB ?a ?b ?a\nEXIT ?a\n", &signed),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn loops_do_not_warn() {
+        // ?i counts down in the loop, the range of a loop variable ends up unknown
+        assert_eq!(
+            warnings(
+                "DEF ?i 10\nDEF ?one 1\nDEF ?a 100\n:loop\nDIV ?a ?i ?a\nSUB ?i ?one ?i\nJNZ ?i :loop\nEXIT ?a\n"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn unknown_values_are_not_warned_about() {
+        assert_eq!(
+            warnings("DEF ?a 1\nDEF ?b 2\nYOINK ?a ?b ?a\nYOINK ?a ?b ?b\nDIV ?a ?b ?a\nADD ?a ?b ?a\nEXIT ?a\n"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn snapshot_programs_have_no_warnings() {
+        for program in [
+            include_str!("../../tests/snapshots/arithmetic.ass"),
+            include_str!("../../tests/snapshots/bitwise.ass"),
+            include_str!("../../tests/snapshots/blocks.ass"),
+            include_str!("../../tests/snapshots/branches.ass"),
+            include_str!("../../tests/snapshots/copy_and_yeet.ass"),
+            include_str!("../../tests/snapshots/yoink.ass"),
+        ] {
+            assert_eq!(warnings(program), vec![], "{}", program);
+        }
+    }
+}
@@ -0,0 +1,447 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    code_gen::{debug_info::DebugInfo, RawInstructions},
+    emulator::{Machine, StepResult},
+    target::Target,
+};
+
+const HELP: &str = "\
+step [n]            run until the next source line, n times
+stepi [n]           run one machine instruction, n times
+continue            run until a breakpoint, watchpoint or the end of the program
+break :label        stop before the code after a label runs
+break <line>        stop before the code of a source line runs
+watch ?variable     stop after a variable changes
+delete <n>          removes breakpoint or watchpoint n
+info                lists breakpoints and watchpoints
+print ?variable     shows the value of a variable
+print <address>     shows the value of a memory cell
+vars                shows the value of every variable
+where               shows the instruction that runs next
+help                shows this
+quit                stops debugging";
+
+/// stops the program before the instruction at one of the program counter values runs
+struct Breakpoint {
+    number: usize,
+    name: String,
+    program_counters: Vec<u64>,
+}
+
+/// stops the program after the value at the address changes
+struct Watchpoint {
+    number: usize,
+    name: String,
+    address: u64,
+    value: u64,
+}
+
+enum Stop {
+    Running,
+    Exited { code: u64 },
+    Error(String),
+}
+
+/// runs a program in the emulator one piece at a time
+pub struct Debugger {
+    machine: Machine,
+    debug_info: DebugInfo,
+    /// source line of every ast node
+    ast_lines: Vec<usize>,
+    source: Vec<String>,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    /// breakpoints and watchpoints are numbered together in the order they were made
+    next_number: usize,
+    state: Stop,
+}
+
+impl Debugger {
+    pub fn new(
+        source: &str,
+        ast_lines: Vec<usize>,
+        raw_instructions: &[RawInstructions],
+        debug_info: DebugInfo,
+        target: Target,
+    ) -> Debugger {
+        let machine = Machine::for_target(raw_instructions, debug_info.code_origin, target);
+        Debugger {
+            machine,
+            debug_info,
+            ast_lines,
+            source: source.lines().map(str::to_owned).collect(),
+            breakpoints: vec![],
+            watchpoints: vec![],
+            next_number: 1,
+            state: Stop::Running,
+        }
+    }
+
+    /// reads commands until quit or the end of the input
+    pub fn run_prompt(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "{}", self.location())?;
+        write!(output, "(ass) ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            if matches!(line.trim(), "quit" | "q") {
+                return Ok(());
+            }
+            let response = self.command(&line);
+            if !response.is_empty() {
+                writeln!(output, "{}", response)?;
+            }
+            write!(output, "(ass) ")?;
+            output.flush()?;
+        }
+        writeln!(output)
+    }
+
+    /// runs one command and returns what to show
+    pub fn command(&mut self, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return String::new();
+        };
+        let argument = words.next();
+        let result = match command {
+            "step" | "s" => count(argument).map(|times| self.step_lines(times)),
+            "stepi" | "si" => count(argument).map(|times| self.step_instructions(times)),
+            "continue" | "c" => Ok(self.continue_running()),
+            "break" | "b" => self.add_breakpoint(argument),
+            "watch" | "w" => self.add_watchpoint(argument),
+            "delete" | "d" => self.delete(argument),
+            "info" | "i" => Ok(self.info()),
+            "print" | "p" => self.print(argument),
+            "vars" => Ok(self.variables()),
+            "where" => Ok(self.location()),
+            "help" | "h" => Ok(HELP.to_owned()),
+            command => Err(format!("unknown command {}, try help", command)),
+        };
+        result.unwrap_or_else(|err| err)
+    }
+
+    fn step_lines(&mut self, times: u64) -> String {
+        let mut output = String::new();
+        for _ in 0..times {
+            let line = self.current_line();
+            // runs at least one instruction, then until the code of another line starts
+            output.push_str(&self.run_until(|debugger| debugger.current_line() != line));
+            if !output.is_empty() {
+                break;
+            }
+        }
+        output.push_str(&self.location());
+        output
+    }
+
+    fn step_instructions(&mut self, times: u64) -> String {
+        let mut output = String::new();
+        for _ in 0..times {
+            output.push_str(&self.run_until(|_| true));
+            if !output.is_empty() {
+                break;
+            }
+        }
+        output.push_str(&self.location());
+        output
+    }
+
+    fn continue_running(&mut self) -> String {
+        let mut output = self.run_until(|debugger| debugger.breakpoint_hit().is_some());
+        if let Some(name) = self.breakpoint_hit() {
+            writeln!(output, "breakpoint {}", name).unwrap();
+        }
+        output.push_str(&self.location());
+        output
+    }
+
+    /// runs instructions until `stop` says so, a watchpoint changes or the program ends,
+    /// returns why it stopped if that was not `stop`
+    fn run_until(&mut self, stop: impl Fn(&Debugger) -> bool) -> String {
+        let mut output = String::new();
+        loop {
+            if !matches!(self.state, Stop::Running) {
+                return output;
+            }
+            match self.machine.step() {
+                Ok(StepResult::Running) => {}
+                Ok(StepResult::Exited { code }) => self.state = Stop::Exited { code },
+                Err(err) => self.state = Stop::Error(err.to_string()),
+            }
+            let mut changed = false;
+            for watchpoint in self.watchpoints.iter_mut() {
+                let value = self.machine.read(watchpoint.address);
+                if value != watchpoint.value {
+                    writeln!(
+                        output,
+                        "?{} changed from {} to {}",
+                        watchpoint.name, watchpoint.value, value
+                    )
+                    .unwrap();
+                    watchpoint.value = value;
+                    changed = true;
+                }
+            }
+            if changed || stop(self) {
+                return output;
+            }
+        }
+    }
+
+    fn breakpoint_hit(&self) -> Option<&str> {
+        if !matches!(self.state, Stop::Running) {
+            return None;
+        }
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| {
+                breakpoint
+                    .program_counters
+                    .contains(&self.machine.program_counter)
+            })
+            .map(|breakpoint| breakpoint.name.as_str())
+    }
+
+    fn add_breakpoint(&mut self, argument: Option<&str>) -> Result<String, String> {
+        let argument = argument.ok_or("break needs a :label or a line")?;
+        let program_counters = if let Some(label) = argument.strip_prefix(':') {
+            let address = self
+                .debug_info
+                .labels
+                .get(label)
+                .ok_or(format!("label {} does not exist", label))?;
+            vec![*address]
+        } else {
+            let line: usize = argument
+                .parse()
+                .map_err(|_| format!("{} is not a :label or a line", argument))?;
+            let program_counters: Vec<u64> = (0..self.ast_lines.len())
+                .filter(|source| self.ast_lines[*source] == line)
+                .filter_map(|source| self.debug_info.start_of(source))
+                .collect();
+            if program_counters.is_empty() {
+                return Err(format!("line {} has no code", line));
+            }
+            program_counters
+        };
+        let number = self.next_number();
+        self.breakpoints.push(Breakpoint {
+            number,
+            name: argument.to_owned(),
+            program_counters,
+        });
+        Ok(format!("breakpoint {} at {}", number, argument))
+    }
+
+    fn add_watchpoint(&mut self, argument: Option<&str>) -> Result<String, String> {
+        let name = argument
+            .and_then(|argument| argument.strip_prefix('?'))
+            .ok_or("watch needs a ?variable")?;
+        let address = self.variable_address(name)?;
+        let number = self.next_number();
+        self.watchpoints.push(Watchpoint {
+            number,
+            name: name.to_owned(),
+            address,
+            value: self.machine.read(address),
+        });
+        Ok(format!("watchpoint {} on ?{}", number, name))
+    }
+
+    fn delete(&mut self, argument: Option<&str>) -> Result<String, String> {
+        let number: usize = argument
+            .and_then(|argument| argument.parse().ok())
+            .ok_or("delete needs the number of a breakpoint or watchpoint")?;
+        let breakpoints = self.breakpoints.len();
+        let watchpoints = self.watchpoints.len();
+        self.breakpoints
+            .retain(|breakpoint| breakpoint.number != number);
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.number != number);
+        if breakpoints != self.breakpoints.len() {
+            Ok(format!("deleted breakpoint {}", number))
+        } else if watchpoints != self.watchpoints.len() {
+            Ok(format!("deleted watchpoint {}", number))
+        } else {
+            Err(format!("there is no breakpoint or watchpoint {}", number))
+        }
+    }
+
+    fn info(&self) -> String {
+        let mut lines: Vec<(usize, String)> = vec![];
+        for breakpoint in &self.breakpoints {
+            lines.push((
+                breakpoint.number,
+                format!("breakpoint {} at {}", breakpoint.number, breakpoint.name),
+            ));
+        }
+        for watchpoint in &self.watchpoints {
+            lines.push((
+                watchpoint.number,
+                format!("watchpoint {} on ?{}", watchpoint.number, watchpoint.name),
+            ));
+        }
+        if lines.is_empty() {
+            return "no breakpoints or watchpoints".to_owned();
+        }
+        lines.sort();
+        lines
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn print(&self, argument: Option<&str>) -> Result<String, String> {
+        let argument = argument.ok_or("print needs a ?variable or an address")?;
+        if let Some(name) = argument.strip_prefix('?') {
+            let address = self.variable_address(name)?;
+            return Ok(format!(
+                "?{} = {} ( address {} )",
+                name,
+                self.machine.read(address),
+                address
+            ));
+        }
+        let address: u64 = argument
+            .parse()
+            .map_err(|_| format!("{} is not a ?variable or an address", argument))?;
+        let mut output = format!("[{}] = {}", address, self.machine.read(address));
+        if let Some(name) = self.debug_info.variable_at(address) {
+            write!(output, " ( ?{} )", name).unwrap();
+        }
+        Ok(output)
+    }
+
+    fn variables(&self) -> String {
+        let mut variables: Vec<(&String, &u64)> = self.debug_info.variables.iter().collect();
+        variables.sort_by_key(|(_, address)| **address);
+        if variables.is_empty() {
+            return "no variables".to_owned();
+        }
+        variables
+            .iter()
+            .map(|(name, address)| format!("?{} = {}", name, self.machine.read(**address)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn variable_address(&self, name: &str) -> Result<u64, String> {
+        self.debug_info
+            .variables
+            .get(name)
+            .copied()
+            .ok_or(format!("variable {} does not exist", name))
+    }
+
+    fn next_number(&mut self) -> usize {
+        self.next_number += 1;
+        self.next_number - 1
+    }
+
+    /// source line of the instruction that runs next
+    fn current_line(&self) -> Option<usize> {
+        let source = self.debug_info.source_of(self.machine.program_counter)?;
+        self.ast_lines.get(source).copied()
+    }
+
+    fn location(&self) -> String {
+        match &self.state {
+            Stop::Exited { code } => return format!("program exited with code {}", code),
+            Stop::Error(err) => return format!("program stopped: {}", err),
+            Stop::Running => {}
+        }
+        let instruction = match self.machine.current_instruction() {
+            Ok(instruction) => format!("{:?}", instruction),
+            Err(err) => err.to_string(),
+        };
+        let address = self.machine.instruction_address();
+        match self.current_line() {
+            Some(line) => format!(
+                "line {}: {}\n    [{}] {}",
+                line,
+                self.source
+                    .get(line - 1)
+                    .map(|source| source.trim())
+                    .unwrap_or_default(),
+                address,
+                instruction
+            ),
+            None => format!("[{}] {}", address, instruction),
+        }
+    }
+}
+
+fn count(argument: Option<&str>) -> Result<u64, String> {
+    match argument {
+        Some(argument) => argument
+            .parse()
+            .map_err(|_| format!("{} is not a number", argument)),
+        None => Ok(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        code_gen::{generate_code_with_debug_info, CompileOptions},
+        parsing::parse::parse_string_with_lines,
+    };
+
+    fn debugger(program: &str) -> Debugger {
+        let (parsed, ast_lines) = parse_string_with_lines(program.to_owned());
+        let (raw_instructions, debug_info) =
+            generate_code_with_debug_info(parsed, &ast_lines, &CompileOptions::default()).unwrap();
+        Debugger::new(
+            program,
+            ast_lines,
+            &raw_instructions,
+            debug_info,
+            Target::default(),
+        )
+    }
+
+    const PROGRAM: &str =
+        "DEF ?i 0\nDEF ?one 1\nDEF ?three 3\n:loop\nADD ?i ?one ?i\nJNE ?i ?three :loop\nEXIT ?i\n";
+
+    #[test]
+    fn steps_by_source_line() {
+        let mut debugger = debugger(PROGRAM);
+        assert!(debugger.location().starts_with("line 1: DEF ?i 0"));
+        assert!(debugger.command("step").starts_with("line 2: DEF ?one 1"));
+        assert!(debugger
+            .command("step 2")
+            .starts_with("line 5: ADD ?i ?one ?i"));
+        assert!(debugger
+            .command("stepi")
+            .starts_with("line 5: ADD ?i ?one ?i"));
+        assert_eq!(debugger.command("print ?i"), "?i = 0 ( address 56 )");
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_watchpoints() {
+        let mut debugger = debugger(PROGRAM);
+        assert_eq!(debugger.command("break 7"), "breakpoint 1 at 7");
+        assert_eq!(debugger.command("watch ?i"), "watchpoint 2 on ?i");
+        assert!(debugger
+            .command("continue")
+            .starts_with("?i changed from 0 to 1\nline 6"));
+        assert_eq!(debugger.command("delete 1"), "deleted breakpoint 1");
+        assert_eq!(debugger.command("delete 2"), "deleted watchpoint 2");
+        assert_eq!(debugger.command("break :loop"), "breakpoint 3 at :loop");
+        assert!(debugger
+            .command("continue")
+            .starts_with("breakpoint :loop\nline 5"));
+        assert_eq!(debugger.command("print ?i"), "?i = 1 ( address 56 )");
+        assert_eq!(debugger.command("info"), "breakpoint 3 at :loop");
+        assert_eq!(debugger.command("delete 3"), "deleted breakpoint 3");
+        assert_eq!(debugger.command("continue"), "program exited with code 3");
+        assert_eq!(debugger.command("vars"), "?i = 3\n?one = 1\n?three = 3");
+    }
+}
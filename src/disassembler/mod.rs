@@ -0,0 +1,185 @@
+use std::fmt::Write as _;
+
+use crate::{
+    code_gen::{
+        object::{ObjectFile, RelocationKind, SymbolKind},
+        RawInstructions,
+    },
+    target::InstructionSet,
+};
+
+/// reads the cells of a program, one number per line like the compiler writes them
+pub fn parse_program(
+    program: &str,
+    instruction_set: &InstructionSet,
+) -> Result<Vec<RawInstructions>, String> {
+    let mut cells = vec![];
+    for (line_num, line) in program.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let cell = line
+            .parse::<u64>()
+            .map_err(|_| format!("line {}: {} is not a number", line_num + 1, line))?;
+        cells.push(cell);
+    }
+    let width = instruction_set.cell_width as usize;
+    if cells.len() % width != 0 {
+        return Err(format!(
+            "the program has {} cells, which is not a whole number of {} cell instructions",
+            cells.len(),
+            width
+        ));
+    }
+    cells
+        .chunks(width)
+        .enumerate()
+        .map(|(instruction, cells)| {
+            instruction_set.decode(cells).ok_or_else(|| {
+                format!(
+                    "instruction {} at address {} has unknown opcode {}",
+                    instruction,
+                    instruction_set.address(instruction),
+                    cells[instruction_set.opcode_slot as usize]
+                )
+            })
+        })
+        .collect()
+}
+
+/// the operands the instruction uses
+fn used_operands(instruction: &RawInstructions) -> usize {
+    let mut instruction = instruction.clone();
+    (1..=3)
+        .filter(|operand| instruction.operand_mut(*operand).is_some())
+        .count()
+}
+
+fn write_instruction(string: &mut String, address: u64, name: &str, operands: &[String]) {
+    writeln!(string, "{:>6}: {} {}", address, name, operands.join(" ")).unwrap();
+}
+
+/// one line per instruction with the address of its first cell, like `    16: COPY 28 13`
+pub fn disassemble_program(
+    instructions: &[RawInstructions],
+    code_origin: u64,
+    instruction_set: &InstructionSet,
+) -> String {
+    let mut string = String::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        let operands: Vec<String> = instruction.operands()[..used_operands(instruction)]
+            .iter()
+            .map(u64::to_string)
+            .collect();
+        let address = code_origin + instruction_set.address(index);
+        write_instruction(&mut string, address, instruction.name(), &operands);
+    }
+    string
+}
+
+/// like disassemble_program for an object at address 0, operands the linker moves show
+/// what they point at and exported labels are shown before the code they point at
+pub fn disassemble_object(object: &ObjectFile, instruction_set: &InstructionSet) -> String {
+    let mut string = String::new();
+    writeln!(string, "# ram {}", object.ram_size).unwrap();
+    if let Some(origin) = object.origin {
+        writeln!(string, "# origin {}", origin).unwrap();
+    }
+    for (name, address) in &object.fixed_variables {
+        writeln!(string, "# ?{} is at {}", name, address).unwrap();
+    }
+    for symbol in &object.symbols {
+        match symbol.kind {
+            SymbolKind::Variable => {
+                writeln!(string, "# exports ?{} at ram+{}", symbol.name, symbol.value).unwrap()
+            }
+            SymbolKind::Fixed => {
+                writeln!(string, "# exports ?{} at {}", symbol.name, symbol.value).unwrap()
+            }
+            SymbolKind::Label => {}
+        }
+    }
+    let labels = |instruction: usize| {
+        object
+            .symbols
+            .iter()
+            .filter(move |symbol| {
+                symbol.kind == SymbolKind::Label
+                    && instruction_set.instruction_at(symbol.value) == Some(instruction)
+            })
+            .map(|symbol| symbol.name.as_str())
+    };
+    for (index, instruction) in object.raw_instructions.iter().enumerate() {
+        for label in labels(index) {
+            writeln!(string, ":{}", label).unwrap();
+        }
+        let operands: Vec<String> = instruction.operands()[..used_operands(instruction)]
+            .iter()
+            .enumerate()
+            .map(|(operand, value)| {
+                let relocation = object.relocations.iter().find(|relocation| {
+                    relocation.instruction == index && relocation.operand == operand + 1
+                });
+                match relocation.map(|relocation| &relocation.kind) {
+                    None | Some(RelocationKind::Absolute) => value.to_string(),
+                    Some(RelocationKind::Code) => format!("code+{}", value),
+                    Some(RelocationKind::Ram) => format!("ram+{}", value),
+                    Some(RelocationKind::Label(name)) => format!(":{}", name),
+                    Some(RelocationKind::Variable(name)) => format!("?{}", name),
+                }
+            })
+            .collect();
+        let address = instruction_set.address(index);
+        write_instruction(&mut string, address, instruction.name(), &operands);
+    }
+    for label in labels(object.raw_instructions.len()) {
+        writeln!(string, ":{}", label).unwrap();
+    }
+    string
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        code_gen::{compile_object, generate_code, raw_instructions_to_string, CompileOptions},
+        parsing::parse::parse_string,
+    };
+
+    const PROGRAM: &str = "MODULE lib\nDEF ?a 7\n:start\nEXIT ?a\nEXPORT :start\nENDMODULE\n";
+
+    #[test]
+    fn programs_turn_back_into_their_instructions() {
+        let instruction_set = InstructionSet::default();
+        let code = generate_code(parse_string(PROGRAM.to_owned())).unwrap();
+        let cells = raw_instructions_to_string(code.clone(), &instruction_set);
+        assert_eq!(parse_program(&cells, &instruction_set), Ok(code));
+        let code = parse_program(&cells, &instruction_set).unwrap();
+        assert_eq!(
+            disassemble_program(&code, 0, &instruction_set),
+            "     0: ADD 7 0\n     4: WRITE 12\n     8: EXIT 12\n"
+        );
+        assert_eq!(
+            parse_program("101\n7\n0\n", &instruction_set),
+            Err(
+                "the program has 3 cells, which is not a whole number of 4 cell instructions"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn objects_show_their_relocations_and_labels() {
+        let object = compile_object(
+            parse_string(PROGRAM.to_owned()),
+            &[],
+            &CompileOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            disassemble_object(&object, &InstructionSet::default()),
+            "# ram 1\n     0: ADD 7 0\n     4: WRITE ram+0\n:lib::start\n     8: EXIT ram+0\n"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::{collections::BTreeMap, fmt::Write as _};
+
+use crate::code_gen::debug_info::DebugInfo;
+
+use super::profile::Profile;
+
+/// how often every source line that has code started running,
+/// lines with more than one instruction count the most run one
+pub fn line_hits(
+    profile: &Profile,
+    debug_info: &DebugInfo,
+    ast_lines: &[usize],
+) -> BTreeMap<usize, u64> {
+    let mut hits: BTreeMap<usize, u64> = BTreeMap::new();
+    for (source, line) in ast_lines.iter().enumerate() {
+        let Some(instruction) = debug_info
+            .start_of(source)
+            .and_then(|program_counter| debug_info.instruction_at(program_counter))
+        else {
+            continue;
+        };
+        let count = profile.counts.get(instruction).copied().unwrap_or(0);
+        let line_hits = hits.entry(*line).or_insert(0);
+        *line_hits = (*line_hits).max(count);
+    }
+    hits
+}
+
+/// coverage of one source file in the LCOV format
+pub fn lcov_record(
+    source_file: &str,
+    profile: &Profile,
+    debug_info: &DebugInfo,
+    ast_lines: &[usize],
+) -> String {
+    let hits = line_hits(profile, debug_info, ast_lines);
+    let mut record = String::new();
+    writeln!(record, "TN:").unwrap();
+    writeln!(record, "SF:{}", source_file).unwrap();
+    for (line, count) in &hits {
+        writeln!(record, "DA:{},{}", line, count).unwrap();
+    }
+    writeln!(record, "LF:{}", hits.len()).unwrap();
+    writeln!(
+        record,
+        "LH:{}",
+        hits.values().filter(|count| **count > 0).count()
+    )
+    .unwrap();
+    writeln!(record, "end_of_record").unwrap();
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        code_gen::{generate_code_with_debug_info, CompileOptions},
+        emulator::{profile::run_profiled, Machine},
+        parsing::parse::parse_string_with_lines,
+    };
+
+    #[test]
+    fn records_hits_per_line() {
+        let program = "DEF ?i 0\nDEF ?one 1\nDEF ?three 3\n:loop\nADD ?i ?one ?i\nJNE ?i ?three :loop\nJE ?i ?three :done\n# never runs\nEXIT ?one\n:done\nEXIT ?i\n";
+        let (parsed, ast_lines) = parse_string_with_lines(program.to_owned());
+        let (raw_instructions, debug_info) =
+            generate_code_with_debug_info(parsed, &ast_lines, &CompileOptions::default()).unwrap();
+        let mut machine = Machine::new(&raw_instructions);
+        let mut profile = Profile::default();
+        run_profiled(&mut machine, 1000, &mut profile).unwrap();
+        assert_eq!(
+            lcov_record("loop.ass", &profile, &debug_info, &ast_lines),
+            "TN:\nSF:loop.ass\nDA:1,1\nDA:2,1\nDA:3,1\nDA:5,3\nDA:6,3\nDA:7,1\nDA:9,0\nDA:11,1\nLF:8\nLH:7\nend_of_record\n"
+        );
+    }
+}
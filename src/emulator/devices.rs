@@ -0,0 +1,205 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, BufRead, Write},
+    rc::Rc,
+};
+
+use super::Yeet;
+
+/// something on the SECS ( Serial External Communication System ) that YEET writes to and YOINK reads from
+pub trait SecsDevice {
+    fn yeet(&mut self, yeet: &Yeet) -> io::Result<()>;
+    /// answers a YOINK, devices that can only be written to fail
+    fn yoink(&mut self, _instruction: u64) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the device can not be read from",
+        ))
+    }
+}
+
+/// prints the data of every YEET to stdout, one number per line,
+/// and reads a number from stdin for every YOINK
+pub struct Console;
+
+impl SecsDevice for Console {
+    fn yeet(&mut self, yeet: &Yeet) -> io::Result<()> {
+        writeln!(io::stdout(), "{}", yeet.data)
+    }
+    fn yoink(&mut self, _instruction: u64) -> io::Result<u64> {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        line.trim().parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a number", line.trim()),
+            )
+        })
+    }
+}
+
+/// writes every YEET to a file as `instruction data` lines
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    pub fn create(path: &str) -> io::Result<FileSink> {
+        Ok(FileSink {
+            file: File::create(path)?,
+        })
+    }
+}
+
+impl SecsDevice for FileSink {
+    fn yeet(&mut self, yeet: &Yeet) -> io::Result<()> {
+        writeln!(self.file, "{} {}", yeet.instruction, yeet.data)
+    }
+}
+
+/// keeps every YEET in memory and answers YOINKs with queued input,
+/// clones share what they recorded
+#[derive(Clone, Default)]
+pub struct Recorder {
+    yeets: Rc<RefCell<Vec<Yeet>>>,
+    input: Rc<RefCell<VecDeque<u64>>>,
+}
+
+impl Recorder {
+    pub fn yeets(&self) -> Vec<Yeet> {
+        self.yeets.borrow().clone()
+    }
+    /// the next YOINK gets the first value that was queued, YOINKs without input get 0
+    pub fn queue_input(&self, value: u64) {
+        self.input.borrow_mut().push_back(value);
+    }
+}
+
+impl SecsDevice for Recorder {
+    fn yeet(&mut self, yeet: &Yeet) -> io::Result<()> {
+        self.yeets.borrow_mut().push(yeet.clone());
+        Ok(())
+    }
+    fn yoink(&mut self, _instruction: u64) -> io::Result<u64> {
+        Ok(self.input.borrow_mut().pop_front().unwrap_or(0))
+    }
+}
+
+/// devices by device ID
+pub type Devices = HashMap<u64, Box<dyn SecsDevice>>;
+
+/// reads a device config, every line is a device ID and what is connected to it
+/// ```text
+/// # comments start with #
+/// 1 console
+/// 2 file output.txt
+/// 3 recorder
+/// ```
+pub fn parse_device_config(config: &str) -> Result<Devices, String> {
+    let mut devices: Devices = HashMap::new();
+    for (line_number, line) in config.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("line {}: {}", line_number + 1, message);
+        let mut words = line.split_whitespace();
+        let id = words.next().unwrap();
+        let id: u64 = id
+            .parse()
+            .map_err(|_| error(format!("{} is not a device ID", id)))?;
+        let device: Box<dyn SecsDevice> = match (words.next(), words.next()) {
+            (Some("console"), None) => Box::new(Console),
+            (Some("recorder"), None) => Box::new(Recorder::default()),
+            (Some("file"), Some(path)) => Box::new(
+                FileSink::create(path)
+                    .map_err(|err| error(format!("could not create {}: {}", path, err)))?,
+            ),
+            _ => return Err(error(format!("unknown device {}", line))),
+        };
+        if words.next().is_some() {
+            return Err(error(format!("unknown device {}", line)));
+        }
+        if devices.insert(id, device).is_some() {
+            return Err(error(format!("device {} is connected twice", id)));
+        }
+    }
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        code_gen::generate_code,
+        emulator::{EmulatorError, Machine},
+        parsing::parse::parse_string,
+    };
+
+    #[test]
+    fn yeets_go_to_the_device_with_their_id() {
+        let program = "DEF ?one 1\nDEF ?two 2\nDEF ?address 0\nYEET ?one ?two ?address\nYEET ?two ?one ?address\nEXIT ?one\n";
+        let mut machine = Machine::new(&generate_code(parse_string(program.to_owned())).unwrap());
+        let recorder = Recorder::default();
+        machine.attach(2, Box::new(recorder.clone()));
+        machine.run(100).unwrap();
+        // ?address is 0 so the data is the opcode of the first instruction
+        assert_eq!(
+            recorder.yeets(),
+            vec![Yeet {
+                device: 2,
+                instruction: 1,
+                data: 101,
+            }]
+        );
+        assert_eq!(machine.yeets.len(), 2);
+    }
+
+    #[test]
+    fn yoinks_read_from_the_device() {
+        let program = "DEF ?one 1\nDEF ?two 2\nDEF ?value 0\nYOINK ?two ?one ?value\nYOINK ?two ?one ?value\nEXIT ?value\n";
+        let mut machine = Machine::new(&generate_code(parse_string(program.to_owned())).unwrap());
+        let recorder = Recorder::default();
+        recorder.queue_input(7);
+        recorder.queue_input(9);
+        machine.attach(2, Box::new(recorder));
+        assert_eq!(machine.run(100), Ok(9));
+    }
+
+    #[test]
+    fn file_sinks_can_not_be_read_from() {
+        let program = "DEF ?one 1\nDEF ?value 0\nYOINK ?one ?one ?value\nEXIT ?value\n";
+        let mut machine = Machine::new(&generate_code(parse_string(program.to_owned())).unwrap());
+        let path = std::env::temp_dir().join(format!("ass-sink-{}.txt", std::process::id()));
+        machine.attach(
+            1,
+            Box::new(FileSink::create(path.to_str().unwrap()).unwrap()),
+        );
+        assert_eq!(
+            machine.run(100),
+            Err(EmulatorError::DeviceError {
+                device: 1,
+                message: "the device can not be read from".to_owned(),
+            })
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn parses_device_config() {
+        let devices = parse_device_config("# devices\n1 console\n\n3 recorder\n").unwrap();
+        let mut ids: Vec<&u64> = devices.keys().collect();
+        ids.sort();
+        assert_eq!(ids, vec![&1, &3]);
+        assert_eq!(
+            parse_device_config("1 console\n1 recorder\n").err(),
+            Some("line 2: device 1 is connected twice".to_owned())
+        );
+        assert_eq!(
+            parse_device_config("1 printer\n").err(),
+            Some("line 1: unknown device 1 printer".to_owned())
+        );
+    }
+}
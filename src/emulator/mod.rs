@@ -0,0 +1,259 @@
+#![allow(dead_code)]
+
+use std::fmt::Display;
+
+use crate::{
+    code_gen::{ir::Arithmetic, RawInstructions},
+    target::{ArithmeticError, Target},
+};
+
+use self::devices::{Devices, SecsDevice};
+
+pub mod coverage;
+pub mod devices;
+pub mod profile;
+pub mod trace;
+
+/// runs compiled programs the way the target machine does
+///
+/// the program is loaded at its code origin and every instruction takes the cells
+/// its instruction set says, 4 by default with the opcode followed by the a, b and c operand.
+/// like the labels generate_code makes, the program counter points one
+/// instruction past the instruction being run, so it starts at the second instruction
+pub struct Machine {
+    pub memory: Vec<u64>,
+    /// address of the first instruction
+    pub code_origin: u64,
+    pub program_counter: u64,
+    /// result of the last ADD, SUB, MUL or DIV
+    pub accumulator: u64,
+    /// everything written with YEET in order
+    pub yeets: Vec<Yeet>,
+    pub steps: u64,
+    /// YEETs to other device IDs are only kept in yeets
+    pub devices: Devices,
+    /// how big words are and what happens when arithmetic does not fit in one
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Yeet {
+    pub device: u64,
+    pub instruction: u64,
+    pub data: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmulatorError {
+    InvalidOpcode {
+        address: u64,
+        opcode: u64,
+    },
+    DivisionByZero {
+        address: u64,
+    },
+    /// only when the target traps on overflows
+    Overflow {
+        address: u64,
+    },
+    StepLimitReached {
+        steps: u64,
+    },
+    DeviceError {
+        device: u64,
+        message: String,
+    },
+}
+
+impl Display for EmulatorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmulatorError::InvalidOpcode { address, opcode } => {
+                write!(f, "invalid opcode {} at address {}", opcode, address)
+            }
+            EmulatorError::DivisionByZero { address } => {
+                write!(f, "division by zero at address {}", address)
+            }
+            EmulatorError::Overflow { address } => {
+                write!(f, "arithmetic overflow at address {}", address)
+            }
+            EmulatorError::StepLimitReached { steps } => {
+                write!(f, "program did not exit after {} steps", steps)
+            }
+            EmulatorError::DeviceError { device, message } => {
+                write!(f, "device {} failed: {}", device, message)
+            }
+        }
+    }
+}
+
+/// what happened after running one instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepResult {
+    Running,
+    Exited { code: u64 },
+}
+
+impl Machine {
+    pub fn new(raw_instructions: &[RawInstructions]) -> Machine {
+        Machine::for_target(raw_instructions, 0, Target::default())
+    }
+
+    /// loads the program at the code origin with the opcodes and cells of the target
+    pub fn for_target(
+        raw_instructions: &[RawInstructions],
+        code_origin: u64,
+        target: Target,
+    ) -> Machine {
+        let mut memory = vec![0; code_origin as usize];
+        memory.extend(
+            raw_instructions
+                .iter()
+                .flat_map(|instruction| target.instruction_set.encode(instruction)),
+        );
+        Machine {
+            memory,
+            code_origin,
+            program_counter: code_origin + target.instruction_set.jump_address(0),
+            accumulator: 0,
+            yeets: vec![],
+            steps: 0,
+            devices: Devices::new(),
+            target,
+        }
+    }
+
+    /// connects a device to a device ID, replacing the one that was there
+    pub fn attach(&mut self, device_id: u64, device: Box<dyn SecsDevice>) {
+        self.devices.insert(device_id, device);
+    }
+
+    /// reads a cell, memory that was never written is 0
+    pub fn read(&self, address: u64) -> u64 {
+        self.memory.get(address as usize).copied().unwrap_or(0)
+    }
+
+    pub fn write(&mut self, address: u64, value: u64) {
+        if address as usize >= self.memory.len() {
+            self.memory.resize(address as usize + 1, 0);
+        }
+        self.memory[address as usize] = value;
+    }
+
+    /// address of the opcode of the instruction that runs next
+    pub fn instruction_address(&self) -> u64 {
+        self.program_counter
+            .wrapping_sub(self.target.instruction_set.cell_width)
+    }
+
+    /// index of the instruction that runs next, none if the program counter is before the code
+    pub fn instruction_index(&self) -> Option<usize> {
+        let offset = self.program_counter.checked_sub(self.code_origin)?;
+        self.target.instruction_set.instruction_at(offset)
+    }
+
+    /// the instruction that runs next
+    pub fn current_instruction(&self) -> Result<RawInstructions, EmulatorError> {
+        let instruction_set = &self.target.instruction_set;
+        let address = self.instruction_address();
+        let cells: Vec<u64> = (0..instruction_set.cell_width)
+            .map(|cell| self.read(address.wrapping_add(cell)))
+            .collect();
+        instruction_set
+            .decode(&cells)
+            .ok_or(EmulatorError::InvalidOpcode {
+                address,
+                opcode: cells[instruction_set.opcode_slot as usize],
+            })
+    }
+
+    pub fn step(&mut self) -> Result<StepResult, EmulatorError> {
+        let address = self.instruction_address();
+        let instruction = self.current_instruction()?;
+        self.steps += 1;
+        self.program_counter += self.target.instruction_set.cell_width;
+        match instruction {
+            RawInstructions::ADD { a, b } => self.calculate(Arithmetic::Add, a, b, address)?,
+            RawInstructions::SUB { a, b } => self.calculate(Arithmetic::Sub, a, b, address)?,
+            RawInstructions::MUL { a, b } => self.calculate(Arithmetic::Mul, a, b, address)?,
+            RawInstructions::DIV { a, b } => self.calculate(Arithmetic::Div, a, b, address)?,
+            RawInstructions::WRITE { a } => self.write(a, self.accumulator),
+            RawInstructions::COPY { a, b } => self.write(b, self.read(a)),
+            RawInstructions::JMP { a } => self.program_counter = a,
+            RawInstructions::Equal { a, b, c } => {
+                if a == b {
+                    self.program_counter = c;
+                }
+            }
+            RawInstructions::NotEqual { a, b, c } => {
+                if a != b {
+                    self.program_counter = c;
+                }
+            }
+            RawInstructions::JHT { a, b, c } => {
+                if self.target.higher_than(a, b) {
+                    self.program_counter = c;
+                }
+            }
+            RawInstructions::EXIT { a } => {
+                return Ok(StepResult::Exited { code: self.read(a) });
+            }
+            RawInstructions::YEET { a, b, c } => {
+                let yeet = Yeet {
+                    device: a,
+                    instruction: b,
+                    data: self.read(c),
+                };
+                if let Some(device) = self.devices.get_mut(&a) {
+                    device
+                        .yeet(&yeet)
+                        .map_err(|err| EmulatorError::DeviceError {
+                            device: a,
+                            message: err.to_string(),
+                        })?;
+                }
+                self.yeets.push(yeet);
+            }
+            RawInstructions::YOINK { a, b, c } => {
+                let value = match self.devices.get_mut(&a) {
+                    Some(device) => device.yoink(b).map_err(|err| EmulatorError::DeviceError {
+                        device: a,
+                        message: err.to_string(),
+                    })?,
+                    // nothing is connected so there is nothing to read
+                    None => 0,
+                };
+                self.write(c, value);
+            }
+        }
+        Ok(StepResult::Running)
+    }
+
+    /// puts the result in the accumulator like the target does it
+    fn calculate(
+        &mut self,
+        arithmetic: Arithmetic,
+        a: u64,
+        b: u64,
+        address: u64,
+    ) -> Result<(), EmulatorError> {
+        self.accumulator = self
+            .target
+            .calculate(arithmetic, a, b)
+            .map_err(|err| match err {
+                ArithmeticError::DivisionByZero => EmulatorError::DivisionByZero { address },
+                ArithmeticError::Overflow => EmulatorError::Overflow { address },
+            })?;
+        Ok(())
+    }
+
+    /// runs until the program exits and returns the exit code
+    pub fn run(&mut self, step_limit: u64) -> Result<u64, EmulatorError> {
+        while self.steps < step_limit {
+            if let StepResult::Exited { code } = self.step()? {
+                return Ok(code);
+            }
+        }
+        Err(EmulatorError::StepLimitReached { steps: self.steps })
+    }
+}
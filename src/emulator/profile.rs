@@ -0,0 +1,185 @@
+use std::fmt::Write as _;
+
+use crate::code_gen::debug_info::DebugInfo;
+
+use super::{EmulatorError, Machine, StepResult};
+
+/// how often every instruction ran, every instruction takes one cycle
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Profile {
+    /// runs of every instruction by instruction index
+    pub counts: Vec<u64>,
+    pub total_cycles: u64,
+}
+
+impl Profile {
+    fn record(&mut self, instruction: usize) {
+        if instruction >= self.counts.len() {
+            self.counts.resize(instruction + 1, 0);
+        }
+        self.counts[instruction] += 1;
+        self.total_cycles += 1;
+    }
+}
+
+/// runs until the program exits like Machine::run and counts every instruction that ran,
+/// the profile is also filled in when the program fails
+pub fn run_profiled(
+    machine: &mut Machine,
+    step_limit: u64,
+    profile: &mut Profile,
+) -> Result<u64, EmulatorError> {
+    while machine.steps < step_limit {
+        let instruction = machine.instruction_index();
+        let result = machine.step()?;
+        if let Some(instruction) = instruction {
+            profile.record(instruction);
+        }
+        if let StepResult::Exited { code } = result {
+            return Ok(code);
+        }
+    }
+    Err(EmulatorError::StepLimitReached {
+        steps: machine.steps,
+    })
+}
+
+/// cycles per source line, hottest first
+pub fn cycles_per_line(
+    profile: &Profile,
+    debug_info: &DebugInfo,
+    ast_lines: &[usize],
+) -> Vec<(usize, u64)> {
+    let mut lines: Vec<(usize, u64)> = vec![];
+    for (instruction, count) in profile.counts.iter().enumerate() {
+        let Some(line) = debug_info
+            .instruction_sources
+            .get(instruction)
+            .and_then(|source| ast_lines.get(*source))
+        else {
+            continue;
+        };
+        match lines.iter_mut().find(|(existing, _)| existing == line) {
+            Some((_, cycles)) => *cycles += count,
+            None => lines.push((*line, *count)),
+        }
+    }
+    lines.retain(|(_, cycles)| *cycles > 0);
+    lines.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    lines
+}
+
+/// cycles per block of code that starts at a label, hottest first.
+/// code before the first label is in the block `<start>`,
+/// labels at the same address share a block
+pub fn cycles_per_block(profile: &Profile, debug_info: &DebugInfo) -> Vec<(String, u64)> {
+    let mut labels: Vec<(u64, &String)> = debug_info
+        .labels
+        .iter()
+        .map(|(name, address)| (*address, name))
+        .collect();
+    labels.sort();
+    let mut blocks: Vec<(String, u64)> = vec![];
+    for (instruction, count) in profile.counts.iter().enumerate() {
+        let program_counter = debug_info.program_counter_of(instruction);
+        let block_start = labels
+            .iter()
+            .filter(|(address, _)| *address <= program_counter)
+            .map(|(address, _)| *address)
+            .next_back();
+        let name = match block_start {
+            Some(start) => labels
+                .iter()
+                .filter(|(address, _)| *address == start)
+                .map(|(_, name)| format!(":{}", name))
+                .collect::<Vec<String>>()
+                .join(" "),
+            None => "<start>".to_owned(),
+        };
+        match blocks.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, cycles)) => *cycles += count,
+            None => blocks.push((name, *count)),
+        }
+    }
+    blocks.retain(|(_, cycles)| *cycles > 0);
+    blocks.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    blocks
+}
+
+/// the `top` hottest lines, every block and the total cycles
+pub fn profile_report(
+    profile: &Profile,
+    debug_info: &DebugInfo,
+    ast_lines: &[usize],
+    source: &str,
+    top: usize,
+) -> String {
+    let source: Vec<&str> = source.lines().collect();
+    let mut report = String::new();
+    writeln!(report, "total cycles: {}", profile.total_cycles).unwrap();
+    writeln!(report).unwrap();
+    writeln!(report, "hot lines:").unwrap();
+    writeln!(report, "{:>10} {:>6}  source", "cycles", "line").unwrap();
+    for (line, cycles) in cycles_per_line(profile, debug_info, ast_lines)
+        .into_iter()
+        .take(top)
+    {
+        let code = source
+            .get(line - 1)
+            .map(|code| code.trim())
+            .unwrap_or_default();
+        writeln!(report, "{:>10} {:>6}  {}", cycles, line, code).unwrap();
+    }
+    writeln!(report).unwrap();
+    writeln!(report, "blocks:").unwrap();
+    writeln!(report, "{:>10}  block", "cycles").unwrap();
+    for (block, cycles) in cycles_per_block(profile, debug_info) {
+        writeln!(report, "{:>10}  {}", cycles, block).unwrap();
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        code_gen::{generate_code_with_debug_info, CompileOptions},
+        parsing::parse::parse_string_with_lines,
+    };
+
+    const PROGRAM: &str = "DEF ?i 0\nDEF ?one 1\nDEF ?three 3\n:loop\nADD ?i ?one ?i\nJNE ?i ?three :loop\n:done\nEXIT ?i\n";
+
+    fn profile() -> (Profile, DebugInfo, Vec<usize>) {
+        let (parsed, ast_lines) = parse_string_with_lines(PROGRAM.to_owned());
+        let (raw_instructions, debug_info) =
+            generate_code_with_debug_info(parsed, &ast_lines, &CompileOptions::default()).unwrap();
+        let mut machine = Machine::new(&raw_instructions);
+        let mut profile = Profile::default();
+        assert_eq!(run_profiled(&mut machine, 1000, &mut profile), Ok(3));
+        (profile, debug_info, ast_lines)
+    }
+
+    #[test]
+    fn counts_cycles_per_line() {
+        let (profile, debug_info, ast_lines) = profile();
+        // 3 DEFs of 2 instructions, the loop runs 3 times with ADD 4 and JNE 3 and EXIT 1
+        assert_eq!(profile.total_cycles, 6 + 3 * (4 + 3) + 1);
+        assert_eq!(
+            cycles_per_line(&profile, &debug_info, &ast_lines),
+            vec![(5, 12), (6, 9), (1, 2), (2, 2), (3, 2), (8, 1)]
+        );
+    }
+
+    #[test]
+    fn counts_cycles_per_block() {
+        let (profile, debug_info, _) = profile();
+        assert_eq!(
+            cycles_per_block(&profile, &debug_info),
+            vec![
+                (":loop".to_owned(), 21),
+                ("<start>".to_owned(), 6),
+                (":done".to_owned(), 1)
+            ]
+        );
+    }
+}
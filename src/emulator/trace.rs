@@ -0,0 +1,231 @@
+use std::{fmt::Write as _, io};
+
+use crate::code_gen::{debug_info::DebugInfo, RawInstructions};
+
+use super::{EmulatorError, Machine, StepResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum TraceFormat {
+    /// one line per instruction for people to read
+    Text,
+    /// one JSON object per line for other programs to read
+    Json,
+}
+
+/// a memory cell an instruction used
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellChange {
+    pub address: u64,
+    pub before: u64,
+    pub after: u64,
+}
+
+/// everything one executed instruction did
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    /// how many instructions ran before this one
+    pub step: u64,
+    /// program counter before the instruction ran
+    pub program_counter: u64,
+    pub next_program_counter: u64,
+    pub instruction: RawInstructions,
+    /// opcode of the instruction in the instruction set of the machine
+    pub opcode: u64,
+    pub line: Option<usize>,
+    pub accumulator_before: u64,
+    pub accumulator_after: u64,
+    /// the cells the address operands point at
+    pub cells: Vec<CellChange>,
+}
+
+/// memory the instruction reads or writes through its operands,
+/// the other operands are immediates
+fn used_addresses(instruction: &RawInstructions) -> Vec<u64> {
+    match *instruction {
+        RawInstructions::COPY { a, b } => vec![a, b],
+        RawInstructions::WRITE { a } | RawInstructions::EXIT { a } => vec![a],
+        RawInstructions::YEET { c, .. } | RawInstructions::YOINK { c, .. } => vec![c],
+        _ => vec![],
+    }
+}
+
+/// runs one instruction and records what it did,
+/// nothing is recorded for instructions that could not run
+pub fn traced_step(
+    machine: &mut Machine,
+    debug_info: Option<&DebugInfo>,
+    ast_lines: &[usize],
+) -> Result<(StepResult, TraceEntry), EmulatorError> {
+    let program_counter = machine.program_counter;
+    let instruction = machine.current_instruction()?;
+    let addresses = used_addresses(&instruction);
+    let before: Vec<u64> = addresses
+        .iter()
+        .map(|address| machine.read(*address))
+        .collect();
+    let accumulator_before = machine.accumulator;
+    let step = machine.steps;
+    let result = machine.step()?;
+    let cells = addresses
+        .into_iter()
+        .zip(before)
+        .map(|(address, before)| CellChange {
+            address,
+            before,
+            after: machine.read(address),
+        })
+        .collect();
+    let line = debug_info
+        .and_then(|debug_info| debug_info.source_of(program_counter))
+        .and_then(|source| ast_lines.get(source).copied());
+    Ok((
+        result,
+        TraceEntry {
+            step,
+            program_counter,
+            next_program_counter: machine.program_counter,
+            opcode: instruction.opcode(&machine.target.instruction_set),
+            instruction,
+            line,
+            accumulator_before,
+            accumulator_after: machine.accumulator,
+            cells,
+        },
+    ))
+}
+
+/// runs until the program exits like Machine::run and writes every instruction to `output`
+pub fn run_traced(
+    machine: &mut Machine,
+    step_limit: u64,
+    debug_info: Option<&DebugInfo>,
+    ast_lines: &[usize],
+    format: TraceFormat,
+    output: &mut impl io::Write,
+) -> Result<u64, EmulatorError> {
+    while machine.steps < step_limit {
+        let (result, entry) = traced_step(machine, debug_info, ast_lines)?;
+        let line = match format {
+            TraceFormat::Text => trace_entry_to_string(&entry),
+            TraceFormat::Json => trace_entry_to_json(&entry),
+        };
+        writeln!(output, "{}", line).unwrap();
+        if let StepResult::Exited { code } = result {
+            return Ok(code);
+        }
+    }
+    Err(EmulatorError::StepLimitReached {
+        steps: machine.steps,
+    })
+}
+
+pub fn trace_entry_to_string(entry: &TraceEntry) -> String {
+    let mut string = format!("{} pc={}", entry.step, entry.program_counter);
+    if let Some(line) = entry.line {
+        write!(string, " line={}", line).unwrap();
+    }
+    write!(string, " {:?}", entry.instruction).unwrap();
+    for cell in &entry.cells {
+        write!(
+            string,
+            " [{}]={}->{}",
+            cell.address, cell.before, cell.after
+        )
+        .unwrap();
+    }
+    write!(
+        string,
+        " acc={}->{} next_pc={}",
+        entry.accumulator_before, entry.accumulator_after, entry.next_program_counter
+    )
+    .unwrap();
+    string
+}
+
+pub fn trace_entry_to_json(entry: &TraceEntry) -> String {
+    let [a, b, c] = entry.instruction.operands();
+    let line = match entry.line {
+        Some(line) => line.to_string(),
+        None => "null".to_owned(),
+    };
+    let cells = entry
+        .cells
+        .iter()
+        .map(|cell| {
+            format!(
+                "{{\"address\":{},\"before\":{},\"after\":{}}}",
+                cell.address, cell.before, cell.after
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(",");
+    format!(
+        "{{\"step\":{},\"pc\":{},\"line\":{},\"instruction\":{{\"name\":\"{}\",\"opcode\":{},\"a\":{},\"b\":{},\"c\":{}}},\"cells\":[{}],\"accumulator\":{{\"before\":{},\"after\":{}}},\"next_pc\":{}}}",
+        entry.step,
+        entry.program_counter,
+        line,
+        entry.instruction.name(),
+        entry.opcode,
+        a,
+        b,
+        c,
+        cells,
+        entry.accumulator_before,
+        entry.accumulator_after,
+        entry.next_program_counter
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        code_gen::{generate_code_with_debug_info, CompileOptions},
+        parsing::parse::parse_string_with_lines,
+    };
+
+    fn trace(program: &str, format: TraceFormat) -> Vec<String> {
+        let (parsed, ast_lines) = parse_string_with_lines(program.to_owned());
+        let (raw_instructions, debug_info) =
+            generate_code_with_debug_info(parsed, &ast_lines, &CompileOptions::default()).unwrap();
+        let mut machine = Machine::new(&raw_instructions);
+        let mut output = vec![];
+        run_traced(
+            &mut machine,
+            100,
+            Some(&debug_info),
+            &ast_lines,
+            format,
+            &mut output,
+        )
+        .unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn traces_every_instruction() {
+        let trace = trace("DEF ?a 3\n\nEXIT ?a\n", TraceFormat::Text);
+        assert_eq!(
+            trace,
+            vec![
+                "0 pc=4 line=1 ADD { a: 3, b: 0 } acc=0->3 next_pc=8",
+                "1 pc=8 line=1 WRITE { a: 12 } [12]=0->3 acc=3->3 next_pc=12",
+                "2 pc=12 line=3 EXIT { a: 12 } [12]=3->3 acc=3->3 next_pc=16",
+            ]
+        );
+    }
+
+    #[test]
+    fn traces_json_lines() {
+        let trace = trace("DEF ?a 3\nEXIT ?a\n", TraceFormat::Json);
+        assert_eq!(
+            trace[1],
+            "{\"step\":1,\"pc\":8,\"line\":1,\"instruction\":{\"name\":\"WRITE\",\"opcode\":103,\"a\":12,\"b\":0,\"c\":0},\"cells\":[{\"address\":12,\"before\":0,\"after\":3}],\"accumulator\":{\"before\":3,\"after\":3},\"next_pc\":12}"
+        );
+        assert_eq!(trace.len(), 3);
+    }
+}
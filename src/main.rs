@@ -1,43 +1,631 @@
-use std::{fs};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
-use clap::Parser;
-use log::debug;
+use clap::{Parser, Subcommand};
+use log::{debug, error};
 
 use crate::{
-    code_gen::{generate_code, raw_instructions_to_string},
-    parsing::parse::parse_string,
+    code_gen::{
+        compile_object, generate_code_with_debug_info,
+        link::link,
+        object::{is_object_file, object_to_string, parse_object},
+        raw_instructions_to_string, CompileOptions,
+    },
+    debugger::Debugger,
+    disassembler::{disassemble_object, disassemble_program, parse_program},
+    emulator::{
+        coverage::lcov_record,
+        devices::parse_device_config,
+        profile::{profile_report, run_profiled, Profile},
+        trace::{run_traced, TraceFormat},
+        Machine,
+    },
+    parsing::{
+        ast::AssASTTypes, format::format_source, include::expand_includes,
+        parse::parse_string_for_target,
+    },
+    target::{
+        parse_address, parse_instruction_set, parse_memory_map, InstructionSet, MemoryMap,
+        Overflow, Target,
+    },
 };
 mod code_gen;
+mod debugger;
+mod disassembler;
+mod emulator;
 mod parsing;
-#[derive(Parser,Debug)]
+mod target;
+mod test_runner;
+#[derive(Parser, Debug)]
 struct Args {
+    #[command(subcommand)]
+    command: Command,
     /// debug
-    #[arg(short,default_value_t=false)]
+    #[arg(short, global = true, default_value_t = false)]
     debug: bool,
-    /// file to input
-    file_input: String,
-    /// file to output
-    file_output: String
+}
+/// how source files are compiled, the same for every command that compiles them
+#[derive(clap::Args, Debug)]
+struct CompileArgs {
+    /// 0 compiles the program as written, 1 folds constants, 2 also removes instructions that do nothing
+    #[arg(
+        short = 'O',
+        long,
+        value_name = "LEVEL",
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u8).range(0..=2)
+    )]
+    opt_level: u8,
+    /// directory to look for INCLUDE files in when they are not next to the file that includes them
+    #[arg(short = 'I', long = "include", value_name = "DIR")]
+    include_paths: Vec<PathBuf>,
+    /// sets a variable before the program starts like DEF ?NAME VALUE does, VALUE is 1 if left out
+    #[arg(short = 'D', long = "define", value_name = "NAME[=VALUE]")]
+    defines: Vec<String>,
+    #[command(flatten)]
+    target: TargetArgs,
+}
+impl CompileArgs {
+    fn options(&self) -> Result<CompileOptions, u64> {
+        let target = self.target.target()?;
+        let mut defines = vec![];
+        for define in &self.defines {
+            let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+            let name = name.strip_prefix('?').unwrap_or(name);
+            if name.is_empty() {
+                error!("-D {}: the variable needs a name", define);
+                return Err(2);
+            }
+            match target.parse_number(value) {
+                Ok(value) => defines.push((name.to_owned(), value)),
+                Err(err) => {
+                    error!("-D {}: {}", define, err);
+                    return Err(2);
+                }
+            }
+        }
+        Ok(CompileOptions {
+            opt_level: self.opt_level,
+            target,
+            defines,
+        })
+    }
+}
+/// what build writes
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum OutputFormat {
+    /// the cells of a program, one number per line
+    Program,
+    /// an object file to link later
+    Object,
+}
+/// the words of the machine the program is for
+#[derive(clap::Args, Debug)]
+struct TargetArgs {
+    /// bits in a word
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u32).range(1..=64))]
+    word_size: u32,
+    /// words are two's complement numbers, DEF takes negative numbers and JHT compares signed
+    #[arg(long, default_value_t = false)]
+    signed: bool,
+    /// what happens when ADD, SUB, MUL or DIV do not fit in a word
+    #[arg(long, value_enum, default_value_t = Overflow::Wrap)]
+    overflow: Overflow,
+    /// file with the opcodes and cells of the instructions, see the README
+    #[arg(long)]
+    instruction_set: Option<String>,
+    /// linker script with where the code and the RAM are, the options below override it
+    #[arg(long)]
+    memory_map: Option<String>,
+    /// address of the first instruction
+    #[arg(long, value_parser = parse_address)]
+    code_origin: Option<u64>,
+    /// address of the first variable, right after the code by default
+    #[arg(long, value_parser = parse_address)]
+    ram_base: Option<u64>,
+    /// cells there are for variables
+    #[arg(long, value_parser = parse_address)]
+    ram_size: Option<u64>,
+}
+impl TargetArgs {
+    fn target(&self) -> Result<Target, u64> {
+        let instruction_set = match &self.instruction_set {
+            Some(file) => match parse_instruction_set(&fs::read_to_string(file).unwrap()) {
+                Ok(instruction_set) => instruction_set,
+                Err(err) => {
+                    error!("{}: {}", file, err);
+                    return Err(2);
+                }
+            },
+            None => InstructionSet::default(),
+        };
+        let mut memory = match &self.memory_map {
+            Some(file) => match parse_memory_map(&fs::read_to_string(file).unwrap()) {
+                Ok(memory) => memory,
+                Err(err) => {
+                    error!("{}: {}", file, err);
+                    return Err(2);
+                }
+            },
+            None => MemoryMap::default(),
+        };
+        memory.code_origin = self.code_origin.or(memory.code_origin);
+        memory.ram_base = self.ram_base.or(memory.ram_base);
+        memory.ram_size = self.ram_size.or(memory.ram_size);
+        Ok(Target {
+            word_size: self.word_size,
+            signed: self.signed,
+            overflow: self.overflow,
+            instruction_set,
+            memory,
+        })
+    }
+}
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// compiles a program, or an object file to link later
+    Build {
+        /// what to write
+        #[arg(long, value_enum, default_value_t = OutputFormat::Program)]
+        format: OutputFormat,
+        /// file to write to, the input with a .txt or .asso extension by default
+        #[arg(short, long)]
+        output: Option<String>,
+        #[command(flatten)]
+        compile: CompileArgs,
+        /// file to compile
+        file_input: String,
+    },
+    /// compiles programs and shows their errors and warnings without writing anything
+    Check {
+        #[command(flatten)]
+        compile: CompileArgs,
+        /// files to compile
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// runs a program in the emulator
+    Run {
+        /// steps the program may take before it is stopped
+        #[arg(long, default_value_t = 1_000_000)]
+        step_limit: u64,
+        /// log every instruction that runs
+        #[arg(long)]
+        trace: Option<TraceFormat>,
+        /// file to write the trace to instead of stderr
+        #[arg(long, requires = "trace")]
+        trace_output: Option<String>,
+        /// file that says which device is connected to which device ID
+        #[arg(long)]
+        devices: Option<String>,
+        /// count how many cycles every line and label takes
+        #[arg(long, conflicts_with = "trace")]
+        profile: bool,
+        /// amount of lines the profile shows
+        #[arg(long, default_value_t = 10, requires = "profile")]
+        top: usize,
+        /// file to write the LCOV coverage of the program to
+        #[arg(long, conflicts_with = "trace")]
+        coverage: Option<String>,
+        #[command(flatten)]
+        compile: CompileArgs,
+        /// file to run
+        file_input: String,
+    },
+    /// turns a program or an object file back into instructions
+    Disasm {
+        /// file to write to instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+        #[command(flatten)]
+        target: TargetArgs,
+        /// program or object file to disassemble
+        file_input: String,
+    },
+    /// indents blocks and puts one space between words in every .ass file
+    Fmt {
+        /// only list the files that are not formatted and fail if there are any
+        #[arg(long)]
+        check: bool,
+        /// files or directories to format
+        #[arg(default_value = ".")]
+        paths: Vec<String>,
+    },
+    /// compiles and runs every .ass file with `# expect-exit:` or `# expect-yeet:` comments
+    Test {
+        /// steps a program may take before it fails
+        #[arg(long, default_value_t = 1_000_000)]
+        step_limit: u64,
+        /// file to write the LCOV coverage of all tests to
+        #[arg(long)]
+        coverage: Option<String>,
+        #[command(flatten)]
+        compile: CompileArgs,
+        /// files or directories to look for tests in
+        #[arg(default_value = ".")]
+        paths: Vec<String>,
+    },
+    /// links object files made with build --format object into a program,
+    /// the first object is where the program starts
+    Link {
+        /// file to write to
+        #[arg(short, long, required = true)]
+        output: String,
+        #[command(flatten)]
+        target: TargetArgs,
+        /// object files to link
+        #[arg(required = true)]
+        objects: Vec<String>,
+    },
+    /// runs a program in the emulator with a prompt to step through it, type help for the commands
+    Debug {
+        #[command(flatten)]
+        compile: CompileArgs,
+        /// file to debug
+        file_input: String,
+    },
 }
 fn main() -> Result<(), u64> {
-    
     let args = Args::parse();
     if args.debug {
         std::env::set_var("RUST_LOG", "DEBUG");
+    } else if std::env::var_os("RUST_LOG").is_none() {
+        // warnings about the program are shown by default
+        std::env::set_var("RUST_LOG", "WARN");
     }
     pretty_env_logger::init();
-    let input = replace_windows_with_unix(fs::read_to_string(args.file_input).unwrap());
-    debug!("input = {:#?}",input);
-    let parsed_string = parse_string(input);
-    debug!("parsed_string = {:#?}",parsed_string);
-    let generated_code = generate_code(parsed_string);
-    if let Err(_) = generated_code {
+    match args.command {
+        Command::Build {
+            format,
+            output,
+            compile,
+            file_input,
+        } => build(file_input, output, format, &compile),
+        Command::Check { compile, files } => check(&files, &compile),
+        Command::Run {
+            step_limit,
+            trace,
+            trace_output,
+            devices,
+            profile,
+            top,
+            coverage,
+            compile,
+            file_input,
+        } => {
+            let options = RunOptions {
+                step_limit,
+                trace,
+                trace_output,
+                devices,
+                profile: profile.then_some(top),
+                coverage,
+            };
+            run_program(file_input, options, &compile)
+        }
+        Command::Disasm {
+            output,
+            target,
+            file_input,
+        } => disassemble(file_input, output, &target.target()?),
+        Command::Fmt { check, paths } => format_files(&paths, check),
+        Command::Test {
+            step_limit,
+            coverage,
+            compile,
+            paths,
+        } => {
+            let options = compile.options()?;
+            let coverage = coverage.as_deref();
+            if !test_runner::run_tests(
+                &paths,
+                step_limit,
+                coverage,
+                &options,
+                &compile.include_paths,
+            ) {
+                return Err(1);
+            }
+            Ok(())
+        }
+        Command::Link {
+            output,
+            target,
+            objects,
+        } => link_objects(output, objects, &target.target()?),
+        Command::Debug {
+            compile,
+            file_input,
+        } => debug_program(file_input, &compile),
+    }
+}
+
+/// a source file with the files it includes, parsed
+struct Source {
+    /// the file without its includes, the ast lines are lines of it
+    text: String,
+    parsed: Vec<AssASTTypes>,
+    ast_lines: Vec<usize>,
+}
+
+fn parse_source(file: &str, include_paths: &[PathBuf], target: &Target) -> Result<Source, u64> {
+    let text = replace_windows_with_unix(fs::read_to_string(file).unwrap());
+    debug!("input = {:#?}", text);
+    let directory = Path::new(file).parent().unwrap_or(Path::new("."));
+    let expanded = match expand_includes(&text, directory, include_paths) {
+        Ok(expanded) => expanded,
+        Err(err) => {
+            error!("{}: {}", file, err);
+            return Err(2);
+        }
+    };
+    let (parsed, ast_lines) = match parse_string_for_target(expanded.text.clone(), target) {
+        Ok(parsed) => parsed,
+        Err(mut err) => {
+            err.line = expanded.source_line(err.line);
+            error!("{}: {}", file, err);
+            return Err(2);
+        }
+    };
+    debug!("parsed_string = {:#?}", parsed);
+    let ast_lines = ast_lines
+        .into_iter()
+        .map(|line| expanded.source_line(line))
+        .collect();
+    Ok(Source {
+        text,
+        parsed,
+        ast_lines,
+    })
+}
+
+fn build(
+    file_input: String,
+    output: Option<String>,
+    format: OutputFormat,
+    compile: &CompileArgs,
+) -> Result<(), u64> {
+    let options = compile.options()?;
+    let source = parse_source(&file_input, &compile.include_paths, &options.target)?;
+    let object = compile_object(source.parsed, &source.ast_lines, &options);
+    if object.is_err() {
+        return Err(2);
+    }
+    let object = object.unwrap();
+    let built = match format {
+        OutputFormat::Object => {
+            debug!("object = {:#?}", object);
+            object_to_string(&object)
+        }
+        OutputFormat::Program => {
+            let generated_code = link(vec![object], &options.target);
+            if generated_code.is_err() {
+                return Err(2);
+            }
+            debug!("generated_code = {:#?}", generated_code);
+            raw_instructions_to_string(generated_code.unwrap(), &options.target.instruction_set)
+        }
+    };
+    let extension = match format {
+        OutputFormat::Program => "txt",
+        OutputFormat::Object => "asso",
+    };
+    let output = output.unwrap_or_else(|| {
+        let output = Path::new(&file_input).with_extension(extension);
+        output.display().to_string()
+    });
+    println!("done");
+    fs::write(output, built).unwrap();
+    Ok(())
+}
+
+fn check(files: &[String], compile: &CompileArgs) -> Result<(), u64> {
+    let options = compile.options()?;
+    let mut failed = false;
+    for file in files {
+        let source = parse_source(file, &compile.include_paths, &options.target)?;
+        if compile_object(source.parsed, &source.ast_lines, &options).is_err() {
+            failed = true;
+        }
+    }
+    if failed {
         return Err(2);
     }
-    debug!("generated_code = {:#?}",generated_code);
-    let final_product = raw_instructions_to_string(generated_code.unwrap());
     println!("done");
-    fs::write(args.file_output, final_product).unwrap();
+    Ok(())
+}
+
+fn disassemble(file_input: String, output: Option<String>, target: &Target) -> Result<(), u64> {
+    let input = replace_windows_with_unix(fs::read_to_string(&file_input).unwrap());
+    let instruction_set = &target.instruction_set;
+    let listing = if is_object_file(&input) {
+        let object = parse_object(&input);
+        if object.is_err() {
+            return Err(2);
+        }
+        disassemble_object(&object.unwrap(), instruction_set)
+    } else {
+        match parse_program(&input, instruction_set) {
+            Ok(instructions) => disassemble_program(
+                &instructions,
+                target.memory.code_origin.unwrap_or(0),
+                instruction_set,
+            ),
+            Err(err) => {
+                error!("{}: {}", file_input, err);
+                return Err(2);
+            }
+        }
+    };
+    match output {
+        Some(output) => fs::write(output, listing).unwrap(),
+        None => print!("{}", listing),
+    }
+    Ok(())
+}
+
+fn format_files(paths: &[String], check: bool) -> Result<(), u64> {
+    let mut unformatted = false;
+    for file in test_runner::discover_tests(paths) {
+        let source = fs::read_to_string(&file).unwrap();
+        let formatted = format_source(&source);
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", file.display());
+            unformatted = true;
+        } else {
+            fs::write(&file, formatted).unwrap();
+        }
+    }
+    if unformatted {
+        return Err(1);
+    }
+    Ok(())
+}
+
+fn link_objects(output: String, objects: Vec<String>, target: &Target) -> Result<(), u64> {
+    let mut parsed_objects = vec![];
+    for object in objects {
+        let parsed_object = parse_object(&fs::read_to_string(object).unwrap());
+        if parsed_object.is_err() {
+            return Err(2);
+        }
+        parsed_objects.push(parsed_object.unwrap());
+    }
+    let linked_code = link(parsed_objects, target);
+    if linked_code.is_err() {
+        return Err(2);
+    }
+    debug!("linked_code = {:#?}", linked_code);
+    println!("done");
+    fs::write(
+        output,
+        raw_instructions_to_string(linked_code.unwrap(), &target.instruction_set),
+    )
+    .unwrap();
+    Ok(())
+}
+
+struct RunOptions {
+    step_limit: u64,
+    trace: Option<TraceFormat>,
+    trace_output: Option<String>,
+    devices: Option<String>,
+    /// amount of lines to show in the profile
+    profile: Option<usize>,
+    /// file to write the LCOV coverage to
+    coverage: Option<String>,
+}
+
+fn run_program(file_input: String, options: RunOptions, compile: &CompileArgs) -> Result<(), u64> {
+    let RunOptions {
+        step_limit,
+        trace,
+        trace_output,
+        devices,
+        profile,
+        coverage,
+    } = options;
+    let options = compile.options()?;
+    let Source {
+        text: input,
+        parsed,
+        ast_lines,
+    } = parse_source(&file_input, &compile.include_paths, &options.target)?;
+    let generated_code = generate_code_with_debug_info(parsed, &ast_lines, &options);
+    if generated_code.is_err() {
+        return Err(2);
+    }
+    let (raw_instructions, debug_info) = generated_code.unwrap();
+    let mut machine =
+        Machine::for_target(&raw_instructions, debug_info.code_origin, options.target);
+    if let Some(devices) = devices {
+        match parse_device_config(&fs::read_to_string(devices).unwrap()) {
+            Ok(devices) => machine.devices = devices,
+            Err(err) => {
+                error!("{}", err);
+                return Err(2);
+            }
+        }
+    }
+    let mut cycles = Profile::default();
+    let result = match trace {
+        Some(format) => {
+            let mut output: Box<dyn std::io::Write> = match trace_output {
+                Some(file) => Box::new(std::io::BufWriter::new(fs::File::create(file).unwrap())),
+                None => Box::new(std::io::stderr()),
+            };
+            run_traced(
+                &mut machine,
+                step_limit,
+                Some(&debug_info),
+                &ast_lines,
+                format,
+                &mut output,
+            )
+        }
+        None if profile.is_some() || coverage.is_some() => {
+            run_profiled(&mut machine, step_limit, &mut cycles)
+        }
+        None => machine.run(step_limit),
+    };
+    if let Some(top) = profile {
+        print!(
+            "{}",
+            profile_report(&cycles, &debug_info, &ast_lines, &input, top)
+        );
+    }
+    if let Some(coverage) = coverage {
+        fs::write(
+            coverage,
+            lcov_record(&file_input, &cycles, &debug_info, &ast_lines),
+        )
+        .unwrap();
+    }
+    for yeet in &machine.yeets {
+        println!(
+            "yeet dev={} instr={} data={}",
+            yeet.device, yeet.instruction, yeet.data
+        );
+    }
+    match result {
+        Ok(code) => {
+            println!("exited with code {}", code);
+            Ok(())
+        }
+        Err(err) => {
+            error!("{}", err);
+            Err(3)
+        }
+    }
+}
+
+fn debug_program(file_input: String, compile: &CompileArgs) -> Result<(), u64> {
+    let options = compile.options()?;
+    let Source {
+        text: input,
+        parsed,
+        ast_lines,
+    } = parse_source(&file_input, &compile.include_paths, &options.target)?;
+    let generated_code = generate_code_with_debug_info(parsed, &ast_lines, &options);
+    if generated_code.is_err() {
+        return Err(2);
+    }
+    let (raw_instructions, debug_info) = generated_code.unwrap();
+    let mut debugger = Debugger::new(
+        &input,
+        ast_lines,
+        &raw_instructions,
+        debug_info,
+        options.target,
+    );
+    debugger
+        .run_prompt(std::io::stdin().lock(), std::io::stdout())
+        .unwrap();
     Ok(())
 }
 
@@ -1,9 +1,9 @@
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Address(pub String);
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Label(pub String);
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AssASTTypes {
     /// a > b == true jump to c
     JumpIfHigherThan {
@@ -11,6 +11,34 @@ pub enum AssASTTypes {
         b: Address,
         c: Label,
     },
+    /// a < b == true jump to c
+    JumpIfLowerThan {
+        a: Address,
+        b: Address,
+        c: Label,
+    },
+    /// a >= b == true jump to c
+    JumpIfHigherOrEqual {
+        a: Address,
+        b: Address,
+        c: Label,
+    },
+    /// a <= b == true jump to c
+    JumpIfLowerOrEqual {
+        a: Address,
+        b: Address,
+        c: Label,
+    },
+    /// a == 0 jump to b
+    JumpIfZero {
+        a: Address,
+        b: Label,
+    },
+    /// a != 0 jump to b
+    JumpIfNotZero {
+        a: Address,
+        b: Label,
+    },
     /// a/b = c
     Divide {
         a: Address,
@@ -57,11 +85,57 @@ pub enum AssASTTypes {
         b: Address,
         c: Address,
     },
+    /// a & b = c
+    And {
+        a: Address,
+        b: Address,
+        c: Address,
+    },
+    /// a | b = c
+    Or {
+        a: Address,
+        b: Address,
+        c: Address,
+    },
+    /// a ^ b = c
+    Xor {
+        a: Address,
+        b: Address,
+        c: Address,
+    },
+    /// !a = b
+    Not {
+        a: Address,
+        b: Address,
+    },
+    /// a << b = c
+    ShiftLeft {
+        a: Address,
+        b: Address,
+        c: Address,
+    },
+    /// a >> b = c
+    ShiftRight {
+        a: Address,
+        b: Address,
+        c: Address,
+    },
+    /// a % b = c
+    Modulo {
+        a: Address,
+        b: Address,
+        c: Address,
+    },
     /// define a thing
     Define {
         name: Label,
         a: u64,
     },
+    /// gives a name to a fixed address without writing to it
+    DefineAt {
+        name: Label,
+        address: u64,
+    },
     Exit {
         code: Address,
     },
@@ -71,4 +145,19 @@ pub enum AssASTTypes {
         b: Address,
         c: Address,
     },
+    /// read from SECS into c
+    Yoink {
+        a: Address,
+        b: Address,
+        c: Address,
+    },
+    /// everything after this belongs to the module until the module ends
+    ModuleStart(Label),
+    ModuleEnd,
+    /// lets other modules use a symbol of the current module
+    Export(Label),
+    /// lets the current module use an exported symbol without its module name
+    Import(Label),
+    /// the address the code starts at
+    Origin(u64),
 }
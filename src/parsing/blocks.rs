@@ -0,0 +1,311 @@
+use super::{
+    ast::{Address, AssASTTypes, Label},
+    operators::ParsedConditionOperator,
+    parse::{ParseError, ParseErrorType},
+};
+
+/// start of the labels IF and WHILE make, names in the source cant start with it
+pub const GENERATED_LABEL_PREFIX: char = '@';
+
+/// line is the source line the block starts at
+enum Block {
+    If {
+        id: usize,
+        has_else: bool,
+        line: usize,
+    },
+    While {
+        id: usize,
+        line: usize,
+    },
+}
+
+/// keeps track of the IF and WHILE blocks that are still open
+/// and turns them into jumps to generated labels
+///
+/// generated labels start with `@`, local label resolution leaves them alone
+/// so labels inside a block dont split it and they dont change which label
+/// normal local labels belong to
+#[derive(Default)]
+pub struct Blocks {
+    open_blocks: Vec<Block>,
+    next_id: usize,
+}
+
+impl Blocks {
+    /// IF ?a == ?b
+    pub fn start_if(
+        &mut self,
+        condition: ParsedConditionOperator,
+        line: usize,
+    ) -> Vec<AssASTTypes> {
+        let id = self.next_id();
+        self.open_blocks.push(Block::If {
+            id,
+            has_else: false,
+            line,
+        });
+        vec![
+            jump_if(condition, block_label("if", id, "then")),
+            AssASTTypes::Jump {
+                a: block_label("if", id, "else"),
+            },
+            AssASTTypes::LabelDefenition(block_label("if", id, "then")),
+        ]
+    }
+    pub fn start_else(&mut self) -> Result<Vec<AssASTTypes>, ParseErrorType> {
+        let Some(Block::If { id, has_else, .. }) = self.open_blocks.last_mut() else {
+            return Err(ParseErrorType::ElseWithoutIf);
+        };
+        if *has_else {
+            return Err(ParseErrorType::SecondElse);
+        }
+        *has_else = true;
+        Ok(vec![
+            AssASTTypes::Jump {
+                a: block_label("if", *id, "end"),
+            },
+            AssASTTypes::LabelDefenition(block_label("if", *id, "else")),
+        ])
+    }
+    pub fn end_if(&mut self) -> Result<Vec<AssASTTypes>, ParseErrorType> {
+        let Some(Block::If { id, has_else, .. }) = self.open_blocks.pop() else {
+            return Err(ParseErrorType::EndifWithoutIf);
+        };
+        let mut asl = vec![];
+        if !has_else {
+            asl.push(AssASTTypes::LabelDefenition(block_label("if", id, "else")));
+        }
+        asl.push(AssASTTypes::LabelDefenition(block_label("if", id, "end")));
+        Ok(asl)
+    }
+    /// WHILE ?a < ?b
+    pub fn start_while(
+        &mut self,
+        condition: ParsedConditionOperator,
+        line: usize,
+    ) -> Vec<AssASTTypes> {
+        let id = self.next_id();
+        self.open_blocks.push(Block::While { id, line });
+        vec![
+            AssASTTypes::LabelDefenition(block_label("while", id, "start")),
+            jump_if(condition, block_label("while", id, "body")),
+            AssASTTypes::Jump {
+                a: block_label("while", id, "end"),
+            },
+            AssASTTypes::LabelDefenition(block_label("while", id, "body")),
+        ]
+    }
+    pub fn end_while(&mut self) -> Result<Vec<AssASTTypes>, ParseErrorType> {
+        let Some(Block::While { id, .. }) = self.open_blocks.pop() else {
+            return Err(ParseErrorType::EndwhileWithoutWhile);
+        };
+        Ok(vec![
+            AssASTTypes::Jump {
+                a: block_label("while", id, "start"),
+            },
+            AssASTTypes::LabelDefenition(block_label("while", id, "end")),
+        ])
+    }
+    /// an error at the line of the innermost block that was never closed
+    pub fn finish(&self) -> Result<(), ParseError> {
+        let (line, type_of_error) = match self.open_blocks.last() {
+            Some(Block::If { line, .. }) => (*line, ParseErrorType::IfWithoutEndif),
+            Some(Block::While { line, .. }) => (*line, ParseErrorType::WhileWithoutEndwhile),
+            None => return Ok(()),
+        };
+        Err(ParseError {
+            line,
+            type_of_error,
+        })
+    }
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+}
+
+/// labels made by IF and WHILE, also after the module name is added to them
+pub fn is_generated_label(name: &str) -> bool {
+    let name = name.rsplit("::").next().unwrap_or(name);
+    name.starts_with(GENERATED_LABEL_PREFIX)
+}
+
+fn block_label(block: &str, id: usize, part: &str) -> Label {
+    Label(format!(
+        "{}{}{}_{}",
+        GENERATED_LABEL_PREFIX, block, id, part
+    ))
+}
+
+/// jumps to the label if the condition is true
+fn jump_if(condition: ParsedConditionOperator, label: Label) -> AssASTTypes {
+    let a = Address(condition.a_name);
+    let b = Address(condition.b_name);
+    match condition.comparison.as_str() {
+        "==" => AssASTTypes::Equal { a, b, c: label },
+        "!=" => AssASTTypes::NotEqual { a, b, c: label },
+        ">" => AssASTTypes::JumpIfHigherThan { a, b, c: label },
+        "<" => AssASTTypes::JumpIfLowerThan { a, b, c: label },
+        ">=" => AssASTTypes::JumpIfHigherOrEqual { a, b, c: label },
+        "<=" => AssASTTypes::JumpIfLowerOrEqual { a, b, c: label },
+        comparison => panic!("unknown comparison {}", comparison),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        code_gen::generate_code,
+        parsing::parse::{parse_string, parse_string_for_target},
+        target::Target,
+    };
+
+    fn label(name: &str) -> Label {
+        Label(name.to_owned())
+    }
+
+    fn address(name: &str) -> Address {
+        Address(name.to_owned())
+    }
+
+    fn jump(name: &str) -> AssASTTypes {
+        AssASTTypes::Jump { a: label(name) }
+    }
+
+    fn define(name: &str) -> AssASTTypes {
+        AssASTTypes::LabelDefenition(label(name))
+    }
+
+    fn exit(name: &str) -> AssASTTypes {
+        AssASTTypes::Exit {
+            code: address(name),
+        }
+    }
+
+    #[test]
+    fn if_else_becomes_jumps_around_the_branches() {
+        assert_eq!(
+            parse_string("IF ?a == ?b\nEXIT ?a\nELSE\nEXIT ?b\nENDIF\n".to_owned()),
+            vec![
+                AssASTTypes::Equal {
+                    a: address("a"),
+                    b: address("b"),
+                    c: label("@if0_then"),
+                },
+                jump("@if0_else"),
+                define("@if0_then"),
+                exit("a"),
+                jump("@if0_end"),
+                define("@if0_else"),
+                exit("b"),
+                define("@if0_end"),
+            ]
+        );
+    }
+
+    #[test]
+    fn while_jumps_back_to_its_condition() {
+        assert_eq!(
+            parse_string("WHILE ?i <= ?n\nEXIT ?i\nENDWHILE\n".to_owned()),
+            vec![
+                define("@while0_start"),
+                AssASTTypes::JumpIfLowerOrEqual {
+                    a: address("i"),
+                    b: address("n"),
+                    c: label("@while0_body"),
+                },
+                jump("@while0_end"),
+                define("@while0_body"),
+                exit("i"),
+                jump("@while0_start"),
+                define("@while0_end"),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_blocks_get_their_own_labels() {
+        let parsed = parse_string(
+            "WHILE ?i != ?n\nIF ?i > ?n\nEXIT ?i\nENDIF\nIF ?i < ?n\nEXIT ?n\nENDIF\nENDWHILE\n"
+                .to_owned(),
+        );
+        // an IF without ELSE puts its else label at its end
+        assert_eq!(
+            parsed[6..11],
+            [
+                define("@if1_then"),
+                exit("i"),
+                define("@if1_else"),
+                define("@if1_end"),
+                AssASTTypes::JumpIfLowerThan {
+                    a: address("i"),
+                    b: address("n"),
+                    c: label("@if2_then"),
+                },
+            ]
+        );
+        assert_eq!(
+            parsed[parsed.len() - 2..],
+            [jump("@while0_start"), define("@while0_end")]
+        );
+    }
+
+    fn error(program: &str) -> ParseError {
+        parse_string_for_target(program.to_owned(), &Target::default()).unwrap_err()
+    }
+
+    #[test]
+    fn badly_nested_blocks_are_errors_at_their_line() {
+        let error_at = |line, type_of_error| ParseError {
+            line,
+            type_of_error,
+        };
+        assert_eq!(
+            error("WHILE ?a < ?b\nELSE\nENDWHILE\n"),
+            error_at(2, ParseErrorType::ElseWithoutIf)
+        );
+        assert_eq!(
+            error("IF ?a < ?b\nELSE\nELSE\nENDIF\n"),
+            error_at(3, ParseErrorType::SecondElse)
+        );
+        assert_eq!(
+            error("WHILE ?a < ?b\nENDIF\n"),
+            error_at(2, ParseErrorType::EndifWithoutIf)
+        );
+        assert_eq!(
+            error("ENDWHILE\n"),
+            error_at(1, ParseErrorType::EndwhileWithoutWhile)
+        );
+    }
+
+    #[test]
+    fn blocks_have_to_be_closed() {
+        assert_eq!(
+            error("WHILE ?a < ?b\nENDWHILE\n\nIF ?a < ?b\n"),
+            ParseError {
+                line: 4,
+                type_of_error: ParseErrorType::IfWithoutEndif
+            }
+        );
+        assert_eq!(
+            error("WHILE ?a < ?b\n  EXIT ?a\n"),
+            ParseError {
+                line: 1,
+                type_of_error: ParseErrorType::WhileWithoutEndwhile
+            }
+        );
+    }
+
+    #[test]
+    fn global_labels_inside_blocks_do_not_split_them() {
+        let program = "DEF ?i 0\nDEF ?one 1\nDEF ?five 5\nWHILE ?i < ?five\n:body\nADD ?i ?one ?i\nIF ?i == ?five\n:done\nEXIT ?i\nENDIF\nENDWHILE\nEXIT ?one\n";
+        let parsed = parse_string(program.to_owned());
+        assert_eq!(
+            parsed[parsed.len() - 3..],
+            [jump("@while0_start"), define("@while0_end"), exit("one")]
+        );
+        assert!(generate_code(parsed).is_ok());
+    }
+}
@@ -0,0 +1,62 @@
+/// spaces of indentation for every block a line is in
+const INDENT: usize = 4;
+
+/// formats a program: one space between words, blocks indented by 4 spaces,
+/// comments kept where they are and at most one empty line in a row
+pub fn format_source(source: &str) -> String {
+    let mut formatted = String::new();
+    let mut depth: usize = 0;
+    let mut empty_lines = 0;
+    for line in source.lines() {
+        let (code, comment) = match line.find('#') {
+            Some(start) => (&line[..start], Some(line[start..].trim_end())),
+            None => (line, None),
+        };
+        let words: Vec<&str> = code.split_whitespace().collect();
+        if words.is_empty() && comment.is_none() {
+            empty_lines += 1;
+            continue;
+        }
+        if !formatted.is_empty() && empty_lines > 0 {
+            formatted.push('\n');
+        }
+        empty_lines = 0;
+        let (closes, opens) = match words.first().copied() {
+            Some("IF" | "WHILE" | "MODULE") => (false, true),
+            Some("ELSE") => (true, true),
+            Some("ENDIF" | "ENDWHILE" | "ENDMODULE") => (true, false),
+            _ => (false, false),
+        };
+        if closes {
+            depth = depth.saturating_sub(1);
+        }
+        formatted.push_str(&" ".repeat(depth * INDENT));
+        formatted.push_str(&words.join(" "));
+        if let Some(comment) = comment {
+            if !words.is_empty() {
+                formatted.push(' ');
+            }
+            formatted.push_str(comment);
+        }
+        formatted.push('\n');
+        if opens {
+            depth += 1;
+        }
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indents_blocks_and_spaces_words() {
+        let source = "\n# count to 3\nDEF  ?i 0\n\n\n\tWHILE ?i < ?three\nIF ?i == ?one # one\n  YEET ?dev ?i ?address\n ELSE\nADD ?i ?one ?i   \nENDIF\n      ENDWHILE\n\n";
+        assert_eq!(
+            format_source(source),
+            "# count to 3\nDEF ?i 0\n\nWHILE ?i < ?three\n    IF ?i == ?one # one\n        YEET ?dev ?i ?address\n    ELSE\n        ADD ?i ?one ?i\n    ENDIF\nENDWHILE\n"
+        );
+        assert_eq!(format_source(&format_source(source)), format_source(source));
+    }
+}
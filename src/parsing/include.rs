@@ -0,0 +1,134 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// a program with the files it includes pasted in
+#[derive(Debug, PartialEq)]
+pub struct Expanded {
+    pub text: String,
+    /// line in the outermost file of every line of the text,
+    /// lines of an included file get the line of its INCLUDE
+    pub lines: Vec<usize>,
+}
+
+impl Expanded {
+    /// turns lines of the text, like the ast lines of the parser, into lines of the outermost file
+    pub fn source_line(&self, line: usize) -> usize {
+        line.checked_sub(1)
+            .and_then(|index| self.lines.get(index))
+            .copied()
+            .unwrap_or(line)
+    }
+}
+
+/// replaces every `INCLUDE file.ass` line with the lines of the file.
+/// files are looked for next to the file that includes them and then in the include paths
+pub fn expand_includes(
+    source: &str,
+    directory: &Path,
+    include_paths: &[PathBuf],
+) -> Result<Expanded, String> {
+    let mut expanded = Expanded {
+        text: String::new(),
+        lines: vec![],
+    };
+    expand(
+        source,
+        directory,
+        include_paths,
+        None,
+        &mut vec![],
+        &mut expanded,
+    )?;
+    Ok(expanded)
+}
+
+/// the file an INCLUDE line names, none for other lines
+fn included_file(line: &str) -> Option<&str> {
+    let code = line.split('#').next().unwrap_or_default();
+    match code.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["INCLUDE", file] => Some(file),
+        _ => None,
+    }
+}
+
+fn expand(
+    source: &str,
+    directory: &Path,
+    include_paths: &[PathBuf],
+    // line of the INCLUDE in the outermost file
+    include_line: Option<usize>,
+    // files that are being included, to find files that include themselves
+    including: &mut Vec<PathBuf>,
+    expanded: &mut Expanded,
+) -> Result<(), String> {
+    for (index, line) in source.lines().enumerate() {
+        let line_number = include_line.unwrap_or(index + 1);
+        let Some(file) = included_file(line) else {
+            expanded.text.push_str(line);
+            expanded.text.push('\n');
+            expanded.lines.push(line_number);
+            continue;
+        };
+        let path = std::iter::once(directory)
+            .chain(include_paths.iter().map(PathBuf::as_path))
+            .map(|directory| directory.join(file))
+            .find(|path| path.is_file())
+            .ok_or_else(|| format!("INCLUDE {}: file not found", file))?;
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if including.contains(&canonical) {
+            return Err(format!("INCLUDE {}: the file includes itself", file));
+        }
+        let included = fs::read_to_string(&path)
+            .map_err(|err| format!("INCLUDE {}: {}", file, err))?
+            .replace('\r', "\n");
+        including.push(canonical);
+        expand(
+            &included,
+            path.parent().unwrap_or(directory),
+            include_paths,
+            Some(line_number),
+            including,
+            expanded,
+        )?;
+        including.pop();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn included_lines_come_from_the_include() {
+        let directory = env::temp_dir().join(format!("ass-include-{}", std::process::id()));
+        let library = directory.join("lib");
+        fs::create_dir_all(&library).unwrap();
+        fs::write(library.join("two.ass"), "DEF ?two 2\nINCLUDE one.ass\n").unwrap();
+        fs::write(library.join("one.ass"), "DEF ?one 1\n").unwrap();
+        fs::write(directory.join("self.ass"), "INCLUDE self.ass\n").unwrap();
+
+        let source = "DEF ?zero 0\nINCLUDE two.ass # numbers\nEXIT ?two\n";
+        let expanded = expand_includes(source, &directory, std::slice::from_ref(&library)).unwrap();
+        assert_eq!(
+            expanded.text,
+            "DEF ?zero 0\nDEF ?two 2\nDEF ?one 1\nEXIT ?two\n"
+        );
+        assert_eq!(expanded.lines, vec![1, 2, 2, 3]);
+        assert_eq!(expanded.source_line(4), 3);
+
+        assert_eq!(
+            expand_includes("INCLUDE two.ass\n", &directory, &[]),
+            Err("INCLUDE two.ass: file not found".to_owned())
+        );
+        assert_eq!(
+            expand_includes("INCLUDE self.ass\n", &directory, &[]),
+            Err("INCLUDE self.ass: the file includes itself".to_owned())
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use super::{
+    ast::{AssASTTypes, Label},
+    blocks::GENERATED_LABEL_PREFIX,
+};
+
+/// turns local labels into normal labels
+///
+/// `.loop` belongs to the last global label before it and becomes `<global>.loop`.
+/// numeric labels like `1` can be defined as often as you want, `1b` jumps to the
+/// closest `1` before the jump and `1f` to the closest `1` after it.
+/// references without a matching label are not changed and neither are the labels IF and WHILE make
+pub fn resolve_local_labels(parsed_types: Vec<AssASTTypes>) -> Vec<AssASTTypes> {
+    let numeric_labels = collect_numeric_labels(&parsed_types);
+    let mut numeric_labels_seen: HashMap<String, usize> = HashMap::new();
+    let mut current_global_label = String::new();
+    let mut resolved: Vec<AssASTTypes> = vec![];
+    for (index, parsed_type) in parsed_types.into_iter().enumerate() {
+        let resolve = |label: Label, current_global_label: &str| {
+            resolve_label_reference(label, current_global_label, &numeric_labels, index)
+        };
+        let resolved_type = match parsed_type {
+            AssASTTypes::LabelDefenition(label) => {
+                if is_numeric_label(&label.0) {
+                    let count = numeric_labels_seen.entry(label.0.clone()).or_insert(0);
+                    *count += 1;
+                    AssASTTypes::LabelDefenition(Label(numeric_label_name(&label.0, *count - 1)))
+                } else if label.0.starts_with(GENERATED_LABEL_PREFIX) {
+                    AssASTTypes::LabelDefenition(label)
+                } else if label.0.starts_with('.') {
+                    AssASTTypes::LabelDefenition(Label(format!(
+                        "{}{}",
+                        current_global_label, label.0
+                    )))
+                } else {
+                    current_global_label = label.0.clone();
+                    AssASTTypes::LabelDefenition(label)
+                }
+            }
+            AssASTTypes::Jump { a } => AssASTTypes::Jump {
+                a: resolve(a, &current_global_label),
+            },
+            AssASTTypes::Equal { a, b, c } => AssASTTypes::Equal {
+                a,
+                b,
+                c: resolve(c, &current_global_label),
+            },
+            AssASTTypes::NotEqual { a, b, c } => AssASTTypes::NotEqual {
+                a,
+                b,
+                c: resolve(c, &current_global_label),
+            },
+            AssASTTypes::JumpIfHigherThan { a, b, c } => AssASTTypes::JumpIfHigherThan {
+                a,
+                b,
+                c: resolve(c, &current_global_label),
+            },
+            AssASTTypes::JumpIfLowerThan { a, b, c } => AssASTTypes::JumpIfLowerThan {
+                a,
+                b,
+                c: resolve(c, &current_global_label),
+            },
+            AssASTTypes::JumpIfHigherOrEqual { a, b, c } => AssASTTypes::JumpIfHigherOrEqual {
+                a,
+                b,
+                c: resolve(c, &current_global_label),
+            },
+            AssASTTypes::JumpIfLowerOrEqual { a, b, c } => AssASTTypes::JumpIfLowerOrEqual {
+                a,
+                b,
+                c: resolve(c, &current_global_label),
+            },
+            AssASTTypes::JumpIfZero { a, b } => AssASTTypes::JumpIfZero {
+                a,
+                b: resolve(b, &current_global_label),
+            },
+            AssASTTypes::JumpIfNotZero { a, b } => AssASTTypes::JumpIfNotZero {
+                a,
+                b: resolve(b, &current_global_label),
+            },
+            other => other,
+        };
+        resolved.push(resolved_type);
+    }
+    resolved
+}
+
+/// every numeric label with the instruction index of each of its defenitions in order
+fn collect_numeric_labels(parsed_types: &[AssASTTypes]) -> HashMap<String, Vec<usize>> {
+    let mut numeric_labels: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, parsed_type) in parsed_types.iter().enumerate() {
+        if let AssASTTypes::LabelDefenition(label) = parsed_type {
+            if is_numeric_label(&label.0) {
+                numeric_labels
+                    .entry(label.0.clone())
+                    .or_default()
+                    .push(index);
+            }
+        }
+    }
+    numeric_labels
+}
+
+fn resolve_label_reference(
+    label: Label,
+    current_global_label: &str,
+    numeric_labels: &HashMap<String, Vec<usize>>,
+    index: usize,
+) -> Label {
+    if label.0.starts_with('.') {
+        return Label(format!("{}{}", current_global_label, label.0));
+    }
+    let (number, direction) = label.0.split_at(label.0.len().saturating_sub(1));
+    if !is_numeric_label(number) {
+        return label;
+    }
+    let Some(defenitions) = numeric_labels.get(number) else {
+        return label;
+    };
+    let occurence = match direction {
+        "b" => defenitions
+            .iter()
+            .rposition(|defenition| *defenition < index),
+        "f" => defenitions
+            .iter()
+            .position(|defenition| *defenition > index),
+        _ => None,
+    };
+    match occurence {
+        Some(occurence) => Label(numeric_label_name(number, occurence)),
+        None => label,
+    }
+}
+
+fn is_numeric_label(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|char| char.is_ascii_digit())
+}
+
+fn numeric_label_name(number: &str, occurence: usize) -> String {
+    format!("{}@{}", number, occurence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn define(name: &str) -> AssASTTypes {
+        AssASTTypes::LabelDefenition(Label(name.to_owned()))
+    }
+
+    fn jump(name: &str) -> AssASTTypes {
+        AssASTTypes::Jump {
+            a: Label(name.to_owned()),
+        }
+    }
+
+    #[test]
+    fn local_labels_belong_to_the_last_global_label() {
+        let resolved = resolve_local_labels(vec![
+            jump(".loop"),
+            define("first"),
+            define(".loop"),
+            jump(".loop"),
+            define("second"),
+            jump(".loop"),
+            define(".loop"),
+        ]);
+        assert_eq!(
+            resolved,
+            vec![
+                jump(".loop"),
+                define("first"),
+                define("first.loop"),
+                jump("first.loop"),
+                define("second"),
+                jump("second.loop"),
+                define("second.loop"),
+            ]
+        );
+    }
+
+    #[test]
+    fn numeric_labels_jump_to_the_closest_defenition() {
+        let resolved = resolve_local_labels(vec![
+            define("1"),
+            jump("1b"),
+            jump("1f"),
+            define("1"),
+            jump("1b"),
+            jump("1f"),
+        ]);
+        assert_eq!(
+            resolved,
+            vec![
+                define("1@0"),
+                jump("1@0"),
+                jump("1@1"),
+                define("1@1"),
+                jump("1@1"),
+                // there is no 1 after it
+                jump("1f"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unresolved_references_are_left_alone() {
+        let resolved = resolve_local_labels(vec![jump("1b"), jump("2f"), jump("3x"), define("1")]);
+        assert_eq!(
+            resolved,
+            vec![jump("1b"), jump("2f"), jump("3x"), define("1@0")]
+        );
+    }
+}
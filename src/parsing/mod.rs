@@ -1,3 +1,7 @@
 pub mod ast;
+pub mod blocks;
+pub mod format;
+pub mod include;
+pub mod labels;
 pub mod operators;
 pub mod parse;
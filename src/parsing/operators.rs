@@ -1,10 +1,15 @@
 use std::str::Chars;
 
+use crate::target::{parse_address, Target};
+
 pub struct ParsedDEF {
     pub name: String,
     pub value: u64,
+    /// the address after an @, the variable gets it instead of a value
+    pub address: Option<u64>,
 }
-pub fn parse_def_operator(chars: &mut Chars) -> ParsedDEF {
+/// the number has to fit in a word of the target
+pub fn parse_def_operator(chars: &mut Chars, target: &Target) -> ParsedDEF {
     let mut determined_if_defenition_is_constant_or_ram = false;
     let mut got_variable_label = false;
     let mut variable_label_storage: String = String::new();
@@ -32,16 +37,32 @@ pub fn parse_def_operator(chars: &mut Chars) -> ParsedDEF {
             panic!()
         }
 
-        if !is_newline(char) && char.is_ascii_digit() {
+        let is_address = number_string_storage.starts_with('@');
+        if !is_newline(char)
+            && (char.is_ascii_digit()
+                || (char == '-' || char == '@') && number_string_storage.is_empty()
+                || is_address && char.is_ascii_alphanumeric())
+        {
             number_string_storage.push(char);
         } else if is_newline(char) || char == ' ' {
+            let error = |err: String| panic!("DEF ?{}: {}", variable_label_storage, err);
+            if let Some(address) = number_string_storage.strip_prefix('@') {
+                return ParsedDEF {
+                    value: 0,
+                    address: Some(parse_address(address).unwrap_or_else(error)),
+                    name: variable_label_storage,
+                };
+            }
             return ParsedDEF {
+                value: target
+                    .parse_number(&number_string_storage)
+                    .unwrap_or_else(error),
+                address: None,
                 name: variable_label_storage,
-                value: number_string_storage.parse().unwrap(),
             };
         }
     }
-    panic!("{}, {}",variable_label_storage, number_string_storage)
+    panic!("{}, {}", variable_label_storage, number_string_storage)
 }
 
 pub struct Parsed1ArgOperator {
@@ -168,7 +189,11 @@ pub fn parse_2_arg_with_label_operator(chars: &mut Chars) -> Parsed2ArgWithLabel
     let mut variable_label_storage = [String::new(), String::new(), String::new()];
     let mut index = 0;
     let mut found_first_variable = false;
+    let mut previous = ' ';
     for char in chars.by_ref() {
+        // a : in the middle of a word is part of a module path like math::two
+        let starts_word = previous == ' ';
+        previous = char;
         match char {
             '?' => {
                 if found_first_variable {
@@ -177,7 +202,7 @@ pub fn parse_2_arg_with_label_operator(chars: &mut Chars) -> Parsed2ArgWithLabel
 
                 found_first_variable = true;
             }
-            ':' => {
+            ':' if starts_word => {
                 if index != 1 {
                     panic!()
                 }
@@ -206,6 +231,54 @@ pub fn parse_2_arg_with_label_operator(chars: &mut Chars) -> Parsed2ArgWithLabel
         c_label_name: variable_label_storage[2].to_owned(),
     }
 }
+pub struct Parsed1ArgWithLabelOperator {
+    pub a_name: String,
+    pub b_label_name: String,
+}
+pub fn parse_1_arg_with_label_operator(chars: &mut Chars) -> Parsed1ArgWithLabelOperator {
+    let mut variable_label_storage = [String::new(), String::new()];
+    let mut index = 0;
+    let mut found_first_variable = false;
+    let mut previous = ' ';
+    for char in chars.by_ref() {
+        // a : in the middle of a word is part of a module path like math::two
+        let starts_word = previous == ' ';
+        previous = char;
+        match char {
+            '?' => {
+                if found_first_variable {
+                    panic!()
+                }
+                found_first_variable = true;
+            }
+            ':' if starts_word => {
+                if index != 0 || !found_first_variable {
+                    panic!()
+                }
+                index += 1;
+            }
+            ' ' => {
+                if index == 1 && !variable_label_storage[1].is_empty() {
+                    break;
+                }
+            }
+            '\n' => {
+                if index == 1 {
+                    break;
+                }
+            }
+            _ => {
+                if found_first_variable {
+                    variable_label_storage[index].push(char);
+                }
+            }
+        }
+    }
+    Parsed1ArgWithLabelOperator {
+        a_name: variable_label_storage[0].to_owned(),
+        b_label_name: variable_label_storage[1].to_owned(),
+    }
+}
 pub struct Parsed2ArgOperator {
     pub a_name: String,
     pub b_name: String,
@@ -246,6 +319,56 @@ pub fn parse_2_arg_operator(chars: &mut Chars) -> Parsed2ArgOperator {
     }
 }
 
+pub struct ParsedNameOperator {
+    pub name: String,
+}
+/// parses a bare name like the ones used by MODULE, EXPORT and IMPORT
+pub fn parse_name_operator(chars: &mut Chars) -> ParsedNameOperator {
+    let mut variable_label_storage: String = String::new();
+    for char in chars.by_ref() {
+        if !is_newline(char) && char != ' ' {
+            variable_label_storage.push(char);
+            continue;
+        } else if variable_label_storage.is_empty() && char == ' ' {
+            continue;
+        } else if variable_label_storage.is_empty() {
+            panic!()
+        }
+        break;
+    }
+    if variable_label_storage.is_empty() {
+        panic!();
+    }
+    ParsedNameOperator {
+        name: variable_label_storage,
+    }
+}
+
+pub struct ParsedConditionOperator {
+    pub a_name: String,
+    pub comparison: String,
+    pub b_name: String,
+}
+/// parses conditions like `?a == ?b` used by IF and WHILE
+pub fn parse_condition_operator(chars: &mut Chars) -> ParsedConditionOperator {
+    let mut line_storage: String = String::new();
+    for char in chars.by_ref() {
+        if is_newline(char) {
+            break;
+        }
+        line_storage.push(char);
+    }
+    let words: Vec<&str> = line_storage.split_whitespace().collect();
+    match words.as_slice() {
+        [a, comparison, b] if a.starts_with('?') && b.starts_with('?') => ParsedConditionOperator {
+            a_name: a[1..].to_owned(),
+            comparison: comparison.to_string(),
+            b_name: b[1..].to_owned(),
+        },
+        _ => panic!("invalid condition {}", line_storage),
+    }
+}
+
 pub fn is_newline(character: char) -> bool {
     character == '\n'
 }
@@ -1,18 +1,76 @@
-use std::str::Chars;
+use std::{fmt::Display, str::Chars};
+
+use crate::target::{parse_address, Target};
 
 use super::{
     ast::{Address, AssASTTypes, Label},
+    blocks::{Blocks, GENERATED_LABEL_PREFIX},
+    labels::resolve_local_labels,
     operators::{
-        parse_1_arg_operator, parse_2_arg_operator, parse_2_arg_with_label_operator,
-        parse_3_argument_operator, parse_def_operator, parse_jmp_argument_operator,
+        parse_1_arg_operator, parse_1_arg_with_label_operator, parse_2_arg_operator,
+        parse_2_arg_with_label_operator, parse_3_argument_operator, parse_condition_operator,
+        parse_def_operator, parse_jmp_argument_operator, parse_name_operator,
     },
 };
 
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    /// the source line ( starting at 1 ) the error is at
+    pub line: usize,
+    pub type_of_error: ParseErrorType,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let err_message = match self.type_of_error {
+            ParseErrorType::ElseWithoutIf => "ELSE without IF",
+            ParseErrorType::SecondElse => "IF can only have one ELSE",
+            ParseErrorType::EndifWithoutIf => "ENDIF without IF",
+            ParseErrorType::EndwhileWithoutWhile => "ENDWHILE without WHILE",
+            ParseErrorType::IfWithoutEndif => "IF without ENDIF",
+            ParseErrorType::WhileWithoutEndwhile => "WHILE without ENDWHILE",
+        };
+        write!(f, "parse error at line {}: {}", self.line, err_message)
+    }
+}
+
+/// IF and WHILE blocks that are not put inside each other right
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorType {
+    ElseWithoutIf,
+    SecondElse,
+    EndifWithoutIf,
+    EndwhileWithoutWhile,
+    IfWithoutEndif,
+    WhileWithoutEndwhile,
+}
+
+/// for tests that do not need the source lines
+#[cfg(test)]
 pub fn parse_string(str: String) -> Vec<AssASTTypes> {
+    parse_string_with_lines(str).0
+}
+/// for tests that compile for the default target
+#[cfg(test)]
+pub fn parse_string_with_lines(str: String) -> (Vec<AssASTTypes>, Vec<usize>) {
+    parse_string_for_target(str, &Target::default()).unwrap()
+}
+/// also returns the source line ( starting at 1 ) of every ast node,
+/// the numbers have to fit in a word of the target
+pub fn parse_string_for_target(
+    str: String,
+    target: &Target,
+) -> Result<(Vec<AssASTTypes>, Vec<usize>), ParseError> {
     let mut chars = str.chars();
     let mut string_instruction_storage = String::new();
     let mut asl: Vec<AssASTTypes> = vec![];
+    let mut lines: Vec<usize> = vec![];
+    let mut instruction_line = 1;
+    let mut counted_until = 0;
+    let mut blocks = Blocks::default();
     while let Some(char) = chars.next() {
+        // everything the last instruction added came from its line
+        lines.resize(asl.len(), instruction_line);
         match char {
             '\n' => {
                 string_instruction_storage = String::new();
@@ -22,15 +80,30 @@ pub fn parse_string(str: String) -> Vec<AssASTTypes> {
                 ignore_comment(&mut chars);
                 continue;
             }
+            // indentation
+            ' ' | '\t' if string_instruction_storage.is_empty() => {
+                continue;
+            }
             _ => {}
         }
+        if string_instruction_storage.is_empty() {
+            let offset = str.len() - chars.as_str().len() - char.len_utf8();
+            instruction_line += str[counted_until..offset].matches('\n').count();
+            counted_until = offset;
+        }
         string_instruction_storage.push(char);
         match string_instruction_storage.as_str() {
             "DEF" => {
-                let def_operator_parsed = parse_def_operator(&mut chars);
-                let def_operator_ast = AssASTTypes::Define {
-                    name: Label(def_operator_parsed.name),
-                    a: def_operator_parsed.value,
+                let def_operator_parsed = parse_def_operator(&mut chars, target);
+                let def_operator_ast = match def_operator_parsed.address {
+                    Some(address) => AssASTTypes::DefineAt {
+                        name: Label(def_operator_parsed.name),
+                        address,
+                    },
+                    None => AssASTTypes::Define {
+                        name: Label(def_operator_parsed.name),
+                        a: def_operator_parsed.value,
+                    },
                 };
                 asl.push(def_operator_ast);
                 string_instruction_storage = String::new();
@@ -80,6 +153,84 @@ pub fn parse_string(str: String) -> Vec<AssASTTypes> {
                 string_instruction_storage = String::new();
                 continue;
             }
+            "AND" => {
+                let and_operator = parse_3_argument_operator(&mut chars);
+                let and_operator_ast = AssASTTypes::And {
+                    a: Address(and_operator.a_name),
+                    b: Address(and_operator.b_name),
+                    c: Address(and_operator.c_name),
+                };
+                asl.push(and_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            // OR is the start of ORG too
+            "OR" if chars.clone().next() == Some(' ') => {
+                let or_operator = parse_3_argument_operator(&mut chars);
+                let or_operator_ast = AssASTTypes::Or {
+                    a: Address(or_operator.a_name),
+                    b: Address(or_operator.b_name),
+                    c: Address(or_operator.c_name),
+                };
+                asl.push(or_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "XOR" => {
+                let xor_operator = parse_3_argument_operator(&mut chars);
+                let xor_operator_ast = AssASTTypes::Xor {
+                    a: Address(xor_operator.a_name),
+                    b: Address(xor_operator.b_name),
+                    c: Address(xor_operator.c_name),
+                };
+                asl.push(xor_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "SHL" => {
+                let shl_operator = parse_3_argument_operator(&mut chars);
+                let shl_operator_ast = AssASTTypes::ShiftLeft {
+                    a: Address(shl_operator.a_name),
+                    b: Address(shl_operator.b_name),
+                    c: Address(shl_operator.c_name),
+                };
+                asl.push(shl_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "SHR" => {
+                let shr_operator = parse_3_argument_operator(&mut chars);
+                let shr_operator_ast = AssASTTypes::ShiftRight {
+                    a: Address(shr_operator.a_name),
+                    b: Address(shr_operator.b_name),
+                    c: Address(shr_operator.c_name),
+                };
+                asl.push(shr_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            // MOD is the start of MODULE too
+            "MOD" if chars.clone().next() == Some(' ') => {
+                let mod_operator = parse_3_argument_operator(&mut chars);
+                let mod_operator_ast = AssASTTypes::Modulo {
+                    a: Address(mod_operator.a_name),
+                    b: Address(mod_operator.b_name),
+                    c: Address(mod_operator.c_name),
+                };
+                asl.push(mod_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "NOT" => {
+                let not_operator = parse_2_arg_operator(&mut chars);
+                let not_operator_ast = AssASTTypes::Not {
+                    a: Address(not_operator.a_name),
+                    b: Address(not_operator.b_name),
+                };
+                asl.push(not_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
             "YEET" => {
                 let yeet_operator = parse_3_argument_operator(&mut chars);
                 let yeet_operator_ast = AssASTTypes::Yeet {
@@ -90,6 +241,17 @@ pub fn parse_string(str: String) -> Vec<AssASTTypes> {
                 asl.push(yeet_operator_ast);
                 string_instruction_storage = String::new();
             }
+            "YOINK" => {
+                let yoink_operator = parse_3_argument_operator(&mut chars);
+                let yoink_operator_ast = AssASTTypes::Yoink {
+                    a: Address(yoink_operator.a_name),
+                    b: Address(yoink_operator.b_name),
+                    c: Address(yoink_operator.c_name),
+                };
+                asl.push(yoink_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
             "JHT" => {
                 let jht_operator = parse_2_arg_with_label_operator(&mut chars);
                 let jht_operator_ast = AssASTTypes::JumpIfHigherThan {
@@ -103,6 +265,12 @@ pub fn parse_string(str: String) -> Vec<AssASTTypes> {
             }
             ":" => {
                 let label = parse_label(&mut chars);
+                if label.starts_with(GENERATED_LABEL_PREFIX) {
+                    panic!(
+                        "label :{} can not start with {}",
+                        label, GENERATED_LABEL_PREFIX
+                    )
+                }
                 let label_ast = AssASTTypes::LabelDefenition(Label(label));
                 asl.push(label_ast);
                 string_instruction_storage = String::new();
@@ -126,6 +294,59 @@ pub fn parse_string(str: String) -> Vec<AssASTTypes> {
                 string_instruction_storage = String::new();
                 continue;
             }
+            "JLT" => {
+                let jlt_operator = parse_2_arg_with_label_operator(&mut chars);
+                let jlt_operator_ast = AssASTTypes::JumpIfLowerThan {
+                    a: Address(jlt_operator.a_name),
+                    b: Address(jlt_operator.b_name),
+                    c: Label(jlt_operator.c_label_name),
+                };
+                asl.push(jlt_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "JGE" => {
+                let jge_operator = parse_2_arg_with_label_operator(&mut chars);
+                let jge_operator_ast = AssASTTypes::JumpIfHigherOrEqual {
+                    a: Address(jge_operator.a_name),
+                    b: Address(jge_operator.b_name),
+                    c: Label(jge_operator.c_label_name),
+                };
+                asl.push(jge_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "JLE" => {
+                let jle_operator = parse_2_arg_with_label_operator(&mut chars);
+                let jle_operator_ast = AssASTTypes::JumpIfLowerOrEqual {
+                    a: Address(jle_operator.a_name),
+                    b: Address(jle_operator.b_name),
+                    c: Label(jle_operator.c_label_name),
+                };
+                asl.push(jle_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "JZ" => {
+                let jz_operator = parse_1_arg_with_label_operator(&mut chars);
+                let jz_operator_ast = AssASTTypes::JumpIfZero {
+                    a: Address(jz_operator.a_name),
+                    b: Label(jz_operator.b_label_name),
+                };
+                asl.push(jz_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "JNZ" => {
+                let jnz_operator = parse_1_arg_with_label_operator(&mut chars);
+                let jnz_operator_ast = AssASTTypes::JumpIfNotZero {
+                    a: Address(jnz_operator.a_name),
+                    b: Label(jnz_operator.b_label_name),
+                };
+                asl.push(jnz_operator_ast);
+                string_instruction_storage = String::new();
+                continue;
+            }
             "JE" => {
                 let je_operator = parse_2_arg_with_label_operator(&mut chars);
                 let je_operator_ast = AssASTTypes::Equal {
@@ -158,11 +379,82 @@ pub fn parse_string(str: String) -> Vec<AssASTTypes> {
                 string_instruction_storage = String::new();
                 continue;
             }
-
+            "MODULE" => {
+                let module_operator = parse_name_operator(&mut chars);
+                asl.push(AssASTTypes::ModuleStart(Label(module_operator.name)));
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "ENDMODULE" => {
+                asl.push(AssASTTypes::ModuleEnd);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "EXPORT" => {
+                let export_operator = parse_name_operator(&mut chars);
+                asl.push(AssASTTypes::Export(Label(export_operator.name)));
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "IMPORT" => {
+                let import_operator = parse_name_operator(&mut chars);
+                asl.push(AssASTTypes::Import(Label(import_operator.name)));
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "ORG" => {
+                let org_operator = parse_name_operator(&mut chars);
+                let address =
+                    parse_address(&org_operator.name).unwrap_or_else(|err| panic!("ORG: {}", err));
+                asl.push(AssASTTypes::Origin(address));
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "IF" => {
+                let condition = parse_condition_operator(&mut chars);
+                asl.append(&mut blocks.start_if(condition, instruction_line));
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "ELSE" => {
+                let mut block_asl = blocks.start_else().map_err(|type_of_error| ParseError {
+                    line: instruction_line,
+                    type_of_error,
+                })?;
+                asl.append(&mut block_asl);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "ENDIF" => {
+                let mut block_asl = blocks.end_if().map_err(|type_of_error| ParseError {
+                    line: instruction_line,
+                    type_of_error,
+                })?;
+                asl.append(&mut block_asl);
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "WHILE" => {
+                let condition = parse_condition_operator(&mut chars);
+                asl.append(&mut blocks.start_while(condition, instruction_line));
+                string_instruction_storage = String::new();
+                continue;
+            }
+            "ENDWHILE" => {
+                let mut block_asl = blocks.end_while().map_err(|type_of_error| ParseError {
+                    line: instruction_line,
+                    type_of_error,
+                })?;
+                asl.append(&mut block_asl);
+                string_instruction_storage = String::new();
+                continue;
+            }
             _ => {}
         };
     }
-    asl
+    lines.resize(asl.len(), instruction_line);
+    blocks.finish()?;
+    Ok((resolve_local_labels(asl), lines))
 }
 fn ignore_comment(chars: &mut Chars) {
     for char in chars.by_ref() {
@@ -194,3 +486,58 @@ fn parse_label(chars: &mut Chars) -> String {
     }
     accumulator
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// builds the AST of a branch from its operands
+    type Branch = fn(Address, Address, Label) -> AssASTTypes;
+
+    #[test]
+    fn branches_take_module_paths_in_every_operand() {
+        let branches: [(&str, Branch); 6] = [
+            ("JE", |a, b, c| AssASTTypes::Equal { a, b, c }),
+            ("JNE", |a, b, c| AssASTTypes::NotEqual { a, b, c }),
+            ("JHT", |a, b, c| AssASTTypes::JumpIfHigherThan { a, b, c }),
+            ("JLT", |a, b, c| AssASTTypes::JumpIfLowerThan { a, b, c }),
+            ("JGE", |a, b, c| AssASTTypes::JumpIfHigherOrEqual {
+                a,
+                b,
+                c,
+            }),
+            ("JLE", |a, b, c| AssASTTypes::JumpIfLowerOrEqual { a, b, c }),
+        ];
+        let name = |name: &str| name.to_owned();
+        for (branch, ast) in branches {
+            for (a, b, c) in [
+                ("math::two", "z", "end"),
+                ("z", "math::two", "end"),
+                ("math::one", "math::two", "math::end"),
+            ] {
+                let program = format!("{} ?{} ?{} :{}\n", branch, a, b, c);
+                assert_eq!(
+                    parse_string(program),
+                    vec![ast(Address(name(a)), Address(name(b)), Label(name(c)))]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn zero_branches_take_module_paths_in_every_operand() {
+        assert_eq!(
+            parse_string("JZ ?math::two :math::end\nJNZ ?z :end\n".to_owned()),
+            vec![
+                AssASTTypes::JumpIfZero {
+                    a: Address("math::two".to_owned()),
+                    b: Label("math::end".to_owned()),
+                },
+                AssASTTypes::JumpIfNotZero {
+                    a: Address("z".to_owned()),
+                    b: Label("end".to_owned()),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+
+use crate::code_gen::{ir::Arithmetic, RawInstructions};
+
+/// what happens when the result of arithmetic does not fit in a word
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum Overflow {
+    /// only the lowest bits of the result are kept
+    #[default]
+    Wrap,
+    /// the result becomes the largest or smallest number a word holds
+    Saturate,
+    /// the program stops with an error
+    Trap,
+}
+
+/// the machine the program is compiled for
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    /// bits in a word, 1 to 64
+    pub word_size: u32,
+    /// words are two's complement numbers, this also changes what JHT compares
+    pub signed: bool,
+    pub overflow: Overflow,
+    pub instruction_set: InstructionSet,
+    pub memory: MemoryMap,
+}
+
+impl Default for Target {
+    fn default() -> Target {
+        Target {
+            word_size: 64,
+            signed: false,
+            overflow: Overflow::Wrap,
+            instruction_set: InstructionSet::default(),
+            memory: MemoryMap::default(),
+        }
+    }
+}
+
+/// where the code and the variables go in memory
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryMap {
+    /// address of the first instruction, ORG sets it too. 0 if neither does
+    pub code_origin: Option<u64>,
+    /// address of the first variable, right after the code if not set
+    pub ram_base: Option<u64>,
+    /// cells there are for variables, no limit if not set
+    pub ram_size: Option<u64>,
+}
+
+impl MemoryMap {
+    /// address of the first variable when the code ends at the address
+    pub fn ram_start(&self, code_end: u64) -> u64 {
+        self.ram_base.unwrap_or(code_end)
+    }
+}
+
+/// reads an address, in decimal or in hexadecimal starting with 0x
+pub fn parse_address(address: &str) -> Result<u64, String> {
+    let parsed = match address.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => address.parse(),
+    };
+    parsed.map_err(|_| format!("{} is not an address", address))
+}
+
+/// reads a linker script, every line sets where the code or the variables go
+/// ```text
+/// # comments start with #
+/// code-origin 0x100
+/// ram-base 0x8000
+/// ram-size 0x1000
+/// ```
+pub fn parse_memory_map(script: &str) -> Result<MemoryMap, String> {
+    let mut memory = MemoryMap::default();
+    for (line_number, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("line {}: {}", line_number + 1, message);
+        let (setting, address) = match line.split_whitespace().collect::<Vec<&str>>()[..] {
+            [setting, address] => (setting, parse_address(address).map_err(error)?),
+            _ => return Err(error(format!("unknown setting {}", line))),
+        };
+        match setting {
+            "code-origin" => memory.code_origin = Some(address),
+            "ram-base" => memory.ram_base = Some(address),
+            "ram-size" => memory.ram_size = Some(address),
+            _ => return Err(error(format!("unknown setting {}", line))),
+        }
+    }
+    Ok(memory)
+}
+
+/// how instructions are put into memory.
+/// every instruction takes the same amount of cells, the opcode and every operand have their own cell in it.
+/// with cells for only the a and b operand instructions that need c can not be used
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstructionSet {
+    /// opcode of every instruction by its name, like ADD or Equal
+    pub opcodes: HashMap<String, u64>,
+    /// cells every instruction takes
+    pub cell_width: u64,
+    /// cell of the opcode in an instruction
+    pub opcode_slot: u64,
+    /// cells of the a, b and c operand in an instruction, c can be left out
+    pub operand_slots: Vec<u64>,
+}
+
+/// the opcodes instructions have unless the instruction set gives them others
+const DEFAULT_OPCODES: [(&str, u64); 13] = [
+    ("ADD", 101),
+    ("COPY", 102),
+    ("WRITE", 103),
+    ("JMP", 104),
+    ("SUB", 105),
+    ("MUL", 106),
+    ("Equal", 107),
+    ("NotEqual", 108),
+    ("EXIT", 109),
+    ("JHT", 110),
+    ("YEET", 111),
+    ("DIV", 112),
+    ("YOINK", 113),
+];
+
+impl Default for InstructionSet {
+    fn default() -> InstructionSet {
+        let opcodes = DEFAULT_OPCODES
+            .iter()
+            .map(|(name, opcode)| (name.to_string(), *opcode))
+            .collect();
+        InstructionSet {
+            opcodes,
+            cell_width: 4,
+            opcode_slot: 0,
+            operand_slots: vec![1, 2, 3],
+        }
+    }
+}
+
+impl InstructionSet {
+    /// address of the first cell of an instruction
+    pub fn address(&self, instruction: usize) -> u64 {
+        instruction as u64 * self.cell_width
+    }
+    /// what a jump to the instruction jumps to, the program counter points one instruction past
+    /// the instruction that runs
+    pub fn jump_address(&self, instruction: usize) -> u64 {
+        self.address(instruction + 1)
+    }
+    /// the instruction that runs when the program counter has this value
+    pub fn instruction_at(&self, program_counter: u64) -> Option<usize> {
+        (program_counter / self.cell_width)
+            .checked_sub(1)
+            .map(|instruction| instruction as usize)
+    }
+    /// address of operand 1, 2 or 3 of an instruction
+    pub fn operand_address(&self, instruction: usize, operand: usize) -> u64 {
+        self.address(instruction) + self.operand_slots[operand - 1]
+    }
+    /// the instruction and operand 1, 2 or 3 a cell belongs to, none for opcodes and unused cells
+    pub fn operand_at(&self, address: u64) -> Option<(usize, usize)> {
+        let slot = address % self.cell_width;
+        let operand = self.operand_slots.iter().position(|used| *used == slot)?;
+        Some(((address / self.cell_width) as usize, operand + 1))
+    }
+    /// the cells of an instruction
+    pub fn encode(&self, instruction: &RawInstructions) -> Vec<u64> {
+        let mut cells = vec![0; self.cell_width as usize];
+        cells[self.opcode_slot as usize] = instruction.opcode(self);
+        for (slot, operand) in self.operand_slots.iter().zip(instruction.operands()) {
+            cells[*slot as usize] = operand;
+        }
+        cells
+    }
+    /// turns the cells of an instruction back into the instruction, none for unknown opcodes
+    pub fn decode(&self, cells: &[u64]) -> Option<RawInstructions> {
+        let mut operands = [0; 3];
+        for (operand, slot) in operands.iter_mut().zip(&self.operand_slots) {
+            *operand = cells[*slot as usize];
+        }
+        RawInstructions::from_operands(cells[self.opcode_slot as usize], operands, self)
+    }
+}
+
+/// reads an instruction set, every line sets the cells of instructions or an opcode.
+/// instructions that are not in the file keep their default opcode
+/// ```text
+/// # comments start with #
+/// cells 4
+/// opcode 0
+/// operands 1 2 3
+/// # or cells 3 and operands 1 2 for instructions without c
+/// ADD 201
+/// ```
+pub fn parse_instruction_set(description: &str) -> Result<InstructionSet, String> {
+    let mut instruction_set = InstructionSet::default();
+    for (line_number, line) in description.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("line {}: {}", line_number + 1, message);
+        let mut words = line.split_whitespace();
+        let key = words.next().unwrap();
+        let numbers = words
+            .map(|word| {
+                word.parse::<u64>()
+                    .map_err(|_| error(format!("{} is not a number", word)))
+            })
+            .collect::<Result<Vec<u64>, String>>()?;
+        match (key, numbers.as_slice()) {
+            ("cells", [cells]) => instruction_set.cell_width = *cells,
+            ("opcode", [slot]) => instruction_set.opcode_slot = *slot,
+            ("operands", slots @ ([_, _] | [_, _, _])) => {
+                instruction_set.operand_slots = slots.to_vec()
+            }
+            (name, [opcode]) if instruction_set.opcodes.contains_key(name) => {
+                instruction_set.opcodes.insert(name.to_owned(), *opcode);
+            }
+            _ => return Err(error(format!("unknown setting {}", line))),
+        }
+    }
+    let mut slots = vec![instruction_set.opcode_slot];
+    slots.extend(&instruction_set.operand_slots);
+    if slots.iter().any(|slot| *slot >= instruction_set.cell_width) {
+        return Err(format!(
+            "the opcode and the {} operands have to be in the {} cells of an instruction",
+            instruction_set.operand_slots.len(),
+            instruction_set.cell_width
+        ));
+    }
+    let cells = slots.len();
+    slots.sort();
+    slots.dedup();
+    if slots.len() != cells {
+        return Err("the opcode and the operands need a cell each".to_owned());
+    }
+    let mut opcodes: Vec<u64> = instruction_set.opcodes.values().copied().collect();
+    opcodes.sort();
+    opcodes.dedup();
+    if opcodes.len() != instruction_set.opcodes.len() {
+        return Err("two instructions have the same opcode".to_owned());
+    }
+    Ok(instruction_set)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithmeticError {
+    DivisionByZero,
+    /// only with Overflow::Trap
+    Overflow,
+}
+
+impl Target {
+    /// a word with every bit set
+    pub fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.word_size)
+    }
+    /// the smallest number a word holds
+    pub fn min(&self) -> i128 {
+        match self.signed {
+            true => -(1 << (self.word_size - 1)),
+            false => 0,
+        }
+    }
+    /// the largest number a word holds
+    pub fn max(&self) -> i128 {
+        match self.signed {
+            true => (1 << (self.word_size - 1)) - 1,
+            false => self.mask() as i128,
+        }
+    }
+    /// the number a word holds
+    pub fn value(&self, word: u64) -> i128 {
+        let word = word & self.mask();
+        if self.signed && word >> (self.word_size - 1) == 1 {
+            word as i128 - (1 << self.word_size)
+        } else {
+            word as i128
+        }
+    }
+    /// the word for a number, only the lowest bits are kept
+    pub fn word(&self, value: i128) -> u64 {
+        value as u64 & self.mask()
+    }
+    /// the word the machine stores for a result
+    pub fn fit(&self, value: i128) -> Result<u64, ArithmeticError> {
+        if (self.min()..=self.max()).contains(&value) {
+            return Ok(self.word(value));
+        }
+        match self.overflow {
+            Overflow::Wrap => Ok(self.word(value)),
+            Overflow::Saturate => Ok(self.word(value.clamp(self.min(), self.max()))),
+            Overflow::Trap => Err(ArithmeticError::Overflow),
+        }
+    }
+    /// like the machine does it
+    pub fn calculate(
+        &self,
+        arithmetic: Arithmetic,
+        a: u64,
+        b: u64,
+    ) -> Result<u64, ArithmeticError> {
+        let (a, b) = (self.value(a), self.value(b));
+        let exact = match arithmetic {
+            Arithmetic::Add => Some(a + b),
+            Arithmetic::Sub => Some(a - b),
+            Arithmetic::Mul => a.checked_mul(b),
+            Arithmetic::Div => {
+                if b == 0 {
+                    return Err(ArithmeticError::DivisionByZero);
+                }
+                Some(a / b)
+            }
+        };
+        let Some(exact) = exact else {
+            // a MUL of two 64 bit words can be too big even for an i128
+            return match self.overflow {
+                Overflow::Wrap => Ok(self.word(a.wrapping_mul(b))),
+                _ => self.fit(if (a < 0) == (b < 0) {
+                    i128::MAX
+                } else {
+                    i128::MIN
+                }),
+            };
+        };
+        self.fit(exact)
+    }
+    /// what JHT compares
+    pub fn higher_than(&self, a: u64, b: u64) -> bool {
+        self.value(a) > self.value(b)
+    }
+    /// the word for a number in the source, numbers that do not fit are an error
+    pub fn parse_number(&self, number: &str) -> Result<u64, String> {
+        let value: i128 = number
+            .parse()
+            .map_err(|_| format!("{} is not a number", number))?;
+        if !(self.min()..=self.max()).contains(&value) {
+            return Err(format!(
+                "{} does not fit in a {} bit {} word, it has to be between {} and {}",
+                number,
+                self.word_size,
+                if self.signed { "signed" } else { "unsigned" },
+                self.min(),
+                self.max()
+            ));
+        }
+        Ok(self.word(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(word_size: u32, signed: bool, overflow: Overflow) -> Target {
+        Target {
+            word_size,
+            signed,
+            overflow,
+            ..Target::default()
+        }
+    }
+
+    #[test]
+    fn wraps_saturates_or_traps() {
+        let wrap = target(16, false, Overflow::Wrap);
+        assert_eq!(wrap.calculate(Arithmetic::Add, 65535, 2), Ok(1));
+        assert_eq!(wrap.calculate(Arithmetic::Sub, 0, 1), Ok(65535));
+        let saturate = target(16, false, Overflow::Saturate);
+        assert_eq!(saturate.calculate(Arithmetic::Mul, 300, 300), Ok(65535));
+        assert_eq!(saturate.calculate(Arithmetic::Sub, 0, 1), Ok(0));
+        let trap = target(16, false, Overflow::Trap);
+        assert_eq!(
+            trap.calculate(Arithmetic::Add, 65535, 1),
+            Err(ArithmeticError::Overflow)
+        );
+        let wide = Target::default();
+        assert_eq!(
+            wide.calculate(Arithmetic::Mul, u64::MAX, u64::MAX),
+            Ok(u64::MAX.wrapping_mul(u64::MAX))
+        );
+    }
+
+    #[test]
+    fn signed_words_are_twos_complement() {
+        let signed = target(8, true, Overflow::Wrap);
+        assert_eq!(signed.parse_number("-1"), Ok(255));
+        assert_eq!(signed.value(255), -1);
+        assert!(signed.higher_than(1, 255));
+        assert_eq!(signed.calculate(Arithmetic::Div, 250, 2), Ok(253));
+        assert_eq!(signed.calculate(Arithmetic::Add, 127, 1), Ok(128));
+    }
+
+    #[test]
+    fn numbers_have_to_fit_in_a_word() {
+        let unsigned = target(16, false, Overflow::Wrap);
+        assert_eq!(unsigned.parse_number("65535"), Ok(65535));
+        assert!(unsigned.parse_number("65536").is_err());
+        assert!(unsigned.parse_number("-1").is_err());
+        assert_eq!(
+            Target::default().parse_number("18446744073709551615"),
+            Ok(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn instructions_can_be_renumbered_and_moved() {
+        let instruction_set =
+            parse_instruction_set("# opcode last\ncells 5\nopcode 4\noperands 0 1 2\nADD 7\n")
+                .unwrap();
+        let add = RawInstructions::ADD { a: 3, b: 4 };
+        assert_eq!(instruction_set.encode(&add), vec![3, 4, 0, 0, 7]);
+        assert_eq!(instruction_set.decode(&[3, 4, 0, 0, 7]), Some(add));
+        assert_eq!(instruction_set.jump_address(1), 10);
+        assert_eq!(instruction_set.operand_address(1, 2), 6);
+        assert_eq!(instruction_set.operand_at(6), Some((1, 2)));
+        assert_eq!(instruction_set.operand_at(9), None);
+        assert_eq!(
+            instruction_set.encode(&RawInstructions::EXIT { a: 1 }),
+            vec![1, 0, 0, 0, 109]
+        );
+    }
+
+    #[test]
+    fn instructions_can_leave_out_the_c_operand() {
+        let instruction_set = parse_instruction_set("cells 3\noperands 1 2\n").unwrap();
+        let copy = RawInstructions::COPY { a: 5, b: 6 };
+        assert_eq!(instruction_set.encode(&copy), vec![102, 5, 6]);
+        assert_eq!(instruction_set.decode(&[102, 5, 6]), Some(copy));
+        assert_eq!(instruction_set.jump_address(1), 6);
+        assert_eq!(instruction_set.operand_at(5), Some((1, 2)));
+    }
+
+    #[test]
+    fn instruction_sets_need_room_for_every_operand() {
+        assert!(parse_instruction_set("cells 3\n").is_err());
+        assert!(parse_instruction_set("cells 3\noperands 1 2 3\n").is_err());
+        assert!(parse_instruction_set("operands 1\n").is_err());
+        assert!(parse_instruction_set("operands 1 1 2\n").is_err());
+        assert!(parse_instruction_set("ADD 102\n").is_err());
+        assert!(parse_instruction_set("PUSH 1\n").is_err());
+    }
+
+    #[test]
+    fn memory_maps_take_hexadecimal_addresses() {
+        let memory = parse_memory_map("# the board\ncode-origin 0x100\nram-base 32768\n").unwrap();
+        assert_eq!(
+            memory,
+            MemoryMap {
+                code_origin: Some(0x100),
+                ram_base: Some(0x8000),
+                ram_size: None,
+            }
+        );
+        assert!(parse_memory_map("ram-size 0x\n").is_err());
+        assert!(parse_memory_map("rom 0\n").is_err());
+    }
+}
@@ -0,0 +1,355 @@
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    code_gen::{generate_code_with_debug_info, CompileOptions},
+    emulator::{
+        coverage::lcov_record,
+        profile::{run_profiled, Profile},
+        Machine, Yeet,
+    },
+    parsing::{include::expand_includes, parse::parse_string_for_target},
+};
+
+/// what a program has to do to pass, read from comments like `# expect-exit: 3`
+#[derive(Debug, Default, PartialEq)]
+pub struct Expectations {
+    pub exit_code: Option<u64>,
+    /// every YEET in order, `# expect-yeet: dev=1 instr=2 data=3`
+    pub yeets: Vec<Yeet>,
+    /// `# step-limit: 1000` overrides the step limit for one program
+    pub step_limit: Option<u64>,
+}
+
+impl Expectations {
+    /// programs without expect annotations are not tests
+    pub fn is_test(&self) -> bool {
+        self.exit_code.is_some() || !self.yeets.is_empty()
+    }
+}
+
+pub fn parse_expectations(program: &str) -> Result<Expectations, String> {
+    let mut expectations = Expectations::default();
+    for line in program.lines() {
+        let Some(comment) = line.trim().strip_prefix('#') else {
+            continue;
+        };
+        let Some((key, value)) = comment.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "expect-exit" => {
+                expectations.exit_code = Some(parse_number(value)?);
+            }
+            "step-limit" => {
+                expectations.step_limit = Some(parse_number(value)?);
+            }
+            "expect-yeet" => {
+                let mut yeet = Yeet {
+                    device: 0,
+                    instruction: 0,
+                    data: 0,
+                };
+                for field in value.split_whitespace() {
+                    match field.split_once('=') {
+                        Some(("dev", number)) => yeet.device = parse_number(number)?,
+                        Some(("instr", number)) => yeet.instruction = parse_number(number)?,
+                        Some(("data", number)) => yeet.data = parse_number(number)?,
+                        _ => return Err(format!("invalid expect-yeet field {}", field)),
+                    }
+                }
+                expectations.yeets.push(yeet);
+            }
+            _ => {}
+        }
+    }
+    Ok(expectations)
+}
+
+fn parse_number(value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} is not a number", value))
+}
+
+/// compiles and runs one program, returns why it failed
+/// and the LCOV record of the lines that ran if the program compiled.
+/// INCLUDEs are looked for next to the file the name says the program is from
+pub fn run_test(
+    name: &str,
+    program: &str,
+    expectations: &Expectations,
+    step_limit: u64,
+    options: &CompileOptions,
+    include_paths: &[PathBuf],
+) -> (Result<(), String>, Option<String>) {
+    let directory = Path::new(name).parent().unwrap_or(Path::new("."));
+    let expanded = match expand_includes(program, directory, include_paths) {
+        Ok(expanded) => expanded,
+        Err(err) => return (Err(err), None),
+    };
+    let target = &options.target;
+    // the parser panics on most invalid programs
+    let Ok(parsed) = panic::catch_unwind(AssertUnwindSafe(|| {
+        parse_string_for_target(expanded.text.clone(), target)
+    })) else {
+        return (Err("failed to parse".to_owned()), None);
+    };
+    let (parsed, ast_lines) = match parsed {
+        Ok(parsed) => parsed,
+        Err(mut err) => {
+            err.line = expanded.source_line(err.line);
+            return (Err(err.to_string()), None);
+        }
+    };
+    let ast_lines: Vec<usize> = ast_lines
+        .into_iter()
+        .map(|line| expanded.source_line(line))
+        .collect();
+    let Ok((code, debug_info)) = generate_code_with_debug_info(parsed, &ast_lines, options) else {
+        return (Err("failed to compile".to_owned()), None);
+    };
+    let mut machine = Machine::for_target(&code, debug_info.code_origin, target.clone());
+    let mut profile = Profile::default();
+    let result = run_profiled(
+        &mut machine,
+        expectations.step_limit.unwrap_or(step_limit),
+        &mut profile,
+    );
+    let coverage = lcov_record(name, &profile, &debug_info, &ast_lines);
+    (
+        result
+            .map_err(|err| err.to_string())
+            .and_then(|exit_code| check_expectations(expectations, &machine, exit_code)),
+        Some(coverage),
+    )
+}
+
+fn check_expectations(
+    expectations: &Expectations,
+    machine: &Machine,
+    exit_code: u64,
+) -> Result<(), String> {
+    let mut failures = vec![];
+    if let Some(expected) = expectations.exit_code {
+        if expected != exit_code {
+            failures.push(format!(
+                "expected exit code {}, got {}",
+                expected, exit_code
+            ));
+        }
+    }
+    if !expectations.yeets.is_empty() && expectations.yeets != machine.yeets {
+        failures.push(format!(
+            "expected yeets:\n{}\ngot yeets:\n{}",
+            format_yeets(&expectations.yeets),
+            format_yeets(&machine.yeets)
+        ));
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures.join("\n"))
+    }
+}
+
+fn format_yeets(yeets: &[Yeet]) -> String {
+    if yeets.is_empty() {
+        return "    none".to_owned();
+    }
+    yeets
+        .iter()
+        .map(|yeet| {
+            format!(
+                "    dev={} instr={} data={}",
+                yeet.device, yeet.instruction, yeet.data
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// every .ass file in the paths, directories are searched recursively
+pub fn discover_tests(paths: &[String]) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for path in paths {
+        collect_ass_files(Path::new(path), &mut files);
+    }
+    files.sort();
+    files
+}
+
+fn collect_ass_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            collect_ass_files(&entry.path(), files);
+        }
+    } else if path.extension().is_some_and(|extension| extension == "ass") {
+        files.push(path.to_owned());
+    }
+}
+
+/// runs every test and prints a summary like cargo test, returns if all of them passed.
+/// the coverage of all tests is written to the coverage file as LCOV
+pub fn run_tests(
+    paths: &[String],
+    step_limit: u64,
+    coverage: Option<&str>,
+    options: &CompileOptions,
+    include_paths: &[PathBuf],
+) -> bool {
+    let mut tests = vec![];
+    for file in discover_tests(paths) {
+        let program = fs::read_to_string(&file).unwrap().replace('\r', "\n");
+        match parse_expectations(&program) {
+            Ok(expectations) if !expectations.is_test() => {}
+            expectations => tests.push((file, program, expectations)),
+        }
+    }
+    println!();
+    println!("running {} tests", tests.len());
+    let mut failures: Vec<(String, String)> = vec![];
+    let mut lcov = String::new();
+    for (file, program, expectations) in &tests {
+        let name = file.display().to_string();
+        let result = match expectations {
+            Ok(expectations) => {
+                let (result, record) = run_test(
+                    &name,
+                    program,
+                    expectations,
+                    step_limit,
+                    options,
+                    include_paths,
+                );
+                lcov.push_str(&record.unwrap_or_default());
+                result
+            }
+            Err(err) => Err(err.clone()),
+        };
+        match result {
+            Ok(()) => println!("test {} ... ok", name),
+            Err(reason) => {
+                println!("test {} ... FAILED", name);
+                failures.push((name, reason));
+            }
+        }
+    }
+    println!();
+    if !failures.is_empty() {
+        println!("failures:");
+        println!();
+        for (name, reason) in &failures {
+            println!("---- {} ----", name);
+            println!("{}", reason);
+            println!();
+        }
+        println!("failures:");
+        for (name, _) in &failures {
+            println!("    {}", name);
+        }
+        println!();
+    }
+    let result = if failures.is_empty() { "ok" } else { "FAILED" };
+    println!(
+        "test result: {}. {} passed; {} failed",
+        result,
+        tests.len() - failures.len(),
+        failures.len()
+    );
+    println!();
+    if let Some(coverage) = coverage {
+        fs::write(coverage, lcov).unwrap();
+    }
+    failures.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_annotations() {
+        let program =
+            "# expect-exit: 3\n#expect-yeet: dev=1 instr=2 data=3\n# step-limit: 10\nDEF ?a 3\n";
+        assert_eq!(
+            parse_expectations(program),
+            Ok(Expectations {
+                exit_code: Some(3),
+                yeets: vec![Yeet {
+                    device: 1,
+                    instruction: 2,
+                    data: 3,
+                }],
+                step_limit: Some(10),
+            })
+        );
+    }
+
+    #[test]
+    fn program_without_annotations_is_not_a_test() {
+        let expectations = parse_expectations("# just a comment\nDEF ?a 3\n").unwrap();
+        assert!(!expectations.is_test());
+    }
+
+    #[test]
+    fn checks_exit_code_and_yeets() {
+        let program = "# expect-exit: 4\n# expect-yeet: dev=1 instr=2 data=4\nDEF ?dev 1\nDEF ?instr 2\nDEF ?four 4\nDEF ?address 0\nYEET ?dev ?instr ?address\nEXIT ?four\n";
+        let expectations = parse_expectations(program).unwrap();
+        // ?address points at ?dev which is the first variable after the 13 instructions
+        let program = program.replace("DEF ?address 0", "DEF ?address 52");
+        assert_eq!(
+            run_test(
+                "",
+                &program,
+                &expectations,
+                100,
+                &CompileOptions::default(),
+                &[]
+            )
+            .0,
+            Err(
+                "expected yeets:\n    dev=1 instr=2 data=4\ngot yeets:\n    dev=1 instr=2 data=1"
+                    .to_owned()
+            )
+        );
+        let program = program.replace("DEF ?address 52", "DEF ?address 54");
+        assert_eq!(
+            run_test(
+                "",
+                &program,
+                &expectations,
+                100,
+                &CompileOptions::default(),
+                &[]
+            )
+            .0,
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn step_limit_stops_endless_programs() {
+        let program = "# expect-exit: 0\n:loop\nJMP :loop\n";
+        let expectations = parse_expectations(program).unwrap();
+        assert_eq!(
+            run_test(
+                "",
+                program,
+                &expectations,
+                100,
+                &CompileOptions::default(),
+                &[]
+            )
+            .0,
+            Err("program did not exit after 100 steps".to_owned())
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! compiles every program in tests/snapshots and compares the output
+//! with the checked in .txt file next to it
+//!
+//! run `BLESS=1 cargo test --test snapshots` to update the .txt files after changing code generation
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+fn snapshot_programs() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+    let mut programs: Vec<PathBuf> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ass"))
+        .collect();
+    programs.sort();
+    programs
+}
+
+fn compile(program: &Path) -> String {
+    let output = env::temp_dir().join(format!(
+        "ass-snapshot-{}-{}.txt",
+        std::process::id(),
+        program.file_stem().unwrap().to_string_lossy()
+    ));
+    let status = Command::new(env!("CARGO_BIN_EXE_ass-embly-to-asslang-compiler"))
+        .arg("build")
+        .arg(program)
+        .arg("-o")
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success(), "{} failed to compile", program.display());
+    let compiled = fs::read_to_string(&output).unwrap();
+    fs::remove_file(&output).unwrap();
+    compiled
+}
+
+#[test]
+fn generated_code_matches_snapshots() {
+    let bless = env::var_os("BLESS").is_some();
+    let mut mismatches = vec![];
+    for program in snapshot_programs() {
+        let compiled = compile(&program);
+        let snapshot = program.with_extension("txt");
+        if bless {
+            fs::write(&snapshot, &compiled).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&snapshot).unwrap_or_default();
+        if compiled != expected {
+            mismatches.push(snapshot.display().to_string());
+        }
+    }
+    assert!(
+        mismatches.is_empty(),
+        "generated code changed for:\n{}\nrun `BLESS=1 cargo test --test snapshots` and review the diff",
+        mismatches.join("\n")
+    );
+}